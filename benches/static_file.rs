@@ -0,0 +1,72 @@
+//! Times serving a large file from a static folder, and counts how many
+//! writes it takes
+//!
+//! Run with `cargo bench --bench static_file`. Like the render benchmark,
+//! this times itself instead of using criterion.
+//!
+//! Files used to be copied 8 KiB at a time, so the 32 MiB file here took
+//! 4097 writes (one of them for the status line and headers). Now it
+//! takes 129.
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use rustedflask::flask::App;
+
+/// How big the file that's served is
+const FILE_SIZE: usize = 32 * 1024 * 1024;
+
+/// How many times the file is served
+const ITERATIONS: u32 = 20;
+
+/// Throws away what's written to it, counting the write calls,
+/// which would each be a syscall on a socket
+#[derive(Default)]
+struct CountingSink {
+    writes: usize,
+    bytes: usize,
+}
+
+impl Write for CountingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writes += 1;
+        self.bytes += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let folder = env::temp_dir().join("rustedflask-static-bench");
+    fs::create_dir_all(&folder).unwrap();
+    fs::write(folder.join("large.bin"), vec![b'x'; FILE_SIZE]).unwrap();
+
+    let mut app = App::new("bench".to_string());
+    app.static_folder("/static", &folder);
+
+    let mut sink = CountingSink::default();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let response = app.test().path("/static/large.bin").send();
+        response.write_to(&mut sink).unwrap();
+    }
+    let average: Duration = start.elapsed() / ITERATIONS;
+
+    let writes = sink.writes / ITERATIONS as usize;
+    println!(
+        "{:<32} {:>10.1?} per response",
+        "serving a 32 MiB file", average
+    );
+    println!("{:<32} {:>10} per response", "writes", writes);
+    // nowhere near a write per byte, or even per 8 KiB
+    assert!(sink.bytes >= FILE_SIZE * ITERATIONS as usize);
+    assert!(writes < FILE_SIZE / 8192);
+
+    fs::remove_dir_all(&folder).unwrap();
+}
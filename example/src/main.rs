@@ -13,7 +13,7 @@ fn main_route(_request: HTTPRequest) -> HTTPResponse {
 fn template_route(_request: HTTPRequest) -> HTTPResponse {
     let template_name = "template.html.jinja2";
     let mut variables = HashMap::new();
-    variables.insert("template_name", template_name.to_string());
+    variables.insert("template_name", template_name.into());
     match render_template(template_name, variables, None) {
         Ok(content) => HTTPResponse::from(&*content),
         // Build an error page
@@ -53,7 +53,7 @@ fn template_route_cached(ctx_unlocked: Arc<Mutex<JinjaState>>, _request: HTTPReq
     let mut ctx = ctx_unlocked.lock().unwrap();
     let template_name = "template.html.jinja2";
     let mut variables = HashMap::new();
-    variables.insert("template_name", template_name.to_string());
+    variables.insert("template_name", template_name.into());
     match ctx.render_template(template_name, variables, None) {
         Ok(content) => HTTPResponse::from(&*content),
         // Build an error page
@@ -0,0 +1,55 @@
+//! Building the body of a response a piece at a time
+
+use std::io::{self, Write};
+
+use super::{headers, remove_header, HTTPResponse};
+
+/// Writes into the body of a response, keeping its `Content-Length` up to
+/// date (see `HTTPResponse::body_writer`)
+///
+/// # Examples
+/// ```
+/// # use rustedflask::core::http::HTTPResponse;
+/// use std::io::Write;
+///
+/// let mut response = HTTPResponse::new();
+/// write!(response.body_writer(), "{} + {} = {}", 1, 2, 1 + 2).unwrap();
+/// assert_eq!(response.content, b"1 + 2 = 3");
+/// ```
+pub struct BodyWriter<'a> {
+    response: &'a mut HTTPResponse,
+}
+
+impl Write for BodyWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.response.append_to_body(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HTTPResponse {
+    /// Adds `bytes` to the end of the body, updating the `Content-Length`
+    ///
+    /// A streamed body (see `HTTPResponse::with_body_reader`) is replaced,
+    /// like with `HTTPResponse::with_content`, and so is its
+    /// `Transfer-Encoding`, as the body isn't chunked anymore.
+    pub fn append_to_body(&mut self, bytes: &[u8]) {
+        self.stream = None;
+        self.content.extend_from_slice(bytes);
+        remove_header(&mut self.headers, headers::TRANSFER_ENCODING);
+        remove_header(&mut self.headers, headers::CONTENT_LENGTH);
+        self.headers.insert(
+            headers::CONTENT_LENGTH.to_string(),
+            self.content.len().to_string(),
+        );
+    }
+
+    /// Something `write!` can add to the body with (see `BodyWriter`)
+    pub fn body_writer(&mut self) -> BodyWriter<'_> {
+        BodyWriter { response: self }
+    }
+}
@@ -0,0 +1,68 @@
+//! Reading from a connection through a buffer, while still writing to it
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// A stream that's read from through a `BufReader`, and written to directly
+///
+/// Messages are read from a `BufRead`, so a connection that more than one
+/// message is read from has to be wrapped once and kept like this, or the
+/// start of the next message can be left behind in a dropped buffer.
+///
+/// # Examples
+/// ```no_run
+/// # use rustedflask::core::http::{BufferedStream, HTTPRequest};
+/// # use std::net::TcpStream;
+/// let mut stream = BufferedStream::new(TcpStream::connect("example.com:80").unwrap());
+/// let first = HTTPRequest::get("/").send_over(&mut stream);
+/// let second = HTTPRequest::get("/about").send_over(&mut stream);
+/// ```
+pub struct BufferedStream<S: Read> {
+    reader: BufReader<S>,
+}
+
+impl<S: Read> BufferedStream<S> {
+    /// Starts reading `stream` through a buffer
+    pub fn new(stream: S) -> BufferedStream<S> {
+        BufferedStream {
+            reader: BufReader::new(stream),
+        }
+    }
+
+    /// The stream being read from, which anything already in the
+    /// buffer has been read from
+    pub fn get_mut(&mut self) -> &mut S {
+        self.reader.get_mut()
+    }
+
+    /// Stops buffering, giving back the stream and losing anything
+    /// that was in the buffer
+    pub fn into_inner(self) -> S {
+        self.reader.into_inner()
+    }
+}
+
+impl<S: Read> Read for BufferedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl<S: Read> BufRead for BufferedStream<S> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount)
+    }
+}
+
+impl<S: Read + Write> Write for BufferedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.reader.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.reader.get_mut().flush()
+    }
+}
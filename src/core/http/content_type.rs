@@ -0,0 +1,100 @@
+//! Reading the parameters of headers like `Content-Type`
+
+use std::collections::HashMap;
+
+use super::{get_header, headers, HTTPRequest};
+
+/// Splits `header` at the semicolons that aren't inside of quotes
+fn split_parameters(header: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (position, character) in header.char_indices() {
+        match character {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                pieces.push(&header[start..position]);
+                start = position + 1;
+            }
+            _ => {}
+        }
+    }
+    pieces.push(&header[start..]);
+    pieces
+}
+
+/// Takes the quotes (and the backslashes escaping characters) off of a
+/// quoted parameter value, leaving other values as they are
+fn unquote(value: &str) -> String {
+    let inside = match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(inside) => inside,
+        None => return value.to_string(),
+    };
+    let mut unquoted = String::with_capacity(inside.len());
+    let mut characters = inside.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '\\' => unquoted.extend(characters.next()),
+            character => unquoted.push(character),
+        }
+    }
+    unquoted
+}
+
+/// Splits a `Content-Type` (or a header like it, such as
+/// `Content-Disposition`) into its media type and its parameters
+///
+/// The media type and the names of the parameters are lowercased, as
+/// they're case-insensitive, while the values are kept as they were sent,
+/// without their quotes. Parameters without a `=` are skipped, and when
+/// one is sent more than once, the first is kept.
+///
+/// # Examples
+/// ```
+/// # use rustedflask::core::http::parse_content_type;
+/// let (media_type, parameters) =
+///     parse_content_type("multipart/form-data; Boundary=\"a;b\"; charset=UTF-8");
+/// assert_eq!(media_type, "multipart/form-data");
+/// assert_eq!(parameters["boundary"], "a;b");
+/// assert_eq!(parameters["charset"], "UTF-8");
+/// ```
+pub fn parse_content_type(content_type: &str) -> (String, HashMap<String, String>) {
+    let mut pieces = split_parameters(content_type).into_iter();
+    let media_type = pieces.next().unwrap_or("").trim().to_ascii_lowercase();
+    let mut parameters = HashMap::new();
+    for piece in pieces {
+        if let Some((name, value)) = piece.split_once('=') {
+            parameters
+                .entry(name.trim().to_ascii_lowercase())
+                .or_insert_with(|| unquote(value.trim()));
+        }
+    }
+    (media_type, parameters)
+}
+
+impl HTTPRequest {
+    /// The `charset` parameter of this request's `Content-Type`, in lowercase,
+    /// if it has one
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPRequest;
+    /// let mut request = HTTPRequest::post("/", "Hello, World!");
+    /// request.headers.insert(
+    ///     "Content-Type".to_string(),
+    ///     "text/plain; charset=\"UTF-8\"".to_string(),
+    /// );
+    /// assert_eq!(request.charset().as_deref(), Some("utf-8"));
+    /// ```
+    pub fn charset(&self) -> Option<String> {
+        let content_type = get_header(&self.headers, headers::CONTENT_TYPE)?;
+        let (_, mut parameters) = parse_content_type(content_type);
+        Some(parameters.remove("charset")?.to_ascii_lowercase())
+    }
+}
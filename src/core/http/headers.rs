@@ -0,0 +1,64 @@
+//! The names of common headers, so they don't have to be spelled out
+//! (and possibly misspelled) as strings
+//!
+//! Header names aren't case-sensitive, but these are spelled the way
+//! they usually are.
+//!
+//! # Examples
+//! ```
+//! # use rustedflask::core::http::{headers, HTTPResponse};
+//! let response = HTTPResponse::from("{}")
+//!     .with_header(headers::CONTENT_TYPE.to_string(), "application/json".to_string());
+//! assert_eq!(response.header(headers::CONTENT_TYPE), Some("application/json"));
+//! ```
+
+/// `Accept`, the media types the client can handle
+pub const ACCEPT: &str = "Accept";
+/// `Accept-Encoding`, the compressions the client can handle
+pub const ACCEPT_ENCODING: &str = "Accept-Encoding";
+/// `Accept-Language`, the languages the client would like
+pub const ACCEPT_LANGUAGE: &str = "Accept-Language";
+/// `Access-Control-Allow-Credentials`, whether a cross-origin request can send cookies
+pub const ACCESS_CONTROL_ALLOW_CREDENTIALS: &str = "Access-Control-Allow-Credentials";
+/// `Access-Control-Allow-Headers`, the headers a cross-origin request can send
+pub const ACCESS_CONTROL_ALLOW_HEADERS: &str = "Access-Control-Allow-Headers";
+/// `Access-Control-Allow-Methods`, the methods a cross-origin request can use
+pub const ACCESS_CONTROL_ALLOW_METHODS: &str = "Access-Control-Allow-Methods";
+/// `Access-Control-Allow-Origin`, the origin that can read the response
+pub const ACCESS_CONTROL_ALLOW_ORIGIN: &str = "Access-Control-Allow-Origin";
+/// `Access-Control-Request-Headers`, the headers a preflighted request will send
+pub const ACCESS_CONTROL_REQUEST_HEADERS: &str = "Access-Control-Request-Headers";
+/// `Access-Control-Request-Method`, the method a preflighted request will use
+pub const ACCESS_CONTROL_REQUEST_METHOD: &str = "Access-Control-Request-Method";
+/// `Cache-Control`, how the message can be cached
+pub const CACHE_CONTROL: &str = "Cache-Control";
+/// `Connection`, whether the connection is kept open after the message
+pub const CONNECTION: &str = "Connection";
+/// `Content-Disposition`, the name of a form field or file
+pub const CONTENT_DISPOSITION: &str = "Content-Disposition";
+/// `Content-Encoding`, how the body was compressed
+pub const CONTENT_ENCODING: &str = "Content-Encoding";
+/// `Content-Length`, how many bytes the body is
+pub const CONTENT_LENGTH: &str = "Content-Length";
+/// `Content-Type`, the media type of the body
+pub const CONTENT_TYPE: &str = "Content-Type";
+/// `Cookie`, the cookies the client has
+pub const COOKIE: &str = "Cookie";
+/// `Host`, the host (and port) the request was sent to
+pub const HOST: &str = "Host";
+/// `Location`, where a redirect goes to
+pub const LOCATION: &str = "Location";
+/// `Origin`, the origin of the page that made the request
+pub const ORIGIN: &str = "Origin";
+/// `Set-Cookie`, a cookie for the client to keep
+pub const SET_COOKIE: &str = "Set-Cookie";
+/// `Trailer`, the headers that are sent after a chunked body
+pub const TRAILER: &str = "Trailer";
+/// `Transfer-Encoding`, like `chunked`, for how the body is sent
+pub const TRANSFER_ENCODING: &str = "Transfer-Encoding";
+/// `User-Agent`, what the client is
+pub const USER_AGENT: &str = "User-Agent";
+/// `Vary`, the request headers the response depends on
+pub const VARY: &str = "Vary";
+/// `X-Forwarded-Proto`, the scheme a client used to reach a proxy
+pub const X_FORWARDED_PROTO: &str = "X-Forwarded-Proto";
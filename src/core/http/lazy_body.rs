@@ -0,0 +1,200 @@
+//! Request bodies that are only read from the connection once they're used
+
+use std::{
+    fmt::Debug,
+    io::{self, Read},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+};
+
+use super::{Error, HTTPRequest};
+
+/// The most a `LazyBody` asks the connection for at once
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+
+/// What a `LazyBody` asks the connection it came from for
+enum BodyRequest {
+    /// Up to this many more bytes of the body
+    Read(usize),
+    /// Nothing else, the request has been handled
+    Done,
+}
+
+/// The end of a `LazyBody` that the handler reads from
+struct Reader {
+    requests: Sender<BodyRequest>,
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    remaining: usize,
+}
+
+/// A request body that's still on the connection, and is read from it
+/// as it's used (see `App::lazy_bodies`)
+///
+/// Reading it after the request has been handled fails.
+///
+/// Cloning a `LazyBody` doesn't copy the body, both clones read from the
+/// same connection, so each byte is only read by one of them.
+#[derive(Clone)]
+pub struct LazyBody {
+    reader: Arc<Mutex<Reader>>,
+    length: usize,
+}
+
+/// Lazy bodies can't be compared without reading them, so they're
+/// only equal to their clones
+impl PartialEq for LazyBody {
+    fn eq(&self, other: &LazyBody) -> bool {
+        Arc::ptr_eq(&self.reader, &other.reader)
+    }
+}
+
+impl Eq for LazyBody {}
+
+impl Debug for LazyBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LazyBody")
+            .field("length", &self.length)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LazyBody {
+    /// How long the whole body is, from its `Content-Length`
+    pub fn length(&self) -> usize {
+        self.length
+    }
+}
+
+impl Read for LazyBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut reader = match self.reader.lock() {
+            Ok(reader) => reader,
+            Err(_) => return Err(io::Error::other("request body was poisoned")),
+        };
+        let wanted = buf.len().min(reader.remaining).min(MAX_CHUNK_SIZE);
+        if wanted == 0 {
+            return Ok(0);
+        }
+        let gone = || io::Error::other("the request has already been handled");
+        if reader.requests.send(BodyRequest::Read(wanted)).is_err() {
+            return Err(gone());
+        }
+        let chunk = match reader.chunks.recv() {
+            Ok(chunk) => chunk?,
+            Err(_) => return Err(gone()),
+        };
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        reader.remaining -= chunk.len();
+        Ok(chunk.len())
+    }
+}
+
+/// The connection's end of a `LazyBody`, which reads the body from the
+/// connection whenever the handler asks for it
+pub(crate) struct BodySource {
+    requests: Receiver<BodyRequest>,
+    chunks: Sender<io::Result<Vec<u8>>>,
+    handled: Sender<BodyRequest>,
+    remaining: usize,
+}
+
+/// Tells a `BodySource` the request has been handled when it's dropped,
+/// even if the handler panicked
+pub(crate) struct Handled(Sender<BodyRequest>);
+
+impl Drop for Handled {
+    fn drop(&mut self) {
+        let _ = self.0.send(BodyRequest::Done);
+    }
+}
+
+impl BodySource {
+    /// Makes a body of `length` bytes, and the source it reads from
+    pub(crate) fn new(length: usize) -> (LazyBody, BodySource) {
+        let (request_sender, requests) = channel();
+        let (chunks, chunk_receiver) = channel();
+        let body = LazyBody {
+            reader: Arc::new(Mutex::new(Reader {
+                requests: request_sender.clone(),
+                chunks: chunk_receiver,
+                remaining: length,
+            })),
+            length,
+        };
+        let source = BodySource {
+            requests,
+            chunks,
+            handled: request_sender,
+            remaining: length,
+        };
+        (body, source)
+    }
+
+    /// Something to drop once the request has been handled, which stops `serve`
+    pub(crate) fn handled(&self) -> Handled {
+        Handled(self.handled.clone())
+    }
+
+    /// Reads the body from `stream` as the handler asks for it, until
+    /// the request has been handled (see `BodySource::handled`)
+    pub(crate) fn serve(&mut self, stream: &mut impl Read) {
+        while let Ok(BodyRequest::Read(wanted)) = self.requests.recv() {
+            let mut chunk = vec![0; wanted.min(self.remaining)];
+            let chunk = match stream.read(&mut chunk) {
+                Ok(0) if !chunk.is_empty() => Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => {
+                    chunk.truncate(read);
+                    self.remaining -= read;
+                    Ok(chunk)
+                }
+                Err(why) => Err(why),
+            };
+            if self.chunks.send(chunk).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Reads and throws away whatever the handler didn't read, so the
+    /// next request on `stream` starts in the right place
+    pub(crate) fn drain(self, stream: &mut impl Read) -> io::Result<()> {
+        let expected = self.remaining as u64;
+        let drained = io::copy(&mut stream.take(expected), &mut io::sink())?;
+        if drained < expected {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+impl HTTPRequest {
+    /// Reads the rest of a lazy body (see `App::lazy_bodies`) into `content`,
+    /// returning the whole body
+    ///
+    /// Requests whose body was already read just return it.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::{core::http::{HTTPRequest, HTTPResponse}, flask::App};
+    /// let mut app = App::new("name".to_string());
+    /// app.lazy_bodies(true);
+    /// app.route_with_allowed_methods(
+    ///     "/echo",
+    ///     |mut request: HTTPRequest| match request.read_body() {
+    ///         Ok(body) => HTTPResponse::new().with_content(body.to_vec()),
+    ///         Err(_) => "Couldn't read the body".into(),
+    ///     },
+    ///     vec!["POST".to_string()],
+    /// );
+    /// ```
+    pub fn read_body(&mut self) -> Result<&[u8], Error> {
+        if let Some(mut body) = self.lazy_body.take() {
+            if body.read_to_end(&mut self.content).is_err() {
+                return Err(Error::StreamReadError);
+            }
+        }
+        Ok(&self.content)
+    }
+}
@@ -0,0 +1,42 @@
+//! Limits on what clients can send, so they can't use up the server's memory
+//! or tie up its connections
+
+use std::time::Duration;
+
+/// Limits on the requests read with `HTTPRequest::read_http_request_with_limits`
+///
+/// # Examples
+/// ```
+/// # use rustedflask::core::http::RequestLimits;
+/// let limits = RequestLimits {
+///     max_headers: 50,
+///     ..RequestLimits::default()
+/// };
+/// ```
+#[derive(Clone, Debug)]
+pub struct RequestLimits {
+    /// How many header lines a request can have
+    pub max_headers: usize,
+    /// How long the client has to send the request line and all of the
+    /// headers, from when its first byte arrives
+    ///
+    /// This is on top of the timeout on each read (see `App::run`), which
+    /// doesn't stop a client sending a byte at a time just often enough.
+    /// It's only checked after each read, so it can be overrun by up to
+    /// the read timeout.
+    pub header_timeout: Option<Duration>,
+    /// How many bytes a request body can have, after any chunked encoding
+    /// is taken off
+    pub max_body_size: usize,
+}
+
+impl Default for RequestLimits {
+    /// Allows up to 100 headers, sent within 10 seconds, and bodies of up to 10 MiB
+    fn default() -> Self {
+        RequestLimits {
+            max_headers: 100,
+            header_timeout: Some(Duration::from_secs(10)),
+            max_body_size: 10 * 1024 * 1024,
+        }
+    }
+}
@@ -11,3 +11,20 @@ pub fn httpver_to_vecu8(httpver: (i32, i32)) -> Vec<u8> {
     out.extend(Vec::<u8>::from(httpver.1.to_string()));
     out
 }
+
+/// Copies `bytes` into `out`, leaving out any CRs and LFs
+///
+/// Used for the parts of a message that have to stay on one line, so a
+/// reason or header value can't end its line early and add lines of its own.
+pub fn extend_single_line(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend(bytes.iter().filter(|&&byte| byte != b'\r' && byte != b'\n'));
+}
+
+/// `text` without any CRs, LFs or NULs, the characters `validate_header` rejects
+pub fn without_line_breaks(text: String) -> String {
+    if text.contains(['\r', '\n', '\0']) {
+        text.replace(['\r', '\n', '\0'], "")
+    } else {
+        text
+    }
+}
@@ -2,20 +2,85 @@
 
 use std::{
     collections::HashMap,
-    io::{Read, Write},
+    io::{BufRead, Read, Write},
     net::TcpStream,
+    sync::mpsc::{channel, Sender},
+    thread,
+    time::Instant,
 };
 
-use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
 mod misc;
-use misc::httpver_to_vecu8;
+use misc::{extend_single_line, httpver_to_vecu8, without_line_breaks};
 
-/// An HTTP status code
-#[derive(FromPrimitive, Clone, Debug)]
-#[allow(missing_docs)]
-pub enum HttpStatusCodes {
+pub mod headers;
+
+mod content_type;
+pub use content_type::parse_content_type;
+
+mod buffered;
+pub use buffered::BufferedStream;
+
+mod body_writer;
+pub use body_writer::BodyWriter;
+
+mod lazy_body;
+pub(crate) use lazy_body::BodySource;
+pub use lazy_body::LazyBody;
+
+mod limits;
+pub use limits::RequestLimits;
+
+mod multipart;
+pub use multipart::MultipartPart;
+
+mod params;
+
+mod parsed_body;
+pub use parsed_body::ParsedBody;
+
+mod retry;
+pub use retry::RetryPolicy;
+
+mod stream;
+pub use stream::ResponseStream;
+
+/// Defines `HttpStatusCodes` and the mapping between its variants and their numbers
+macro_rules! status_codes {
+    ($($(#[$attr:meta])* $name:ident = $code:literal,)*) => {
+        /// An HTTP status code
+        #[derive(Clone, Debug, PartialEq, Eq)]
+        #[allow(missing_docs)]
+        pub enum HttpStatusCodes {
+            $($(#[$attr])* $name,)*
+            /// A status code that isn't one of the others, like `599`
+            Custom(u16),
+        }
+
+        impl HttpStatusCodes {
+            /// The number of this status code, i.e., `404` for `NotFound`
+            pub fn status_code_number(&self) -> u16 {
+                match self {
+                    $(HttpStatusCodes::$name => $code,)*
+                    HttpStatusCodes::Custom(code) => *code,
+                }
+            }
+
+            /// Gets the status code for `code`, which is `Custom` if `code`
+            /// is a valid status code (`100` to `599`) that isn't a known one
+            pub fn from_code(code: u16) -> Option<HttpStatusCodes> {
+                match code {
+                    $($code => Some(HttpStatusCodes::$name),)*
+                    100..=599 => Some(HttpStatusCodes::Custom(code)),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
     Continue = 100,
     /// A notice that the server is switching protocols
     /// in response to an upgrade request
@@ -103,6 +168,47 @@ pub enum HttpStatusCodes {
     NetworkAuthenticationRequired = 511,
 }
 
+impl HttpStatusCodes {
+    /// Whether this is a `1xx` status code, like `100 Continue`
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.status_code_number())
+    }
+
+    /// Whether this is a `2xx` status code, like `200 OK`
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status_code_number())
+    }
+
+    /// Whether this is a `3xx` status code, like `302 Found`
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.status_code_number())
+    }
+
+    /// Whether this is a `4xx` status code, like `404 Not Found`
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.status_code_number())
+    }
+
+    /// Whether this is a `5xx` status code, like `500 Internal Server Error`
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.status_code_number())
+    }
+}
+
+impl FromPrimitive for HttpStatusCodes {
+    /// Gets the known status code for `n`, never returning `Custom`
+    fn from_i64(n: i64) -> Option<Self> {
+        match u16::try_from(n).ok().and_then(HttpStatusCodes::from_code) {
+            Some(HttpStatusCodes::Custom(_)) | None => None,
+            known => known,
+        }
+    }
+
+    fn from_u64(n: u64) -> Option<Self> {
+        HttpStatusCodes::from_i64(i64::try_from(n).ok()?)
+    }
+}
+
 /// The reason why `Error::InvalidContentLength` was returned
 #[derive(Debug)]
 pub enum InvalidContentLengthReason {
@@ -121,7 +227,7 @@ pub enum Error {
     InvalidVersionError,
     /// The status code was unreadable, or couldn't be read as an int
     UnreadableStatusCode,
-    /// The status code isn't in the HTTP standard
+    /// The status code is outside of the valid range (`100` to `599`)
     UnknownStatusError,
     /// The `Content-Length` header couldn't be read
     InvalidContentLength(InvalidContentLengthReason),
@@ -133,10 +239,30 @@ pub enum Error {
     CouldntSend,
     /// The recieved data was not HTTP (first five bytes were not `HTTP/`)
     NotHTTP,
+    /// The request had more headers than `RequestLimits.max_headers`
+    TooManyHeaders,
+    /// The request line and headers took longer than `RequestLimits.header_timeout` to arrive
+    HeaderTimeout,
+    /// The request body was bigger than `RequestLimits.max_body_size`
+    BodyTooLarge,
+    /// A header name or value had a CR, LF or NUL in it (see `validate_header`)
+    InvalidHeader,
+    /// The message didn't finish arriving before the deadline it was read with
+    /// (see `HTTPRequest::read_http_request_deadline`)
+    DeadlineExceeded,
+    /// The headers didn't say where the body ends in exactly one way, like a
+    /// `Content-Length` that was sent twice (see `body_framing`)
+    ///
+    /// Messages like this are refused instead of guessed at, as a server
+    /// and a proxy in front of it guessing differently lets requests be smuggled.
+    ConflictingFraming,
 }
 
 /// A response to an `HTTPRequest`
-#[derive(Clone, Debug)]
+///
+/// Responses are equal if everything in them is, with headers compared
+/// regardless of their order.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct HTTPResponse {
     /// Always `HTTP`
     pub httptag: Box<[u8]>,
@@ -150,15 +276,23 @@ pub struct HTTPResponse {
     pub headers: HashMap<String, String>,
     /// The content
     pub content: Vec<u8>,
+    /// A body that's streamed instead of `content` (see `HTTPResponse::with_body_reader`)
+    pub stream: Option<ResponseStream>,
+    /// Whether the server should send `httpversion` as-is, instead of answering
+    /// with the version the request used (see `HTTPResponse::with_status_line`)
+    pub keep_version: bool,
 }
 
 /// An HTTP request
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct HTTPRequest {
     /// The method of the request (`GET`, `POST`, etc.,)
     pub method: Vec<u8>,
-    /// The path of the request
+    /// The path of the request, percent-decoded by the server before it's
+    /// routed (see `flask::percent_decode_path`)
     pub path: Vec<u8>,
+    /// The path exactly as the client sent it in the request line
+    pub raw_path: Vec<u8>,
     /// Always `HTTP`
     pub httptag: Box<[u8]>,
     /// What HTTP version is in use?
@@ -167,10 +301,537 @@ pub struct HTTPRequest {
     pub headers: HashMap<String, String>,
     /// The content of the request
     pub content: Vec<u8>,
+    /// The ID of this request, set by the server (see `App::trust_request_id`)
+    pub request_id: Option<String>,
+    /// Whether the client connected over HTTPS, set by the server
+    /// (see `HTTPRequest::is_secure`)
+    pub secure: bool,
+    /// What the body was parsed into, set by the server
+    /// (see `HTTPRequest::parsed_body`)
+    pub parsed_body: Option<ParsedBody>,
+    /// The body, if it's still on the connection instead of in `content`
+    /// (see `App::lazy_bodies` and `HTTPRequest::read_body`)
+    pub lazy_body: Option<LazyBody>,
+    /// The parameters in the path, by name, set by the server for routes
+    /// like `/user/<id>` (see `HTTPRequest::path_param`)
+    pub path_params: HashMap<String, String>,
+}
+
+/// The method, path and HTTP version from the first line of a request
+pub type RequestLine = (Vec<u8>, Vec<u8>, (i32, i32));
+
+/// The methods defined by HTTP (RFC 9110, and `PATCH` from RFC 5789)
+pub const KNOWN_METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Whether `method` is one of the `KNOWN_METHODS`
+///
+/// Methods are case-sensitive, so `get` isn't known.
+pub fn is_known_method(method: &[u8]) -> bool {
+    KNOWN_METHODS.iter().any(|known| known.as_bytes() == method)
+}
+
+/// The HTTP version, status code and reason from the first line of a response
+pub type StatusLine = ((i32, i32), HttpStatusCodes, Vec<u8>);
+
+/// Looks up a header, ignoring the case of its name
+pub(crate) fn get_header<'a>(
+    headers: &'a HashMap<String, String>,
+    name: &str,
+) -> Option<&'a String> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value)
+}
+
+/// Removes a header, whatever the case of its name
+pub(crate) fn remove_header(headers: &mut HashMap<String, String>, name: &str) {
+    headers.retain(|header, _| !header.eq_ignore_ascii_case(name));
+}
+
+/// Checks that a header can be sent as-is, failing with `InvalidHeader` if its
+/// name or value has a CR, LF or NUL in it
+///
+/// Those could end the header's line early and let whoever picked the value
+/// add headers of their own, or start the body. Anything that came from the
+/// client (like a path to redirect back to) should be checked before it's put
+/// in a header, which `HTTPResponse::try_with_header`, `HTTPResponse::set_cookie`
+/// and `HTTPResponse::redirect` do.
+///
+/// # Examples
+/// ```
+/// # use rustedflask::core::http::validate_header;
+/// assert!(validate_header("Location", "/home").is_ok());
+/// assert!(validate_header("Location", "/\r\nSet-Cookie: a=b").is_err());
+/// ```
+pub fn validate_header(name: &str, value: &str) -> Result<(), Error> {
+    let is_forbidden = |byte: &u8| matches!(byte, b'\r' | b'\n' | b'\0');
+    if name.is_empty() || name.bytes().any(|byte| is_forbidden(&byte) || byte == b':') {
+        return Err(Error::InvalidHeader);
+    }
+    if value.bytes().any(|byte| is_forbidden(&byte)) {
+        return Err(Error::InvalidHeader);
+    }
+    Ok(())
+}
+
+/// Whether `location` starts with a scheme (like `https:`), so it doesn't
+/// need anything from the request to be absolute
+fn has_scheme(location: &str) -> bool {
+    match location.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+/// Takes the `.` and `..` segments out of `path`, like a client does
+/// when it follows a relative link
+fn remove_dot_segments(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    let mut parts = path.split('/').skip(1).peekable();
+    while let Some(segment) = parts.next() {
+        let is_last = parts.peek().is_none();
+        match segment {
+            "." | ".." => {
+                if segment == ".." {
+                    segments.pop();
+                }
+                // `/a/..` is the folder `/`, so it keeps its trailing slash
+                if is_last {
+                    segments.push("");
+                }
+            }
+            segment => segments.push(segment),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+/// Reads a single line ending in `\r\n` from `stream`, without the line ending
+///
+/// This is `read_until(b'\n', ...)`, but the line only ends at a `\n` after
+/// a `\r`, and `deadline` is checked after every read. Fails with
+/// `HeaderTimeout` if it passes before the line is over.
+fn read_line(stream: &mut impl BufRead, deadline: Option<Instant>) -> Result<Vec<u8>, Error> {
+    let mut line = Vec::new();
+    loop {
+        let available = match stream.fill_buf() {
+            Ok([]) => return Err(Error::StreamReadError),
+            Ok(available) => available,
+            Err(why) if why.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return Err(Error::StreamReadError),
+        };
+        let used = match available.iter().position(|&byte| byte == b'\n') {
+            Some(newline) => newline + 1,
+            None => available.len(),
+        };
+        line.extend_from_slice(&available[..used]);
+        stream.consume(used);
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            return Err(Error::HeaderTimeout);
+        }
+        if line.ends_with(b"\r\n") {
+            line.truncate(line.len() - 2);
+            return Ok(line);
+        }
+    }
+}
+
+/// Whether a header can't be sent more than once as a list joined with commas,
+/// as its values can have commas in them
+fn is_unjoinable(name: &str) -> bool {
+    name.eq_ignore_ascii_case(headers::SET_COOKIE)
+}
+
+/// Joins the values of every header called `name` (ignoring case) with `, `,
+/// which means the same thing as sending them separately for list headers
+/// like `Accept`
+///
+/// `Set-Cookie` can't be joined, so only one of its values is returned.
+fn get_combined(headers: &HashMap<String, String>, name: &str) -> Option<String> {
+    let mut values = headers
+        .iter()
+        .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str());
+    let first = values.next()?.to_string();
+    if is_unjoinable(name) {
+        return Some(first);
+    }
+    Some(values.fold(first, |combined, value| combined + ", " + value))
+}
+
+/// Reads header lines from `stream`, up to and including the blank line
+/// before the body
+///
+/// A header that's sent more than once has its values joined with `, `,
+/// except for `Set-Cookie`, where the last one is kept.
+///
+/// Fails with `TooManyHeaders` once there are more than `max_headers` of them,
+/// or with `HeaderTimeout` if `deadline` passes before the blank line.
+fn read_headers(
+    stream: &mut impl BufRead,
+    max_headers: usize,
+    deadline: Option<Instant>,
+) -> Result<HashMap<String, String>, Error> {
+    let mut headers = HashMap::<String, String>::new();
+    let mut count = 0;
+    loop {
+        let line = read_line(stream, deadline)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        count += 1;
+        if count > max_headers {
+            return Err(Error::TooManyHeaders);
+        }
+        let colon = match line.iter().position(|&byte| byte == b':') {
+            Some(colon) => colon,
+            None => return Err(Error::UnreadableMessageError),
+        };
+        let header_key: String = line[..colon].iter().map(|&byte| char::from(byte)).collect();
+        let header_val: String = line[colon + 1..]
+            .iter()
+            .map(|&byte| char::from(byte))
+            .collect();
+        let header_val = header_val.trim();
+        let existing = headers
+            .iter_mut()
+            .find(|(header, _)| header.eq_ignore_ascii_case(&header_key));
+        match existing {
+            Some((_, value)) if !is_unjoinable(&header_key) => {
+                value.push_str(", ");
+                value.push_str(header_val);
+            }
+            Some((_, value)) => *value = header_val.to_string(),
+            None => {
+                headers.insert(header_key, header_val.to_string());
+            }
+        }
+    }
+}
+
+/// Appends the next `length` bytes of `stream` to `content`
+///
+/// The bytes are added as they arrive, so a `length` that's far more than
+/// the peer sends only costs what it really sent. Fails with `BodyTooLarge`
+/// if `content` would grow past `usize::MAX`, or with `DeadlineExceeded` if
+/// `deadline` passes before they've all been read.
+fn read_body_bytes(
+    stream: &mut impl BufRead,
+    content: &mut Vec<u8>,
+    length: usize,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    if content.len().checked_add(length).is_none() {
+        return Err(Error::BodyTooLarge);
+    }
+    let mut remaining = length;
+    while remaining > 0 {
+        let available = match stream.fill_buf() {
+            Ok([]) => return Err(Error::StreamReadError),
+            Ok(available) => available,
+            Err(why) if why.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return Err(Error::StreamReadError),
+        };
+        let read = available.len().min(remaining);
+        content.extend_from_slice(&available[..read]);
+        stream.consume(read);
+        remaining -= read;
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            return Err(Error::DeadlineExceeded);
+        }
+    }
+    Ok(())
+}
+
+/// Appends everything left on `stream` to `content`, for a body that ends
+/// when the connection is closed
+///
+/// The end of the stream is the end of the body, so reading nothing at all
+/// is an empty body, while a read that fails is a `StreamReadError`. Fails
+/// with `BodyTooLarge` once there's more than `max_size` of it, or with
+/// `DeadlineExceeded` if `deadline` passes before the end.
+fn read_until_close(
+    stream: &mut impl BufRead,
+    content: &mut Vec<u8>,
+    max_size: usize,
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    loop {
+        let available = match stream.fill_buf() {
+            Ok([]) => return Ok(()),
+            Ok(available) => available,
+            Err(why) if why.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return Err(Error::StreamReadError),
+        };
+        let read = available.len();
+        if read > max_size - content.len() {
+            return Err(Error::BodyTooLarge);
+        }
+        content.extend_from_slice(available);
+        stream.consume(read);
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            return Err(Error::DeadlineExceeded);
+        }
+    }
+}
+
+/// Turns a `HeaderTimeout` from reading the start of a message with an
+/// overall deadline into `DeadlineExceeded`
+fn deadline_error(error: Error) -> Error {
+    match error {
+        Error::HeaderTimeout => Error::DeadlineExceeded,
+        error => error,
+    }
+}
+
+/// Reads a body sent with `Transfer-Encoding: chunked`, without the chunking,
+/// along with the trailers after it (see `merge_trailers`)
+///
+/// Fails with `BodyTooLarge` as soon as the chunks add up to more than `max_size`,
+/// `TooManyHeaders` if there are more than `max_trailers` trailers, or
+/// `DeadlineExceeded` if `deadline` passes before the end. Chunk extensions
+/// are skipped over.
+fn read_chunked_body(
+    stream: &mut impl BufRead,
+    max_size: usize,
+    max_trailers: usize,
+    deadline: Option<Instant>,
+) -> Result<(Vec<u8>, HashMap<String, String>), Error> {
+    let mut content = Vec::new();
+    loop {
+        let size_line = read_line(stream, deadline).map_err(deadline_error)?;
+        let size = match size_line.iter().position(|&byte| byte == b';') {
+            Some(extensions_start) => &size_line[..extensions_start],
+            None => &size_line[..],
+        };
+        let size = match std::str::from_utf8(size)
+            .ok()
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+        {
+            Some(size) => size,
+            None => return Err(Error::UnreadableMessageError),
+        };
+        if size == 0 {
+            break;
+        }
+        if size > max_size - content.len() {
+            return Err(Error::BodyTooLarge);
+        }
+        read_body_bytes(stream, &mut content, size, deadline)?;
+        if !read_line(stream, deadline)
+            .map_err(deadline_error)?
+            .is_empty()
+        {
+            return Err(Error::UnreadableMessageError);
+        }
+    }
+    // the trailers, which end with a blank line like the headers
+    let trailers = read_headers(stream, max_trailers, deadline).map_err(deadline_error)?;
+    Ok((content, trailers))
+}
+
+/// Headers that can't be sent as trailers, as they have to be known
+/// before the body is read
+const FORBIDDEN_TRAILERS: [&str; 5] = [
+    headers::CONTENT_LENGTH,
+    headers::CONTENT_TYPE,
+    headers::HOST,
+    headers::TRAILER,
+    headers::TRANSFER_ENCODING,
+];
+
+/// Adds the `trailers` sent after a chunked body to `headers`, but only
+/// those the `Trailer` header announced, and none of `FORBIDDEN_TRAILERS`
+///
+/// A trailer that's also a header has its value joined on with `, `.
+fn merge_trailers(headers: &mut HashMap<String, String>, trailers: HashMap<String, String>) {
+    let announced: Vec<String> = match get_header(headers, headers::TRAILER) {
+        Some(announced) => announced
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect(),
+        None => return,
+    };
+    for (name, value) in trailers {
+        let lowercase = name.to_ascii_lowercase();
+        if !announced.contains(&lowercase)
+            || FORBIDDEN_TRAILERS
+                .iter()
+                .any(|forbidden| forbidden.eq_ignore_ascii_case(&name))
+        {
+            continue;
+        }
+        let existing = headers
+            .iter_mut()
+            .find(|(header, _)| header.eq_ignore_ascii_case(&name));
+        match existing {
+            Some((_, existing)) => {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            }
+            None => {
+                headers.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Whether `headers` say the body is sent with `Transfer-Encoding: chunked`
+fn is_chunked(headers: &HashMap<String, String>) -> bool {
+    match get_header(headers, headers::TRANSFER_ENCODING) {
+        // chunked has to be the last coding if it's there
+        Some(codings) => codings
+            .rsplit(',')
+            .next()
+            .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked")),
+        None => false,
+    }
+}
+
+/// How the end of a message's body is found
+#[derive(Debug, PartialEq, Eq)]
+enum BodyFraming {
+    /// It's sent with `Transfer-Encoding: chunked`
+    Chunked,
+    /// It's this many bytes long, from `Content-Length`
+    Length(usize),
+    /// It's sent with some other transfer coding, so it ends when the
+    /// connection is closed
+    UntilClose,
+    /// There's no body, or at least nothing says how long it is
+    Missing,
+}
+
+/// Works out how the body after `headers` is framed
+///
+/// `Transfer-Encoding` wins over `Content-Length` when both are sent,
+/// as RFC 9112 says. A `Content-Length` sent more than once, even with
+/// the same value each time, fails with `ConflictingFraming`.
+fn body_framing(headers: &HashMap<String, String>) -> Result<BodyFraming, Error> {
+    if get_header(headers, headers::TRANSFER_ENCODING).is_some() {
+        return Ok(if is_chunked(headers) {
+            BodyFraming::Chunked
+        } else {
+            BodyFraming::UntilClose
+        });
+    }
+    let content_length = match get_header(headers, headers::CONTENT_LENGTH) {
+        Some(content_length) => content_length,
+        None => return Ok(BodyFraming::Missing),
+    };
+    // repeated headers are joined with commas when they're read
+    if content_length.contains(',') {
+        return Err(Error::ConflictingFraming);
+    }
+    match content_length.trim().parse::<usize>() {
+        Ok(content_length) => Ok(BodyFraming::Length(content_length)),
+        Err(_) => Err(Error::InvalidContentLength(
+            InvalidContentLengthReason::MalformedContentLength,
+        )),
+    }
+}
+
+/// Parses an HTTP version like `HTTP/1.1`
+fn parse_http_version(version: &[u8]) -> Result<(i32, i32), Error> {
+    let version = match version.strip_prefix(b"HTTP/") {
+        Some(version) => version,
+        None => return Err(Error::NotHTTP),
+    };
+    let (major, minor) = match version.iter().position(|&byte| byte == b'.') {
+        Some(dot) => (&version[..dot], &version[dot + 1..]),
+        None => return Err(Error::InvalidVersionError),
+    };
+    let parse_number = |number: &[u8]| {
+        if number.is_empty() || !number.iter().all(u8::is_ascii_digit) {
+            return Err(Error::InvalidVersionError);
+        }
+        String::from_utf8_lossy(number)
+            .parse::<i32>()
+            .map_err(|_| Error::InvalidVersionError)
+    };
+    Ok((parse_number(major)?, parse_number(minor)?))
+}
+
+/// Parses the first line of a request (like `GET / HTTP/1.1`) into its method,
+/// path and HTTP version
+///
+/// The line may or may not include the `\r\n` at the end.
+///
+/// # Examples
+/// ```
+/// # use rustedflask::core::http;
+/// let (method, path, version) = http::parse_request_line(b"GET /index.html HTTP/1.1")?;
+/// assert_eq!(method, b"GET");
+/// assert_eq!(path, b"/index.html");
+/// assert_eq!(version, (1, 1));
+/// # Ok::<(), http::Error>(())
+/// ```
+pub fn parse_request_line(line: &[u8]) -> Result<RequestLine, Error> {
+    let line = line.strip_suffix(b"\r\n").unwrap_or(line);
+    let mut parts = line.split(|&byte| byte == b' ');
+    let (method, path, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(path), Some(version), None) if !method.is_empty() => {
+            (method, path, version)
+        }
+        _ => return Err(Error::UnreadableMessageError),
+    };
+    Ok((method.to_vec(), path.to_vec(), parse_http_version(version)?))
+}
+
+/// Parses the first line of a response (like `HTTP/1.1 404 Not Found`) into
+/// its HTTP version, status code and reason
+///
+/// The line may or may not include the `\r\n` at the end.
+///
+/// # Examples
+/// ```
+/// # use rustedflask::core::http;
+/// let (version, status, reason) = http::parse_status_line(b"HTTP/1.0 404 Not Found")?;
+/// assert_eq!(version, (1, 0));
+/// assert_eq!(status.status_code_number(), 404);
+/// assert_eq!(reason, b"Not Found");
+/// # Ok::<(), http::Error>(())
+/// ```
+pub fn parse_status_line(line: &[u8]) -> Result<StatusLine, Error> {
+    let line = line.strip_suffix(b"\r\n").unwrap_or(line);
+    let mut parts = line.splitn(3, |&byte| byte == b' ');
+    let (version, statuscode, reason) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(version), Some(statuscode), reason) => (version, statuscode, reason.unwrap_or(b"")),
+        _ => return Err(Error::UnreadableMessageError),
+    };
+    let version = parse_http_version(version)?;
+    if statuscode.len() != 3 || !statuscode.iter().all(u8::is_ascii_digit) {
+        return Err(Error::UnreadableStatusCode);
+    }
+    let status_int = match String::from_utf8_lossy(statuscode).parse::<u16>() {
+        Ok(status_int) => status_int,
+        Err(_) => return Err(Error::UnreadableStatusCode),
+    };
+    let status = match HttpStatusCodes::from_code(status_int) {
+        Some(status) => status,
+        None => return Err(Error::UnknownStatusError),
+    };
+    Ok((version, status, reason.to_vec()))
 }
 
 impl From<HTTPRequest> for Vec<u8> {
-    /// Makes a `Vec<u8>` from an `HTTPRequest`, converting it into something suitable to be sent over the Internet
+    /// Makes a `Vec<u8>` from an `HTTPRequest` (see `HTTPRequest::to_bytes`)
+    fn from(request: HTTPRequest) -> Vec<u8> {
+        request.to_bytes()
+    }
+}
+
+impl HTTPRequest {
+    /// Serializes this request into something suitable to be sent over the Internet,
+    /// without consuming it
     /// # Examples
     /// ```
     /// # use rustedflask::core::http;
@@ -180,44 +841,142 @@ impl From<HTTPRequest> for Vec<u8> {
     /// # let request = http::HTTPRequest {
     /// #       method: b"GET".to_vec(),
     /// #       path: b"/".to_vec(),
+    /// #       raw_path: b"/".to_vec(),
     /// #       httptag: Box::new(b"HTTP".to_owned()),
     /// #       httpversion: (1, 1),
     /// #       headers: headers,
     /// #       content: b"".into(),
+    /// #       request_id: None,
+    /// #       secure: false,
+    /// #       parsed_body: None,
+    /// #       lazy_body: None,
+    /// #       path_params: HashMap::new(),
     /// # };
-    ///let request_bytes = Vec::<u8>::from(request);
+    /// let request_bytes = request.to_bytes();
     /// ```
-    fn from(request: HTTPRequest) -> Vec<u8> {
+    pub fn to_bytes(&self) -> Vec<u8> {
         let mut out = Vec::new();
         // GET
-        out.extend(request.method.iter());
+        out.extend(self.method.iter());
         out.push(b' ');
         // GET /
-        out.extend(request.path.iter());
+        out.extend(self.path.iter());
         out.push(b' ');
         // GET / HTTP/
-        out.extend(request.httptag.iter());
+        out.extend(self.httptag.iter());
         out.push(b'/');
         // GET / HTTP/1
-        out.extend(httpver_to_vecu8(request.httpversion));
+        out.extend(httpver_to_vecu8(self.httpversion));
         // Newline
         out.extend(b"\r\n".iter());
         // Headers
-        for (header, val) in request.headers {
-            out.extend(header.as_bytes());
+        for (header, val) in &self.headers {
+            extend_single_line(&mut out, header.as_bytes());
             out.extend(b": ".iter());
-            out.extend(val.as_bytes());
+            extend_single_line(&mut out, val.as_bytes());
             out.extend(b"\r\n");
         }
-        if !request.content.is_empty() {
-            out.extend(request.content);
-        };
+        // the blank line between the headers and the body
         out.extend(b"\r\n");
+        out.extend(&self.content);
         out
     }
-}
 
-impl HTTPRequest {
+    /// Makes an HTTP/1.1 request with `method` for `path`, without any
+    /// headers or body
+    ///
+    /// The `Host` header is left for the caller to add.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPRequest;
+    /// let request = HTTPRequest::new("DELETE", "/users/1");
+    /// assert_eq!(request.to_bytes(), b"DELETE /users/1 HTTP/1.1\r\n\r\n");
+    /// ```
+    pub fn new(method: &str, path: &str) -> HTTPRequest {
+        HTTPRequest {
+            method: method.as_bytes().to_vec(),
+            path: path.as_bytes().to_vec(),
+            raw_path: path.as_bytes().to_vec(),
+            httptag: Box::new(b"HTTP".to_owned()),
+            httpversion: (1, 1),
+            headers: HashMap::new(),
+            content: Vec::new(),
+            request_id: None,
+            secure: false,
+            parsed_body: None,
+            lazy_body: None,
+            path_params: HashMap::new(),
+        }
+    }
+
+    /// Makes a `GET` request for `path` (see `HTTPRequest::new`)
+    pub fn get(path: &str) -> HTTPRequest {
+        HTTPRequest::new("GET", path)
+    }
+
+    /// Makes a `POST` request to `path` with `body` as its body, along
+    /// with its `Content-Length` (see `HTTPRequest::new`)
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPRequest;
+    /// let request = HTTPRequest::post("/search", "q=rust");
+    /// assert_eq!(request.headers["Content-Length"], "6");
+    /// ```
+    pub fn post(path: &str, body: impl Into<Vec<u8>>) -> HTTPRequest {
+        let mut request = HTTPRequest::new("POST", path);
+        request.content = body.into();
+        request.headers.insert(
+            headers::CONTENT_LENGTH.to_string(),
+            request.content.len().to_string(),
+        );
+        request
+    }
+
+    /// Makes a `POST` request to `path` with `json` as its body
+    /// (see `HTTPRequest::with_json_body`)
+    ///
+    /// The `Host` header is left for the caller to add.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPRequest;
+    /// let request = HTTPRequest::post_json("/api/users", r#"{"name":"Ferris"}"#);
+    /// assert_eq!(request.method, b"POST");
+    /// assert_eq!(request.headers["Content-Type"], "application/json");
+    /// assert_eq!(request.headers["Content-Length"], "17");
+    /// ```
+    pub fn post_json(path: &str, json: impl Into<String>) -> HTTPRequest {
+        HTTPRequest::new("POST", path).with_json_body(json)
+    }
+
+    /// Sets the body of this request to `json`, along with its `Content-Type`
+    /// and `Content-Length`
+    ///
+    /// `json` has to be serialized already, e.g. with `serde_json::to_string`,
+    /// as this crate doesn't depend on a JSON library.
+    pub fn with_json_body(self, json: impl Into<String>) -> HTTPRequest {
+        let mut returnval = self;
+        returnval.content = json.into().into_bytes();
+        returnval.headers.insert(
+            headers::CONTENT_TYPE.to_string(),
+            "application/json".to_string(),
+        );
+        returnval.headers.insert(
+            headers::CONTENT_LENGTH.to_string(),
+            returnval.content.len().to_string(),
+        );
+        returnval
+    }
+
+    /// Iterates over the headers of this request, without moving them
+    pub fn iter_headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .map(|(header, value)| (header.as_str(), value.as_str()))
+    }
+
     /// Sends this request to the given `address` via TCP
     /// # Examples
     /// ```
@@ -228,219 +987,503 @@ impl HTTPRequest {
     /// # let mut request = http::HTTPRequest {
     /// #       method: b"GET".to_vec(),
     /// #       path: b"/".to_vec(),
+    /// #       raw_path: b"/".to_vec(),
     /// #       httptag: Box::new(b"HTTP".to_owned()),
     /// #       httpversion: (1, 1),
     /// #       headers: headers,
     /// #       content: b"".into(),
+    /// #       request_id: None,
+    /// #       secure: false,
+    /// #       parsed_body: None,
+    /// #       lazy_body: None,
+    /// #       path_params: HashMap::new(),
     /// # };
     /// // Watch out! You need the port
     /// request.send_to("example.com:80".into());
     /// ```
     pub fn send_to(&mut self, address: String) -> Result<HTTPResponse, Error> {
-        let stream = TcpStream::connect(address);
-        if stream.is_err() {
-            return Err(Error::CouldntConnect);
+        let mut stream = match TcpStream::connect(address) {
+            Ok(stream) => BufferedStream::new(stream),
+            Err(_) => return Err(Error::CouldntConnect),
         };
-
-        let mut unwrapped_stream = stream.unwrap();
-        let send_buffer = &mut [0_u8; 1];
-        for byte in Into::<Vec<u8>>::into(self.to_owned()) {
-            send_buffer[0] = byte;
-            let err = unwrapped_stream.write(send_buffer);
-            if err.is_err() {
-                return Err(Error::CouldntSend);
-            };
-            assert_eq!(err.unwrap(), 1_usize);
-        }
-        HTTPResponse::read_http_response(&mut unwrapped_stream)
+        self.send_over(&mut stream)
     }
 
-    /// Reads an HTTP request from `stream` into an HTTPRequest
-    pub fn read_http_request(stream: &mut impl Read) -> Result<HTTPRequest, Error> {
-        let mut method_string = String::new();
-        let meth_read_buffer = &mut [0_u8; 1];
-        if stream.read(meth_read_buffer).is_err() {
-            return Err(Error::StreamReadError);
-        }
-        while meth_read_buffer[0] != 0x20 {
-            method_string.push(meth_read_buffer[0].into());
-            if stream.read(meth_read_buffer).is_err() {
-                return Err(Error::StreamReadError);
+    /// Sends this request to the given `address` via TCP, trying again
+    /// after network failures (like a refused or reset connection) as
+    /// `policy` allows
+    ///
+    /// If every attempt fails, the error from the last one is returned.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::core::http::{self, RetryPolicy};
+    /// # use std::collections::HashMap;
+    /// # let mut request = http::HTTPRequest {
+    /// #       method: b"GET".to_vec(),
+    /// #       path: b"/".to_vec(),
+    /// #       raw_path: b"/".to_vec(),
+    /// #       httptag: Box::new(b"HTTP".to_owned()),
+    /// #       httpversion: (1, 1),
+    /// #       headers: HashMap::new(),
+    /// #       content: b"".into(),
+    /// #       request_id: None,
+    /// #       secure: false,
+    /// #       parsed_body: None,
+    /// #       lazy_body: None,
+    /// #       path_params: HashMap::new(),
+    /// # };
+    /// let response = request.send_to_with_retry("example.com:80".into(), &RetryPolicy::default());
+    /// ```
+    pub fn send_to_with_retry(
+        &mut self,
+        address: String,
+        policy: &RetryPolicy,
+    ) -> Result<HTTPResponse, Error> {
+        let retries = if policy.allows_method(&self.method) {
+            policy.retries
+        } else {
+            0
+        };
+        let mut retry = 0;
+        loop {
+            match self.send_to(address.clone()) {
+                Err(why) if retry < retries && retry::is_transient(&why) => {
+                    thread::sleep(policy.delay(retry));
+                    retry += 1;
+                }
+                result => return result,
             }
         }
+    }
 
-        let mut path_string = String::new();
-        let path_read_buffer = &mut [0_u8; 1];
-        if stream.read(path_read_buffer).is_err() {
-            return Err(Error::StreamReadError);
+    /// Sends this request over an already open `stream` and reads the response
+    ///
+    /// To send more requests over the same connection, keep reading from the
+    /// same `BufferedStream` (or other `BufRead`).
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::core::http;
+    /// # use std::{collections::HashMap, net::TcpStream};
+    /// # use rustedflask::core::http::BufferedStream;
+    /// # let mut request = http::HTTPRequest {
+    /// #       method: b"GET".to_vec(),
+    /// #       path: b"/".to_vec(),
+    /// #       raw_path: b"/".to_vec(),
+    /// #       httptag: Box::new(b"HTTP".to_owned()),
+    /// #       httpversion: (1, 1),
+    /// #       headers: HashMap::new(),
+    /// #       content: b"".into(),
+    /// #       request_id: None,
+    /// #       secure: false,
+    /// #       parsed_body: None,
+    /// #       lazy_body: None,
+    /// #       path_params: HashMap::new(),
+    /// # };
+    /// let mut stream = BufferedStream::new(TcpStream::connect("example.com:80").unwrap());
+    /// let response = request.send_over(&mut stream);
+    /// ```
+    pub fn send_over(
+        &mut self,
+        stream: &mut (impl BufRead + Write),
+    ) -> Result<HTTPResponse, Error> {
+        if stream.write_all(&self.to_bytes()).is_err() {
+            return Err(Error::CouldntSend);
         }
-        while path_read_buffer[0] != 0x20 {
-            path_string.push(path_read_buffer[0].into());
-            if stream.read(path_read_buffer).is_err() {
-                return Err(Error::StreamReadError);
-            }
+        HTTPResponse::read_http_response(stream)
+    }
+
+    /// Whether this request has a body, even an empty one
+    ///
+    /// A request with `Content-Length: 0` has an empty body, while a request
+    /// without a `Content-Length` or `Transfer-Encoding` header has none at all.
+    pub fn has_body(&self) -> bool {
+        get_header(&self.headers, headers::CONTENT_LENGTH).is_some()
+            || get_header(&self.headers, headers::TRANSFER_ENCODING).is_some()
+    }
+
+    /// Whether the client connected over HTTPS
+    ///
+    /// That's the case when the request came in over TLS (see
+    /// `App::handle_tls_connection`), or through a trusted proxy that says
+    /// the client used HTTPS with `X-Forwarded-Proto: https` (see
+    /// `App::trust_forwarded_proto`). Plain HTTP requests aren't secure.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// The scheme the client used to make this request, `https` if it's
+    /// secure (see `HTTPRequest::is_secure`) and `http` otherwise
+    pub fn scheme(&self) -> &'static str {
+        if self.secure {
+            "https"
+        } else {
+            "http"
         }
+    }
 
-        let method = method_string.into_bytes();
-        let path = path_string.into_bytes();
+    /// The host (and port, if there is one) the client sent this request
+    /// to, from its `Host` header
+    pub fn host(&self) -> Option<&str> {
+        get_header(&self.headers, headers::HOST)
+            .map(|host| host.trim())
+            .filter(|host| !host.is_empty())
+    }
 
-        // read the HTTP thing
-        let httptag: &mut [u8; 5] = &mut [0_u8; 5];
-        let mut err = stream.read(httptag);
-        if err.is_err() {
-            return Err(Error::StreamReadError);
+    /// The absolute URL that `location` (like the `Location` of a redirect)
+    /// points to, relative to this request
+    ///
+    /// Absolute URLs (`https://example.com/a`) are returned as they are.
+    /// Protocol-relative ones (`//example.com/a`) get this request's scheme
+    /// (see `HTTPRequest::scheme`), and paths get its scheme and `Host` as
+    /// well. Paths that don't start with a `/` (`next`, `../up`, `?page=2`)
+    /// are relative to this request's path, like links in a page.
+    ///
+    /// `None` if `location` needs the host, but this request doesn't have one.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPRequest;
+    /// # use std::collections::HashMap;
+    /// # let request = HTTPRequest {
+    /// #       method: b"GET".to_vec(),
+    /// #       path: b"/docs/intro".to_vec(),
+    /// #       raw_path: b"/docs/intro".to_vec(),
+    /// #       httptag: Box::new(b"HTTP".to_owned()),
+    /// #       httpversion: (1, 1),
+    /// #       headers: HashMap::from([("Host".to_string(), "example.com".to_string())]),
+    /// #       content: Vec::new(),
+    /// #       request_id: None,
+    /// #       secure: false,
+    /// #       parsed_body: None,
+    /// #       lazy_body: None,
+    /// #       path_params: HashMap::new(),
+    /// # };
+    /// assert_eq!(
+    ///     request.resolve_location("setup").as_deref(),
+    ///     Some("http://example.com/docs/setup")
+    /// );
+    /// ```
+    pub fn resolve_location(&self, location: &str) -> Option<String> {
+        if has_scheme(location) {
+            return Some(location.to_string());
         }
-        if httptag != b"HTTP/" {
-            return Err(Error::NotHTTP);
-        };
-        let http_version_bytes = &mut [0_u8; 3];
-        err = stream.read(http_version_bytes);
-        if err.is_err() {
-            return Err(Error::StreamReadError);
+        if location.starts_with("//") {
+            return Some(format!("{}:{}", self.scheme(), location));
         }
-        if http_version_bytes[1] != b'.' {
-            return Err(Error::InvalidVersionError);
+        let origin = format!("{}://{}", self.scheme(), self.host()?);
+        let (location, fragment) = match location.find('#') {
+            Some(fragment_start) => location.split_at(fragment_start),
+            None => (location, ""),
         };
-        let http_major = char::try_from(http_version_bytes[0])
-            .unwrap()
-            .to_string()
-            .parse::<i32>();
-        if http_major.is_err() {
-            return Err(Error::InvalidVersionError);
+        let path = String::from_utf8_lossy(&self.path);
+        let path = path.split('#').next().unwrap_or("");
+        let (path, query) = match path.find('?') {
+            Some(query_start) => path.split_at(query_start),
+            None => (path, ""),
         };
-        let http_minor = char::try_from(http_version_bytes[2])
-            .unwrap()
-            .to_string()
-            .parse::<i32>();
-        if http_minor.is_err() {
-            return Err(Error::InvalidVersionError);
+        let target = if location.is_empty() {
+            // just a fragment, which is on this same page
+            format!("{}{}", path, query)
+        } else if location.starts_with('?') {
+            format!("{}{}", path, location)
+        } else {
+            let (location, query) = match location.find('?') {
+                Some(query_start) => location.split_at(query_start),
+                None => (location, ""),
+            };
+            let merged = if location.starts_with('/') {
+                location.to_string()
+            } else {
+                // anything else is next to the requested path, in the same folder
+                let folder = match path.rfind('/') {
+                    Some(folder_end) => &path[..=folder_end],
+                    None => "/",
+                };
+                format!("{}{}", folder, location)
+            };
+            format!("{}{}", remove_dot_segments(&merged), query)
         };
-        let httpversion = (http_major.unwrap(), http_minor.unwrap());
+        Some(format!("{}{}{}", origin, target, fragment))
+    }
 
-        let _ = stream.read(&mut [0_u8; 1]);
+    /// The language in `available` the client would most like a response in,
+    /// going by its `Accept-Language` header
+    ///
+    /// Languages are tried from the highest quality (`q=`) down. A language
+    /// matches itself, and the languages it's a prefix of, so `en` matches
+    /// `en-US` (and the other way around). `None` if nothing matches, or
+    /// there's no `Accept-Language` header.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPRequest;
+    /// # use std::collections::HashMap;
+    /// # let mut headers = HashMap::new();
+    /// # headers.insert("Accept-Language".to_string(), "fr;q=0.5, de, en".to_string());
+    /// # let request = HTTPRequest {
+    /// #       method: b"GET".to_vec(),
+    /// #       path: b"/".to_vec(),
+    /// #       raw_path: b"/".to_vec(),
+    /// #       httptag: Box::new(b"HTTP".to_owned()),
+    /// #       httpversion: (1, 1),
+    /// #       headers,
+    /// #       content: Vec::new(),
+    /// #       request_id: None,
+    /// #       secure: false,
+    /// #       parsed_body: None,
+    /// #       lazy_body: None,
+    /// #       path_params: HashMap::new(),
+    /// # };
+    /// // with `Accept-Language: fr;q=0.5, de, en`
+    /// assert_eq!(request.preferred_language(&["en-US", "fr"]), Some("en-US"));
+    /// ```
+    pub fn preferred_language<'a>(&self, available: &[&'a str]) -> Option<&'a str> {
+        let accept_language = get_header(&self.headers, headers::ACCEPT_LANGUAGE)?;
+        let mut ranges: Vec<(&str, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parameters = range.split(';').map(str::trim);
+                let language = parameters.next().filter(|language| !language.is_empty())?;
+                let quality = parameters
+                    .find_map(|parameter| parameter.strip_prefix("q="))
+                    .map_or(Some(1.0), |quality| quality.parse::<f32>().ok())?;
+                Some((language, quality))
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        // a stable sort, so languages with the same quality stay in the client's order
+        ranges.sort_by(|(_, a), (_, b)| b.total_cmp(a));
 
-        let mut headers = HashMap::<String, String>::new();
+        let is_prefix = |prefix: &str, language: &str| {
+            language.len() > prefix.len()
+                && language.as_bytes()[prefix.len()] == b'-'
+                && language[..prefix.len()].eq_ignore_ascii_case(prefix)
+        };
+        ranges.iter().find_map(|&(range, _)| {
+            if range == "*" {
+                return available.first().copied();
+            }
+            available
+                .iter()
+                .find(|language| language.eq_ignore_ascii_case(range))
+                .or_else(|| {
+                    available
+                        .iter()
+                        .find(|language| is_prefix(range, language) || is_prefix(language, range))
+                })
+                .copied()
+        })
+    }
 
-        loop {
-            let mut header_key = String::new();
-            let mut header_val = String::new();
-            let cur_char = &mut [0_u8; 1];
-            err = stream.read(cur_char);
-            if err.is_err() {
-                return Err(Error::StreamReadError);
+    /// The value of the header called `name` (ignoring case), with the values
+    /// of any repeats of it joined with `, `
+    ///
+    /// Repeated headers are joined when a request is read, so this only
+    /// matters for headers added in different cases afterwards. `Set-Cookie`
+    /// is never joined.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPRequest;
+    /// # use std::io::Cursor;
+    /// let mut stream = Cursor::new(b"GET / HTTP/1.1\r\nAccept: text/html\r\naccept: */*\r\n\r\n");
+    /// let request = HTTPRequest::read_http_request(&mut stream)?;
+    /// assert_eq!(request.get_combined("Accept").unwrap(), "text/html, */*");
+    /// # Ok::<(), rustedflask::core::http::Error>(())
+    /// ```
+    pub fn get_combined(&self, name: &str) -> Option<String> {
+        get_combined(&self.headers, name)
+    }
+
+    /// The value of the header `name`, ignoring the case of the name
+    /// (see `http::headers` for the names of common headers)
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::{headers, HTTPRequest};
+    /// let request = HTTPRequest::post_json("/api", "{}");
+    /// assert_eq!(request.header(headers::CONTENT_TYPE), Some("application/json"));
+    /// assert_eq!(request.header("content-type"), Some("application/json"));
+    /// ```
+    pub fn header(&self, name: &str) -> Option<&str> {
+        get_header(&self.headers, name).map(|value| &**value)
+    }
+
+    /// The parameter called `name` in the path of this request, for routes
+    /// with parameters in their path
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.route("/user/<id>", |request| {
+    ///     format!("User {}", request.path_param("id").unwrap()).as_str().into()
+    /// });
+    /// ```
+    pub fn path_param(&self, name: &str) -> Option<&str> {
+        self.path_params.get(name).map(String::as_str)
+    }
+
+    /// The body of this request, which is empty if it doesn't have one (see `has_body`)
+    pub fn body_bytes(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Reads an HTTP request from `stream` into an HTTPRequest,
+    /// with the default `RequestLimits`
+    ///
+    /// The head is read a line at a time from `stream`'s buffer, so anything
+    /// after the request is left in it. Wrap a connection in a
+    /// `BufferedStream` once, and read every request on it from that.
+    pub fn read_http_request(stream: &mut impl BufRead) -> Result<HTTPRequest, Error> {
+        HTTPRequest::read_http_request_with_limits(stream, &RequestLimits::default())
+    }
+
+    /// Reads an HTTP request from `stream` into an HTTPRequest, failing if
+    /// it goes over any of `limits`
+    pub fn read_http_request_with_limits(
+        stream: &mut impl BufRead,
+        limits: &RequestLimits,
+    ) -> Result<HTTPRequest, Error> {
+        HTTPRequest::read_request(stream, limits, None)
+    }
+
+    /// Reads an HTTP request from `stream` into an HTTPRequest, with the
+    /// default `RequestLimits`, failing with `DeadlineExceeded` if all of it
+    /// hasn't arrived by `deadline`
+    ///
+    /// The clock is checked between reads, so this can't interrupt a read that
+    /// never returns; set a read timeout on the stream for that.
+    pub fn read_http_request_deadline(
+        stream: &mut impl BufRead,
+        deadline: Instant,
+    ) -> Result<HTTPRequest, Error> {
+        HTTPRequest::read_request(stream, &RequestLimits::default(), Some(deadline))
+    }
+
+    /// Reads the start of an HTTP request from `stream`, leaving a body
+    /// with a `Content-Length` on the stream to be read by the `BodySource`
+    pub(crate) fn read_http_request_lazily(
+        stream: &mut impl BufRead,
+        limits: &RequestLimits,
+    ) -> Result<(HTTPRequest, Option<BodySource>), Error> {
+        HTTPRequest::read_request_start(stream, limits, None, true)
+    }
+
+    fn read_request(
+        stream: &mut impl BufRead,
+        limits: &RequestLimits,
+        deadline: Option<Instant>,
+    ) -> Result<HTTPRequest, Error> {
+        HTTPRequest::read_request_start(stream, limits, deadline, false).map(|(request, _)| request)
+    }
+
+    /// Reads a request from `stream`, along with its body unless it's `lazy`
+    /// and has a `Content-Length`
+    fn read_request_start(
+        stream: &mut impl BufRead,
+        limits: &RequestLimits,
+        deadline: Option<Instant>,
+        lazy: bool,
+    ) -> Result<(HTTPRequest, Option<BodySource>), Error> {
+        // the clock starts with the first byte, so time spent waiting
+        // for a request on a kept alive connection doesn't count
+        match stream.fill_buf() {
+            Ok(available) if !available.is_empty() => {}
+            _ => return Err(Error::StreamReadError),
+        }
+        let header_deadline = limits
+            .header_timeout
+            .map(|header_timeout| Instant::now() + header_timeout);
+        // whichever of the header timeout and the overall deadline comes first
+        let header_deadline = match (header_deadline, deadline) {
+            (Some(header_deadline), Some(deadline)) => Some(header_deadline.min(deadline)),
+            (header_deadline, deadline) => header_deadline.or(deadline),
+        };
+        let header_error = |error| match (error, deadline) {
+            (Error::HeaderTimeout, Some(deadline)) if Instant::now() > deadline => {
+                Error::DeadlineExceeded
             }
-            if cur_char[0] == b'\r' {
-                break;
+            (error, _) => error,
+        };
+        let request_line = read_line(stream, header_deadline).map_err(header_error)?;
+        let (method, path, httpversion) = parse_request_line(&request_line)?;
+        let mut headers =
+            read_headers(stream, limits.max_headers, header_deadline).map_err(header_error)?;
+        // any body has to be read, even if the method doesn't usually
+        // have one, so the next request on the stream starts in the right place
+        let mut content = Vec::<u8>::new();
+        let mut body = None;
+        match body_framing(&headers)? {
+            BodyFraming::Chunked => {
+                let trailers;
+                (content, trailers) =
+                    read_chunked_body(stream, limits.max_body_size, limits.max_headers, deadline)?;
+                merge_trailers(&mut headers, trailers);
             }
-            while cur_char[0] != b':' {
-                header_key.push(cur_char[0].into());
-                err = stream.read(cur_char);
-                if err.is_err() {
-                    return Err(Error::StreamReadError);
+            BodyFraming::Length(content_length) => {
+                if content_length > limits.max_body_size {
+                    return Err(Error::BodyTooLarge);
                 }
-            }
-            let _ = stream.read(cur_char);
-            err = stream.read(cur_char);
-            if err.is_err() {
-                return Err(Error::StreamReadError);
-            }
-            while cur_char[0] != b'\r' {
-                header_val.push(cur_char[0].into());
-                err = stream.read(cur_char);
-                if err.is_err() {
-                    return Err(Error::StreamReadError);
+                if lazy && content_length > 0 {
+                    body = Some(BodySource::new(content_length));
+                } else {
+                    read_body_bytes(stream, &mut content, content_length, deadline)?;
                 }
             }
-            let _ = stream.read(cur_char);
-            headers.insert(header_key, header_val);
+            // a request body can only end with the connection if the
+            // response can't be sent back on it
+            BodyFraming::UntilClose => return Err(Error::ConflictingFraming),
+            BodyFraming::Missing => {}
         }
-        // todo finish
-        let mut l_read = 0;
-        let mut content = Vec::<u8>::new();
-        if headers.contains_key("Content-Length") {
-            let string_content_length = headers["Content-Length"].parse();
-            if string_content_length.is_err() {
-                return Err(Error::InvalidContentLength(
-                    InvalidContentLengthReason::MalformedContentLength,
-                ));
-            };
-            let content_length = string_content_length.unwrap();
-            while l_read < content_length {
-                l_read += 1;
-                let tempbuf = &mut [0_u8; 1];
-                err = stream.read(tempbuf);
-                if err.is_err() {
-                    return Err(Error::StreamReadError);
-                }
-                content.push(tempbuf[0]);
-            }
-        };
-        Ok(HTTPRequest {
+        let (lazy_body, source) = body.unzip();
+        let request = HTTPRequest {
             method,
+            raw_path: path.clone(),
             path,
-            httptag: Box::new(*httptag),
+            httptag: Box::new(b"HTTP".to_owned()),
             httpversion,
             headers,
             content,
-        })
+            request_id: None,
+            secure: false,
+            parsed_body: None,
+            lazy_body,
+            path_params: HashMap::new(),
+        };
+        Ok((request, source))
     }
 }
 
 impl From<HTTPResponse> for Vec<u8> {
-    /// Makes a `Vec<u8>` from a request
-    /// # Examples
-    /// ```
-    /// # use rustedflask::core::http;
-    /// # use std::collections::HashMap;
-    /// # let mut headers = HashMap::new();
-    /// # headers.insert("Host".to_string(), "example.com".to_string());
-    /// # let mut response = http::HTTPResponse {
-    /// #   httptag: Box::new(b"HTTP".to_owned()),
-    /// #   httpversion: (1, 1),
-    /// #   statuscode: http::HttpStatusCodes::Ok,
-    /// #   reason: Box::new(b"OK".to_owned()),
-    /// #   headers: headers,
-    /// #   content: b"".into()
-    /// # };
-    /// let response_bytes: Vec<u8> = response.into();
-    /// ```
-    fn from(request: HTTPResponse) -> Vec<u8> {
-        let mut out = Vec::new();
-        out.extend(request.httptag.iter());
-        out.push(b'/');
-        out.extend(httpver_to_vecu8(request.httpversion));
-        out.push(b' ');
-        out.extend(Vec::<u8>::from((request.statuscode as i32).to_string()).iter());
-        out.push(b' ');
-        out.extend(request.reason.iter());
-        out.extend(b"\r\n".iter());
-        // Headers
-        for (header, val) in request.headers {
-            out.extend(header.as_bytes());
-            out.extend(b": ".iter());
-            out.extend(val.as_bytes());
-            out.extend(b"\r\n");
-        }
-        out.extend(b"\r\n");
-        if !request.content.is_empty() {
-            out.extend(request.content);
-        };
-        out.extend(b"\r\n");
-        out
+    /// Makes a `Vec<u8>` from a response (see `HTTPResponse::to_bytes`)
+    fn from(response: HTTPResponse) -> Vec<u8> {
+        response.to_bytes()
     }
 }
 
 impl From<&str> for HTTPResponse {
     fn from(value: &str) -> Self {
+        HTTPResponse::from(value.to_string())
+    }
+}
+
+/// Uses the string's buffer as the body, without copying it
+impl From<String> for HTTPResponse {
+    fn from(value: String) -> Self {
         let mut headers = HashMap::<String, String>::new();
-        headers.insert("Content-Length".into(), value.len().to_string());
+        headers.insert(headers::CONTENT_LENGTH.into(), value.len().to_string());
         HTTPResponse {
             httptag: Box::new(b"HTTP".to_owned()),
             httpversion: (1, 1),
             statuscode: HttpStatusCodes::Ok,
             reason: Box::new(b"OK".to_owned()),
             headers,
-            content: value.to_string().into_bytes(),
+            content: value.into_bytes(),
+            stream: None,
+            keep_version: false,
         }
     }
 }
@@ -455,170 +1498,549 @@ impl HTTPResponse {
             reason: Box::new(b"OK".to_owned()),
             headers: HashMap::new(),
             content: Vec::new(),
+            stream: None,
+            keep_version: false,
         }
     }
-    /// Reads an HTTP response from `stream` into an HTTPResponse
-    pub fn read_http_response(stream: &mut impl Read) -> Result<HTTPResponse, Error> {
-        // read the HTTP thing
-        let http_tag: &mut [u8; 5] = &mut [0_u8; 5];
-        let mut err = stream.read(http_tag);
-        if err.is_err() {
-            return Err(Error::StreamReadError);
-        }
-        if http_tag != b"HTTP/" {
-            return Err(Error::NotHTTP);
-        };
-        let http_version_bytes = &mut [0_u8; 3];
-        err = stream.read(http_version_bytes);
-        if err.is_err() {
-            return Err(Error::StreamReadError);
+
+    /// The status line and headers of this response, up to and
+    /// including the blank line before the body
+    fn head_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(self.httptag.iter());
+        out.push(b'/');
+        out.extend(httpver_to_vecu8(self.httpversion));
+        out.push(b' ');
+        out.extend(self.statuscode.status_code_number().to_string().as_bytes());
+        out.push(b' ');
+        // CRs and LFs are dropped from the reason and headers, so values that came
+        // from the client can't be used to add headers or split the response
+        extend_single_line(&mut out, &self.reason);
+        out.extend(b"\r\n".iter());
+        // Headers
+        for (header, val) in &self.headers {
+            extend_single_line(&mut out, header.as_bytes());
+            out.extend(b": ".iter());
+            extend_single_line(&mut out, val.as_bytes());
+            out.extend(b"\r\n");
         }
-        if http_version_bytes[1] != b'.' {
-            return Err(Error::InvalidVersionError);
-        };
-        let http_major = char::try_from(http_version_bytes[0])
-            .unwrap()
-            .to_string()
-            .parse::<i32>();
-        if http_major.is_err() {
-            return Err(Error::InvalidVersionError);
-        };
-        let http_minor = char::try_from(http_version_bytes[2])
-            .unwrap()
-            .to_string()
-            .parse::<i32>();
-        if http_minor.is_err() {
-            return Err(Error::InvalidVersionError);
-        };
-        let http_version = (http_major.unwrap(), http_minor.unwrap());
-
-        let statuscode = &mut [0_u8; 3];
-        // read the space between the version number and the status code
-        err = stream.read(&mut [0_u8; 1]);
-        if err.is_err() {
-            return Err(Error::StreamReadError);
-        }
-        // get the 3 digit status code
-        err = stream.read(statuscode);
-        if err.is_err() {
-            return Err(Error::StreamReadError);
-        }
-        let mut status_string = String::new();
-        for character in statuscode {
-            status_string.push(char::from(character.to_owned()));
-        }
-        let status_int = status_string.parse::<i32>();
-        if status_int.is_err() {
-            return Err(Error::UnreadableStatusCode);
-        };
-        let status: Option<HttpStatusCodes> = HttpStatusCodes::from_i32(status_int.unwrap());
-        if status.is_none() {
-            return Err(Error::UnknownStatusError);
-        };
-        let nl_buf = &mut [0_u8; 1];
-        let mut reason = Vec::new();
-        err = stream.read(nl_buf);
-        while nl_buf[0] != b'\r' {
-            reason.push(nl_buf[0]);
-            if err.is_err() {
-                return Err(Error::StreamReadError);
+        out.extend(b"\r\n");
+        out
+    }
+
+    /// Serializes this response, without consuming it
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http;
+    /// # use std::collections::HashMap;
+    /// # let mut headers = HashMap::new();
+    /// # headers.insert("Host".to_string(), "example.com".to_string());
+    /// # let response = http::HTTPResponse {
+    /// #   httptag: Box::new(b"HTTP".to_owned()),
+    /// #   httpversion: (1, 1),
+    /// #   statuscode: http::HttpStatusCodes::Ok,
+    /// #   reason: Box::new(b"OK".to_owned()),
+    /// #   headers: headers,
+    /// #   content: b"".into(),
+    /// #   stream: None,
+    /// #   keep_version: false,
+    /// # };
+    /// let response_bytes = response.to_bytes();
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.head_bytes();
+        // the body ends where Content-Length says it does, anything after
+        // it would be read as the start of the next response
+        out.extend(&self.content);
+        out
+    }
+
+    /// The value of the header called `name` (ignoring case), with the values
+    /// of any repeats of it joined with `, ` (see `HTTPRequest::get_combined`)
+    pub fn get_combined(&self, name: &str) -> Option<String> {
+        get_combined(&self.headers, name)
+    }
+
+    /// The number of the status code, like `404`
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPResponse;
+    /// let response = HTTPResponse::from("Hello, World!");
+    /// assert_eq!(response.status_u16(), 200);
+    /// assert!(response.is_success());
+    /// ```
+    pub fn status_u16(&self) -> u16 {
+        self.statuscode.status_code_number()
+    }
+
+    /// Whether the status code is a `1xx` (see `HttpStatusCodes::is_informational`)
+    pub fn is_informational(&self) -> bool {
+        self.statuscode.is_informational()
+    }
+
+    /// Whether the status code is a `2xx` (see `HttpStatusCodes::is_success`)
+    pub fn is_success(&self) -> bool {
+        self.statuscode.is_success()
+    }
+
+    /// Whether the status code is a `3xx` (see `HttpStatusCodes::is_redirection`)
+    pub fn is_redirection(&self) -> bool {
+        self.statuscode.is_redirection()
+    }
+
+    /// Whether the status code is a `4xx` (see `HttpStatusCodes::is_client_error`)
+    pub fn is_client_error(&self) -> bool {
+        self.statuscode.is_client_error()
+    }
+
+    /// Whether the status code is a `5xx` (see `HttpStatusCodes::is_server_error`)
+    pub fn is_server_error(&self) -> bool {
+        self.statuscode.is_server_error()
+    }
+
+    /// The value of the header `name`, ignoring the case of the name
+    /// (see `http::headers` for the names of common headers)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        get_header(&self.headers, name).map(|value| &**value)
+    }
+
+    /// Iterates over the headers of this response, without moving them
+    pub fn iter_headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .map(|(header, value)| (header.as_str(), value.as_str()))
+    }
+
+    /// Writes this response to `writer`, streaming the body
+    /// if it has a `stream`
+    pub fn write_to(self, writer: &mut impl Write) -> std::io::Result<()> {
+        match self.stream {
+            Some(ref stream) => {
+                writer.write_all(&self.head_bytes())?;
+                stream.write_to(writer)
             }
-            err = stream.read(nl_buf);
+            None => writer.write_all(&self.to_bytes()),
         }
-        let _ = stream.read(nl_buf);
-        let mut headers = HashMap::<String, String>::new();
+    }
 
-        loop {
-            let mut header_key = String::new();
-            let mut header_val = String::new();
-            let cur_char = &mut [0_u8; 1];
-            err = stream.read(cur_char);
-            if err.is_err() {
-                return Err(Error::StreamReadError);
-            }
-            if cur_char[0] == b'\r' {
-                break;
-            }
-            while cur_char[0] != b':' {
-                header_key.push(cur_char[0].into());
-                err = stream.read(cur_char);
-                if err.is_err() {
-                    return Err(Error::StreamReadError);
-                }
-            }
-            let _ = stream.read(cur_char);
-            err = stream.read(cur_char);
-            if err.is_err() {
-                return Err(Error::StreamReadError);
+    /// Reads an HTTP response from `stream` into an HTTPResponse, with the
+    /// header and body size limits of the default `RequestLimits`
+    ///
+    /// A response without a `Content-Length` or chunked body ends when
+    /// the connection does, so its body is read until `stream` runs out.
+    pub fn read_http_response(stream: &mut impl BufRead) -> Result<HTTPResponse, Error> {
+        HTTPResponse::read_response(stream, &RequestLimits::default(), None)
+    }
+
+    /// Reads an HTTP response from `stream` into an HTTPResponse, failing
+    /// with `TooManyHeaders` or `BodyTooLarge` if it has more headers or a
+    /// bigger body than `limits` allows
+    ///
+    /// The `header_timeout` of `limits` isn't used, as a server can take
+    /// a while to start answering; see `read_http_response_deadline`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::{HTTPResponse, RequestLimits};
+    /// # use std::io::Cursor;
+    /// let limits = RequestLimits {
+    ///     max_body_size: 4,
+    ///     ..RequestLimits::default()
+    /// };
+    /// let mut stream = Cursor::new(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+    /// assert!(HTTPResponse::read_http_response_with_limits(&mut stream, &limits).is_err());
+    /// ```
+    pub fn read_http_response_with_limits(
+        stream: &mut impl BufRead,
+        limits: &RequestLimits,
+    ) -> Result<HTTPResponse, Error> {
+        HTTPResponse::read_response(stream, limits, None)
+    }
+
+    /// Reads an HTTP response from `stream` into an HTTPResponse, failing with
+    /// `DeadlineExceeded` if all of it hasn't arrived by `deadline`
+    ///
+    /// The clock is checked between reads, so this can't interrupt a read that
+    /// never returns; set a read timeout on the stream for that.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::core::http::{BufferedStream, HTTPResponse};
+    /// # use std::{net::TcpStream, time::{Duration, Instant}};
+    /// let mut stream = BufferedStream::new(TcpStream::connect("example.com:80").unwrap());
+    /// // ...send a request...
+    /// let deadline = Instant::now() + Duration::from_secs(10);
+    /// let response = HTTPResponse::read_http_response_deadline(&mut stream, deadline);
+    /// ```
+    pub fn read_http_response_deadline(
+        stream: &mut impl BufRead,
+        deadline: Instant,
+    ) -> Result<HTTPResponse, Error> {
+        HTTPResponse::read_response(stream, &RequestLimits::default(), Some(deadline))
+    }
+
+    fn read_response(
+        stream: &mut impl BufRead,
+        limits: &RequestLimits,
+        deadline: Option<Instant>,
+    ) -> Result<HTTPResponse, Error> {
+        let status_line = read_line(stream, deadline).map_err(deadline_error)?;
+        let (http_version, status, reason) = parse_status_line(&status_line)?;
+        let headers = read_headers(stream, limits.max_headers, deadline).map_err(deadline_error)?;
+        let mut content = Vec::<u8>::new();
+        match body_framing(&headers)? {
+            // these never have a body, whatever their headers say
+            _ if status.is_informational()
+                || matches!(
+                    status,
+                    HttpStatusCodes::NoContent | HttpStatusCodes::NotModified
+                ) => {}
+            BodyFraming::Chunked => {
+                (content, _) =
+                    read_chunked_body(stream, limits.max_body_size, limits.max_headers, deadline)?
             }
-            while cur_char[0] != b'\r' {
-                header_val.push(cur_char[0].into());
-                err = stream.read(cur_char);
-                if err.is_err() {
-                    return Err(Error::StreamReadError);
+            BodyFraming::Length(content_length) => {
+                if content_length > limits.max_body_size {
+                    return Err(Error::BodyTooLarge);
                 }
+                read_body_bytes(stream, &mut content, content_length, deadline)?
             }
-            let _ = stream.read(cur_char);
-            headers.insert(header_key, header_val);
-        }
-        // todo finish
-        let mut l_read = 0;
-        if !headers.contains_key("Content-Length") {
-            return Err(Error::InvalidContentLength(
-                InvalidContentLengthReason::MissingContentLength,
-            ));
-        }
-        let string_content_length = headers["Content-Length"].parse();
-        if string_content_length.is_err() {
-            return Err(Error::InvalidContentLength(
-                InvalidContentLengthReason::MalformedContentLength,
-            ));
-        };
-        let content_length = string_content_length.unwrap();
-        let mut content = Vec::<u8>::new();
-        while l_read < content_length {
-            l_read += 1;
-            let tempbuf = &mut [0_u8; 1];
-            err = stream.read(tempbuf);
-            if err.is_err() {
-                return Err(Error::StreamReadError);
+            // like HTTP/1.0 servers, which close the connection after the body
+            BodyFraming::UntilClose | BodyFraming::Missing => {
+                read_until_close(stream, &mut content, limits.max_body_size, deadline)?
             }
-            content.push(tempbuf[0]);
         }
         Ok(HTTPResponse {
-            httptag: Box::new(*http_tag),
+            httptag: Box::new(b"HTTP".to_owned()),
             httpversion: http_version,
             reason: reason.into(),
-            statuscode: status.unwrap(),
+            statuscode: status,
             headers,
             content,
+            stream: None,
+            keep_version: false,
         })
     }
+    /// Sets the HTTP version, status code and reason of this response together,
+    /// and has the server send them exactly like that
+    ///
+    /// Usually the server answers with the HTTP version the request used, but
+    /// a proxy passing on an upstream response can keep its status line as it was.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::{HTTPResponse, HttpStatusCodes};
+    /// let response = HTTPResponse::new().with_status_line(
+    ///     (1, 0),
+    ///     HttpStatusCodes::NonAuthoritativeInformation,
+    ///     Box::new(*b"Non-Authoritative Information"),
+    /// );
+    /// ```
+    pub fn with_status_line(
+        self,
+        httpversion: (i32, i32),
+        statuscode: HttpStatusCodes,
+        reason: Box<[u8]>,
+    ) -> HTTPResponse {
+        HTTPResponse {
+            httpversion,
+            keep_version: true,
+            ..self.with_statuscode(statuscode, reason)
+        }
+    }
+
     /// Changes the status code of this request
     pub fn with_statuscode(self, statuscode: HttpStatusCodes, reason: Box<[u8]>) -> HTTPResponse {
-        let mut returnval = self.clone();
+        let mut returnval = self;
         returnval.statuscode = statuscode;
         returnval.reason = reason;
         returnval
     }
 
     /// Adds a header to this request
+    ///
+    /// Any CRs, LFs or NULs in the header are dropped, so it can't be used to
+    /// add headers of its own. To find out about them instead, use
+    /// `HTTPResponse::try_with_header`.
     pub fn with_header(self, header: String, value: String) -> HTTPResponse {
-        let mut returnval = self.clone();
-        returnval.headers.insert(header, value);
+        let mut returnval = self;
         returnval
+            .headers
+            .insert(without_line_breaks(header), without_line_breaks(value));
+        returnval
+    }
+
+    /// Adds a header to this request, failing with `InvalidHeader` if it
+    /// could be used to inject headers (see `validate_header`)
+    pub fn try_with_header(self, header: String, value: String) -> Result<HTTPResponse, Error> {
+        validate_header(&header, &value)?;
+        Ok(self.with_header(header, value))
+    }
+
+    /// Sets a cookie on the client, failing with `InvalidHeader` if the name
+    /// or value could be used to inject headers or cookie attributes
+    ///
+    /// As headers are kept in a map, a response can only set one cookie.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPResponse;
+    /// let mut response = HTTPResponse::from("Logged in");
+    /// response.set_cookie("session", "abc123").unwrap();
+    /// assert!(response.set_cookie("session", "x; Domain=evil.example").is_err());
+    /// ```
+    pub fn set_cookie(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        // `;` would start an attribute, and `=` in the name would move where the value starts
+        if name.contains(['=', ';']) || value.contains(';') {
+            return Err(Error::InvalidHeader);
+        }
+        let cookie = format!("{}={}", name, value);
+        validate_header(headers::SET_COOKIE, &cookie)?;
+        self.headers.insert(headers::SET_COOKIE.to_string(), cookie);
+        Ok(())
+    }
+
+    /// Sets a cookie on the client, like `HTTPResponse::set_cookie`, failing
+    /// with `InvalidHeader` if the name or value could be used to inject
+    /// headers or cookie attributes
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPResponse;
+    /// let response = HTTPResponse::from("Logged in").with_cookie("session", "abc123")?;
+    /// # Ok::<(), rustedflask::core::http::Error>(())
+    /// ```
+    pub fn with_cookie(self, name: &str, value: &str) -> Result<HTTPResponse, Error> {
+        let mut returnval = self;
+        returnval.set_cookie(name, value)?;
+        Ok(returnval)
+    }
+
+    /// Makes a `302 Found` response sending the client to `location`, failing
+    /// with `InvalidHeader` if it could be used to inject headers
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPResponse;
+    /// let response = HTTPResponse::redirect("/login").unwrap();
+    /// assert_eq!(response.statuscode.status_code_number(), 302);
+    /// ```
+    pub fn redirect(location: &str) -> Result<HTTPResponse, Error> {
+        HTTPResponse::from("")
+            .with_statuscode(HttpStatusCodes::Found, Box::new(b"Found".to_owned()))
+            .try_with_header(headers::LOCATION.to_string(), location.to_string())
+    }
+
+    /// Makes a `302 Found` response sending the client that made `request`
+    /// to `target`, like `HTTPResponse::redirect`
+    ///
+    /// Some older clients only follow absolute URLs, so a relative `target`
+    /// (`/login`, or `next` next to the requested path) is made absolute
+    /// with the scheme and `Host` of `request` (see
+    /// `HTTPRequest::resolve_location`). Absolute URLs are sent as they
+    /// are, and so is everything if the request has no `Host`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::{HTTPRequest, HTTPResponse};
+    /// # use std::collections::HashMap;
+    /// # let request = HTTPRequest {
+    /// #       method: b"GET".to_vec(),
+    /// #       path: b"/account".to_vec(),
+    /// #       raw_path: b"/account".to_vec(),
+    /// #       httptag: Box::new(b"HTTP".to_owned()),
+    /// #       httpversion: (1, 1),
+    /// #       headers: HashMap::from([("Host".to_string(), "example.com".to_string())]),
+    /// #       content: Vec::new(),
+    /// #       request_id: None,
+    /// #       secure: false,
+    /// #       parsed_body: None,
+    /// #       lazy_body: None,
+    /// #       path_params: HashMap::new(),
+    /// # };
+    /// let response = HTTPResponse::redirect_from(&request, "/login").unwrap();
+    /// assert_eq!(response.headers["Location"], "http://example.com/login");
+    /// ```
+    pub fn redirect_from(request: &HTTPRequest, target: &str) -> Result<HTTPResponse, Error> {
+        match request.resolve_location(target) {
+            Some(location) => HTTPResponse::redirect(&location),
+            None => HTTPResponse::redirect(target),
+        }
     }
 
     /// Changes the content of this request
     pub fn with_content(self, content: Vec<u8>) -> HTTPResponse {
-        let mut returnval = self.clone();
+        let mut returnval = self;
         returnval.content = content;
+        returnval.stream = None;
+        remove_header(&mut returnval.headers, headers::CONTENT_LENGTH);
         returnval.headers.insert(
-            "Content-Length".to_string(),
-            returnval.clone().content.len().to_string(),
+            headers::CONTENT_LENGTH.to_string(),
+            returnval.content.len().to_string(),
         );
         returnval
     }
+
+    /// Reads all of `reader` into the content of this request, like a small file
+    ///
+    /// Unlike `HTTPResponse::with_body_reader`, the whole body is in memory
+    /// before anything is sent, so the length doesn't have to be known ahead
+    /// of time, and a failed read is returned here instead of cutting the
+    /// response short.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::core::http::HTTPResponse;
+    /// # use std::fs::File;
+    /// let file = File::open("static/about.txt")?;
+    /// let response = HTTPResponse::new().with_body_from_reader(file)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn with_body_from_reader(self, mut reader: impl Read) -> std::io::Result<HTTPResponse> {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        let mut returnval = self.with_content(content);
+        remove_header(&mut returnval.headers, headers::TRANSFER_ENCODING);
+        Ok(returnval)
+    }
+
+    /// Streams `length` bytes from `reader` as the content of this request,
+    /// without buffering them in memory first
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPResponse;
+    /// # use std::io::Cursor;
+    /// let body = Cursor::new(b"Hello, world!".to_vec());
+    /// let response = HTTPResponse::new().with_body_reader(body, 13);
+    /// ```
+    pub fn with_body_reader(self, reader: impl Read + Send + 'static, length: u64) -> HTTPResponse {
+        let mut returnval = self;
+        returnval.content = Vec::new();
+        returnval.stream = Some(ResponseStream::from_reader(reader, length));
+        remove_header(&mut returnval.headers, headers::TRANSFER_ENCODING);
+        remove_header(&mut returnval.headers, headers::CONTENT_LENGTH);
+        returnval
+            .headers
+            .insert(headers::CONTENT_LENGTH.to_string(), length.to_string());
+        returnval
+    }
+
+    /// Sends each segment `segments` produces as part of the content of this
+    /// request, as soon as it's produced, e.g. a file read in blocks
+    ///
+    /// If `length` is known, the segments must add up to exactly that many bytes,
+    /// and it's sent as the `Content-Length`. Otherwise, the content is sent
+    /// with `Transfer-Encoding: chunked`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPResponse;
+    /// let segments = (0..3).map(|i| format!("segment {}\n", i).into_bytes());
+    /// let response = HTTPResponse::new().with_body_iter(segments, None);
+    /// ```
+    pub fn with_body_iter(
+        self,
+        segments: impl Iterator<Item = Vec<u8>> + Send + 'static,
+        length: Option<u64>,
+    ) -> HTTPResponse {
+        let mut returnval = self;
+        returnval.content = Vec::new();
+        returnval.stream = Some(ResponseStream::from_segments(segments, length));
+        match length {
+            Some(length) => {
+                remove_header(&mut returnval.headers, headers::TRANSFER_ENCODING);
+                remove_header(&mut returnval.headers, headers::CONTENT_LENGTH);
+                returnval
+                    .headers
+                    .insert(headers::CONTENT_LENGTH.to_string(), length.to_string());
+            }
+            None => {
+                remove_header(&mut returnval.headers, headers::CONTENT_LENGTH);
+                remove_header(&mut returnval.headers, headers::TRANSFER_ENCODING);
+                returnval.headers.insert(
+                    headers::TRANSFER_ENCODING.to_string(),
+                    "chunked".to_string(),
+                );
+            }
+        }
+        returnval
+    }
+
+    /// Turns a response that was read with `Transfer-Encoding: chunked`
+    /// into one with a `Content-Length` for its (already decoded) `content`,
+    /// so it can be sent on as it is, e.g. by a proxy
+    ///
+    /// Any other transfer codings are kept, along with responses that
+    /// weren't chunked or that have a `stream`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPResponse;
+    /// # use std::io::Cursor;
+    /// let mut stream = Cursor::new(
+    ///     b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n",
+    /// );
+    /// let response = HTTPResponse::read_http_response(&mut stream)?.into_content_length();
+    /// assert_eq!(response.headers["Content-Length"], "2");
+    /// # Ok::<(), rustedflask::core::http::Error>(())
+    /// ```
+    pub fn into_content_length(mut self) -> HTTPResponse {
+        if self.stream.is_some() || !is_chunked(&self.headers) {
+            return self;
+        }
+        let codings = get_header(&self.headers, headers::TRANSFER_ENCODING)
+            .map(|codings| {
+                let mut codings: Vec<&str> = codings.split(',').map(str::trim).collect();
+                // chunked is always the last one
+                codings.pop();
+                codings.join(", ")
+            })
+            .unwrap_or_default();
+        // the trailers were dropped when the body was read
+        self.headers.retain(|name, _| {
+            ![
+                headers::TRANSFER_ENCODING,
+                headers::CONTENT_LENGTH,
+                headers::TRAILER,
+            ]
+            .iter()
+            .any(|removed| removed.eq_ignore_ascii_case(name))
+        });
+        if !codings.is_empty() {
+            self.headers
+                .insert(headers::TRANSFER_ENCODING.to_string(), codings);
+        }
+        self.headers.insert(
+            headers::CONTENT_LENGTH.to_string(),
+            self.content.len().to_string(),
+        );
+        self
+    }
+
+    /// Makes a response whose body is everything sent down the returned
+    /// channel, sent with `Transfer-Encoding: chunked` as it arrives, for
+    /// things like progress updates or logs from a background thread
+    ///
+    /// The body ends once every `Sender` has been dropped.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPResponse;
+    /// # use std::thread;
+    /// let (response, sender) = HTTPResponse::stream_from_channel();
+    /// thread::spawn(move || {
+    ///     for percent in [0, 50, 100] {
+    ///         let _ = sender.send(format!("{}%\n", percent).into_bytes());
+    ///     }
+    /// });
+    /// ```
+    pub fn stream_from_channel() -> (HTTPResponse, Sender<Vec<u8>>) {
+        let (sender, receiver) = channel();
+        let response = HTTPResponse::new().with_body_iter(receiver.into_iter(), None);
+        (response, sender)
+    }
 }
 
 impl Default for HTTPResponse {
@@ -0,0 +1,163 @@
+//! Reading `multipart/form-data` bodies, which is how forms upload files
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{get_header, headers, parse_content_type, Error, HTTPRequest};
+use crate::internals::header_parameter;
+
+/// One of the fields of a `multipart/form-data` body (see `HTTPRequest::multipart`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultipartPart {
+    /// The name of the form field, from `Content-Disposition`
+    pub name: Option<String>,
+    /// The name the client gave the uploaded file, if this is a file,
+    /// exactly as it was sent (see `MultipartPart::safe_filename`)
+    pub filename: Option<String>,
+    /// The headers of this part, like `Content-Type`
+    pub headers: HashMap<String, String>,
+    /// The contents of the field or file
+    pub content: Vec<u8>,
+}
+
+/// Finds where `needle` first is in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+impl MultipartPart {
+    /// Parses a part, from the line ending after its boundary to just
+    /// before the line ending in front of the next boundary
+    fn parse(part: &[u8]) -> Result<MultipartPart, Error> {
+        // the headers end with a blank line, even if there aren't any
+        let (head, content) = match find(part, b"\r\n\r\n") {
+            Some(head_end) => (&part[..head_end], &part[head_end + 4..]),
+            None => return Err(Error::UnreadableMessageError),
+        };
+        let mut headers = HashMap::new();
+        for line in head.split(|&byte| byte == b'\n') {
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            match line.split_once(':') {
+                Some((header, value)) => {
+                    headers.insert(header.to_string(), value.trim().to_string())
+                }
+                None => return Err(Error::UnreadableMessageError),
+            };
+        }
+        let disposition = get_header(&headers, headers::CONTENT_DISPOSITION).cloned();
+        Ok(MultipartPart {
+            name: disposition
+                .as_deref()
+                .and_then(|disposition| header_parameter(disposition, "name")),
+            filename: disposition
+                .as_deref()
+                .and_then(|disposition| header_parameter(disposition, "filename")),
+            headers,
+            content: content.to_vec(),
+        })
+    }
+
+    /// The filename of this part, without any folders in it, so it can't be
+    /// used to write outside of the folder it's saved to
+    ///
+    /// `None` if this isn't a file, or nothing is left of the name.
+    pub fn safe_filename(&self) -> Option<String> {
+        let filename = self.filename.as_deref()?;
+        // clients on Windows can send paths with backslashes
+        let filename = filename.rsplit(['/', '\\']).next().unwrap_or("");
+        let filename: String = filename.chars().filter(|c| !c.is_control()).collect();
+        match filename.trim() {
+            "" | "." | ".." => None,
+            filename => Some(filename.to_string()),
+        }
+    }
+
+    /// Writes this file into `folder`, under its `safe_filename`,
+    /// and returns the path it was written to
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::{core::http::HTTPRequest, flask::App};
+    /// let mut app = App::new("name".to_string());
+    /// app.route_with_allowed_methods(
+    ///     "/upload",
+    ///     |request: HTTPRequest| {
+    ///         for part in request.multipart().unwrap_or_default() {
+    ///             if part.filename.is_some() {
+    ///                 part.save_to("./uploads").unwrap();
+    ///             }
+    ///         }
+    ///         "Uploaded!".into()
+    ///     },
+    ///     vec!["POST".to_string()],
+    /// );
+    /// ```
+    pub fn save_to(&self, folder: impl AsRef<Path>) -> io::Result<PathBuf> {
+        let filename = match self.safe_filename() {
+            Some(filename) => filename,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The part doesn't have a usable filename",
+                ))
+            }
+        };
+        let path = folder.as_ref().join(filename);
+        fs::write(&path, &self.content)?;
+        Ok(path)
+    }
+}
+
+impl HTTPRequest {
+    /// Splits a `multipart/form-data` body into its parts
+    ///
+    /// Fails with `UnreadableMessageError` if the request isn't
+    /// `multipart/form-data` or the body doesn't follow the format.
+    pub fn multipart(&self) -> Result<Vec<MultipartPart>, Error> {
+        let content_type = match get_header(&self.headers, headers::CONTENT_TYPE) {
+            Some(content_type) => content_type,
+            None => return Err(Error::UnreadableMessageError),
+        };
+        let (media_type, mut parameters) = parse_content_type(content_type);
+        let boundary = match parameters.remove("boundary") {
+            Some(boundary) if media_type == "multipart/form-data" && !boundary.is_empty() => {
+                boundary
+            }
+            _ => return Err(Error::UnreadableMessageError),
+        };
+        let delimiter = format!("--{}", boundary).into_bytes();
+        // every part after the first starts on a new line
+        let next_delimiter = format!("\r\n--{}", boundary).into_bytes();
+
+        let mut rest = match find(&self.content, &delimiter) {
+            Some(start) => &self.content[start + delimiter.len()..],
+            None => return Err(Error::UnreadableMessageError),
+        };
+        let mut parts = Vec::new();
+        // the last boundary has `--` after it
+        while !rest.starts_with(b"--") {
+            let part_end = match find(rest, &next_delimiter) {
+                Some(part_end) => part_end,
+                None => return Err(Error::UnreadableMessageError),
+            };
+            // anything after the boundary on its line is padding
+            let part = &rest[..part_end];
+            let part = match find(part, b"\r\n") {
+                Some(line_end) => &part[line_end..],
+                None => return Err(Error::UnreadableMessageError),
+            };
+            parts.push(MultipartPart::parse(part)?);
+            rest = &rest[part_end + next_delimiter.len()..];
+        }
+        Ok(parts)
+    }
+}
@@ -0,0 +1,250 @@
+//! Reading the parameters of a request from its query string and its
+//! body, whether it's form-encoded or JSON
+
+use std::collections::HashMap;
+
+use super::{get_header, headers, HTTPRequest};
+use crate::internals::media_type;
+
+/// Decodes a form-encoded name or value, where `+` is a space
+///
+/// Escapes that aren't followed by two hex digits are kept as-is.
+fn form_decode(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let hex_value = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+    while position < bytes.len() {
+        match bytes[position] {
+            b'+' => decoded.push(b' '),
+            b'%' if position + 2 < bytes.len() => {
+                match (
+                    hex_value(bytes[position + 1]),
+                    hex_value(bytes[position + 2]),
+                ) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high << 4 | low);
+                        position += 3;
+                        continue;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        position += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Adds the `name=value` pairs of a query string or form body to `params`,
+/// keeping the first value of a name that's already there
+fn add_form_params(encoded: &str, params: &mut HashMap<String, String>) {
+    for pair in encoded.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        params
+            .entry(form_decode(name))
+            .or_insert_with(|| form_decode(value));
+    }
+}
+
+/// Reads a JSON document, one value at a time
+struct JsonReader<'j> {
+    json: &'j [u8],
+    position: usize,
+}
+
+impl JsonReader<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .json
+            .get(self.position)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+
+    /// Skips over `byte` (after any whitespace), failing if it's not next
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        self.skip_whitespace();
+        if self.json.get(self.position) != Some(&byte) {
+            return None;
+        }
+        self.position += 1;
+        Some(())
+    }
+
+    /// Reads four hex digits of a `\u` escape
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.json.get(self.position..self.position + 4)?;
+        self.position += 4;
+        u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+    }
+
+    /// Reads a string, undoing its escapes
+    fn string(&mut self) -> Option<String> {
+        self.expect(b'"')?;
+        let mut string = Vec::new();
+        loop {
+            let byte = *self.json.get(self.position)?;
+            self.position += 1;
+            match byte {
+                b'"' => return String::from_utf8(string).ok(),
+                b'\\' => {
+                    let escaped = *self.json.get(self.position)?;
+                    self.position += 1;
+                    let character = match escaped {
+                        b'"' | b'\\' | b'/' => escaped as char,
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // characters outside of the BMP are two escapes
+                            if (0xD800..0xDC00).contains(&code)
+                                && self.json.get(self.position..self.position + 2) == Some(b"\\u")
+                            {
+                                self.position += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000
+                                    + ((code - 0xD800) << 10)
+                                    + (low.checked_sub(0xDC00)?);
+                            }
+                            char::from_u32(code)?
+                        }
+                        _ => return None,
+                    };
+                    let mut buffer = [0; 4];
+                    string.extend(character.encode_utf8(&mut buffer).as_bytes());
+                }
+                byte => string.push(byte),
+            }
+        }
+    }
+
+    /// Reads a value, as text if it's a string, number or boolean, and
+    /// `None` inside the `Some` if it's `null`, an object or an array
+    fn value(&mut self) -> Option<Option<String>> {
+        self.skip_whitespace();
+        match *self.json.get(self.position)? {
+            b'"' => self.string().map(Some),
+            b'{' | b'[' => self.skip_nested().map(|_| None),
+            _ => {
+                let start = self.position;
+                while self
+                    .json
+                    .get(self.position)
+                    .is_some_and(|byte| byte.is_ascii_alphanumeric() || b"+-.".contains(byte))
+                {
+                    self.position += 1;
+                }
+                match std::str::from_utf8(&self.json[start..self.position]).ok()? {
+                    "null" => Some(None),
+                    "" => None,
+                    literal => Some(Some(literal.to_string())),
+                }
+            }
+        }
+    }
+
+    /// Skips over an object or array, and everything in it
+    fn skip_nested(&mut self) -> Option<()> {
+        let mut depth = 0_usize;
+        loop {
+            self.skip_whitespace();
+            match *self.json.get(self.position)? {
+                b'"' => {
+                    self.string()?;
+                    continue;
+                }
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => depth -= 1,
+                _ => {}
+            }
+            self.position += 1;
+            if depth == 0 {
+                return Some(());
+            }
+        }
+    }
+
+    /// Reads an object, with the values that aren't nested or `null`
+    fn flat_object(&mut self) -> Option<Vec<(String, String)>> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.json.get(self.position) == Some(&b'}') {
+            self.position += 1;
+            return Some(members);
+        }
+        loop {
+            let name = self.string()?;
+            self.expect(b':')?;
+            if let Some(value) = self.value()? {
+                members.push((name, value));
+            }
+            self.skip_whitespace();
+            match *self.json.get(self.position)? {
+                b',' => self.position += 1,
+                b'}' => {
+                    self.position += 1;
+                    return Some(members);
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+impl HTTPRequest {
+    /// The parameters of this request from its query string and its body,
+    /// like Flask's `request.values`
+    ///
+    /// Bodies are read if they're form-encoded
+    /// (`application/x-www-form-urlencoded`) or a JSON object, where only
+    /// the strings, numbers and booleans at the top level are kept (as
+    /// text). When a name is in both, the query string's value is kept,
+    /// and when a name is repeated, its first value is. A lazy body has
+    /// to be read first (see `HTTPRequest::read_body`).
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::core::http::HTTPRequest;
+    /// let mut request = HTTPRequest::post("/search?page=2", "q=hello+world");
+    /// request.headers.insert(
+    ///     "Content-Type".to_string(),
+    ///     "application/x-www-form-urlencoded".to_string(),
+    /// );
+    /// let params = request.params();
+    /// assert_eq!(params["q"], "hello world");
+    /// assert_eq!(params["page"], "2");
+    /// ```
+    pub fn params(&self) -> HashMap<String, String> {
+        let mut params = HashMap::new();
+        if let Some(query_start) = self.path.iter().position(|&byte| byte == b'?') {
+            add_form_params(
+                &String::from_utf8_lossy(&self.path[query_start + 1..]),
+                &mut params,
+            );
+        }
+        let content_type = match get_header(&self.headers, headers::CONTENT_TYPE) {
+            Some(content_type) => media_type(content_type),
+            None => return params,
+        };
+        if content_type == "application/x-www-form-urlencoded" {
+            add_form_params(&String::from_utf8_lossy(&self.content), &mut params);
+        } else if content_type == "application/json" || content_type.ends_with("+json") {
+            let mut reader = JsonReader {
+                json: &self.content,
+                position: 0,
+            };
+            for (name, value) in reader.flat_object().unwrap_or_default() {
+                params.entry(name).or_insert(value);
+            }
+        }
+        params
+    }
+}
@@ -0,0 +1,65 @@
+//! Request bodies that have been parsed into a value, by the parser the app
+//! registered for their content type
+
+use std::{
+    any::Any,
+    fmt::{self, Debug},
+    sync::Arc,
+};
+
+use super::HTTPRequest;
+
+/// The value a request body was parsed into (see `HTTPRequest::parsed_body`)
+///
+/// It can be anything a body parser makes, so it's gotten back out by its type.
+#[derive(Clone)]
+pub struct ParsedBody {
+    content_type: String,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+/// Parsed bodies can't be compared without knowing their types, so
+/// they're only equal to their clones
+impl PartialEq for ParsedBody {
+    fn eq(&self, other: &ParsedBody) -> bool {
+        Arc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl Eq for ParsedBody {}
+
+impl Debug for ParsedBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParsedBody")
+            .field("content_type", &self.content_type)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ParsedBody {
+    /// Wraps the value a body of `content_type` was parsed into
+    pub fn new(content_type: &str, value: impl Any + Send + Sync) -> ParsedBody {
+        ParsedBody {
+            content_type: content_type.to_string(),
+            value: Arc::new(value),
+        }
+    }
+
+    /// The content type of the body, which picked the parser
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// The value, if it's a `T`
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.value.downcast_ref()
+    }
+}
+
+impl HTTPRequest {
+    /// The value the body of this request was parsed into, if the app has a
+    /// body parser for its content type (see `App::body_parser`) and it made a `T`
+    pub fn parsed_body<T: Any>(&self) -> Option<&T> {
+        self.parsed_body.as_ref()?.downcast_ref()
+    }
+}
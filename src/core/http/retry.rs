@@ -0,0 +1,66 @@
+//! Retrying requests that failed because of the network
+
+use std::time::Duration;
+
+use super::Error;
+
+/// When and how often `HTTPRequest::send_to_with_retry` tries a request again
+///
+/// Only requests with idempotent methods (`GET`, `HEAD`, `PUT`, `DELETE`,
+/// `OPTIONS` and `TRACE`) are retried, unless `retry_non_idempotent` is set,
+/// since sending something like a `POST` twice could do something twice.
+///
+/// # Examples
+/// ```
+/// # use rustedflask::core::http::RetryPolicy;
+/// # use std::time::Duration;
+/// let policy = RetryPolicy {
+///     retries: 5,
+///     backoff: Duration::from_millis(50),
+///     ..RetryPolicy::default()
+/// };
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// How many times to try again after the first attempt fails
+    pub retries: u32,
+    /// How long to wait before the first retry, which doubles after every retry
+    pub backoff: Duration,
+    /// Whether to retry requests with methods that aren't idempotent, like `POST`
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    /// Retries idempotent requests 3 times, waiting 100ms, 200ms and then 400ms
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a request with the method `method` can be retried
+    pub(crate) fn allows_method(&self, method: &[u8]) -> bool {
+        self.retry_non_idempotent
+            || matches!(
+                method,
+                b"GET" | b"HEAD" | b"PUT" | b"DELETE" | b"OPTIONS" | b"TRACE"
+            )
+    }
+
+    /// How long to wait before retry number `retry` (starting at 0)
+    pub(crate) fn delay(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(2_u32.saturating_pow(retry))
+    }
+}
+
+/// Whether `error` could go away by trying again, like a refused or reset connection
+pub(crate) fn is_transient(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::CouldntConnect | Error::CouldntSend | Error::StreamReadError
+    )
+}
@@ -0,0 +1,168 @@
+//! Response bodies that are sent while they're being produced
+
+use std::{
+    fmt::Debug,
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+};
+
+/// How much of a reader is copied at once when it's sent
+///
+/// `io::copy` only copies 8 KiB at a time, which means a write call for
+/// every 8 KiB of a large file.
+const COPY_BUFFER_SIZE: usize = 256 * 1024;
+
+/// Where a `ResponseStream` gets its body from
+enum StreamSource {
+    /// A reader, e.g. a file or another response, of a known length
+    Reader(Box<dyn Read + Send>),
+    /// Segments that are produced one by one
+    Segments(Box<dyn Iterator<Item = Vec<u8>> + Send>),
+}
+
+/// A response body that's sent as it's produced, instead of being
+/// buffered into `HTTPResponse.content`
+///
+/// Streams with a known length are sent as-is with a `Content-Length`,
+/// ones without are sent with `Transfer-Encoding: chunked`.
+///
+/// Cloning a `ResponseStream` doesn't copy the body, both clones share
+/// the same source, so the body can only be sent once.
+#[derive(Clone)]
+pub struct ResponseStream {
+    source: Arc<Mutex<Option<StreamSource>>>,
+    length: Option<u64>,
+}
+
+/// Streams can't be compared by their bodies without sending them, so
+/// they're only equal to their clones
+impl PartialEq for ResponseStream {
+    fn eq(&self, other: &ResponseStream) -> bool {
+        Arc::ptr_eq(&self.source, &other.source)
+    }
+}
+
+impl Eq for ResponseStream {}
+
+impl ResponseStream {
+    fn new(source: StreamSource, length: Option<u64>) -> ResponseStream {
+        ResponseStream {
+            source: Arc::new(Mutex::new(Some(source))),
+            length,
+        }
+    }
+
+    /// Makes a stream that sends exactly `length` bytes from `reader`
+    pub fn from_reader(reader: impl Read + Send + 'static, length: u64) -> ResponseStream {
+        ResponseStream::new(StreamSource::Reader(Box::new(reader)), Some(length))
+    }
+
+    /// Makes a stream that sends each segment `segments` produces,
+    /// as soon as it's produced
+    ///
+    /// If `length` is given, the segments must add up to exactly that many bytes.
+    pub fn from_segments(
+        segments: impl Iterator<Item = Vec<u8>> + Send + 'static,
+        length: Option<u64>,
+    ) -> ResponseStream {
+        ResponseStream::new(StreamSource::Segments(Box::new(segments)), length)
+    }
+
+    /// How many bytes this stream sends, if it's known ahead of time
+    pub fn length(&self) -> Option<u64> {
+        self.length
+    }
+
+    /// Whether this stream is sent with `Transfer-Encoding: chunked`
+    pub fn is_chunked(&self) -> bool {
+        self.length.is_none()
+    }
+
+    /// Copies the body into `writer`, chunking it if the length isn't known
+    ///
+    /// Fails if the body was already sent, or if the source doesn't produce
+    /// exactly the length it was given (as the `Content-Length` would be wrong)
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let source = match self.source.lock() {
+            Ok(mut source) => source.take(),
+            Err(_) => return Err(io::Error::other("response stream was poisoned")),
+        };
+        let sent = match source {
+            None => return Err(io::Error::other("response stream was already sent")),
+            // readers always have a length
+            Some(StreamSource::Reader(reader)) => {
+                copy(&mut reader.take(self.length.unwrap_or(0)), writer)?
+            }
+            Some(StreamSource::Segments(segments)) => {
+                let mut sent = 0;
+                for segment in segments {
+                    if segment.is_empty() {
+                        // an empty chunk would end the body early
+                        continue;
+                    }
+                    match self.length {
+                        Some(length) => {
+                            if sent + segment.len() as u64 > length {
+                                return Err(io::Error::other(
+                                    "response stream is longer than its length",
+                                ));
+                            }
+                            writer.write_all(&segment)?;
+                        }
+                        None => write_chunk(writer, &segment)?,
+                    }
+                    sent += segment.len() as u64;
+                }
+                sent
+            }
+        };
+        match self.length {
+            Some(length) if sent < length => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "response stream ended before its length",
+            )),
+            Some(_) => Ok(()),
+            // the last, empty chunk
+            None => writer.write_all(b"0\r\n\r\n"),
+        }
+    }
+}
+
+/// Copies everything in `reader` into `writer`, `COPY_BUFFER_SIZE` bytes
+/// at a time, returning how many bytes were copied
+fn copy(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<u64> {
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    let mut copied = 0;
+    loop {
+        // fill as much of the buffer as possible, so each write is a big one
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match reader.read(&mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(why) if why.kind() == io::ErrorKind::Interrupted => continue,
+                Err(why) => return Err(why),
+            }
+        }
+        if filled == 0 {
+            return Ok(copied);
+        }
+        writer.write_all(&buffer[..filled])?;
+        copied += filled as u64;
+    }
+}
+
+/// Writes `data` as a single chunk of a chunked body
+fn write_chunk(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    writer.write_all(format!("{:x}\r\n", data.len()).as_bytes())?;
+    writer.write_all(data)?;
+    writer.write_all(b"\r\n")
+}
+
+impl Debug for ResponseStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseStream")
+            .field("length", &self.length)
+            .finish_non_exhaustive()
+    }
+}
@@ -1,6 +1,6 @@
 //! other things
 
-use std::io::Read;
+use std::io::{BufRead, Read, Write};
 
 /// A Vec that can be read from
 pub struct ReadableVec<'a, T> {
@@ -26,3 +26,77 @@ where
         Ok(buf.len())
     }
 }
+
+impl BufRead for ReadableVec<'_, u8> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        Ok(self.vector)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.vector.drain(..amount);
+    }
+}
+
+/// A stream that keeps a copy of everything read from and written to it,
+/// for checking exactly what was sent and received
+///
+/// # Examples
+/// ```
+/// # use rustedflask::core::misc::RecordingStream;
+/// # use std::io::{Cursor, Read};
+/// let mut stream = RecordingStream::new(Cursor::new(b"hello".to_vec()));
+/// let mut buf = [0_u8; 5];
+/// stream.read_exact(&mut buf).unwrap();
+/// assert_eq!(stream.read_bytes(), b"hello");
+/// ```
+pub struct RecordingStream<S> {
+    stream: S,
+    read: Vec<u8>,
+    written: Vec<u8>,
+}
+
+impl<S> RecordingStream<S> {
+    /// Starts recording `stream`
+    pub fn new(stream: S) -> RecordingStream<S> {
+        RecordingStream {
+            stream,
+            read: Vec::new(),
+            written: Vec::new(),
+        }
+    }
+
+    /// Everything that has been read from the stream so far
+    pub fn read_bytes(&self) -> &[u8] {
+        &self.read
+    }
+
+    /// Everything that has been written to the stream so far
+    pub fn written_bytes(&self) -> &[u8] {
+        &self.written
+    }
+
+    /// Stops recording, giving back the stream
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read> Read for RecordingStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.stream.read(buf)?;
+        self.read.extend(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for RecordingStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.stream.write(buf)?;
+        self.written.extend(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
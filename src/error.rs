@@ -0,0 +1,74 @@
+//! One error type for everything in the crate
+
+use std::fmt::{self, Display};
+
+use crate::core::http;
+use crate::flask::CantBind;
+#[cfg(feature = "jinja")]
+use crate::jinja::JinjaError;
+
+/// Any error from this crate, so an app using several parts of it can
+/// use `?` on all of them
+///
+/// The errors of each module are still returned by its own functions, for
+/// matching on exactly what went wrong; they're converted into this with `?`.
+///
+/// # Examples
+/// ```no_run
+/// # use rustedflask::flask::App;
+/// fn main() -> Result<(), rustedflask::Error> {
+///     let mut app = App::new("name".to_string());
+///     app.route("/", |_| "Hello, World!".into());
+///     app.run_single_threaded("127.0.0.1:8080")?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub enum RustedFlaskError {
+    /// An HTTP message couldn't be read or sent
+    Http(http::Error),
+    /// A template couldn't be rendered
+    #[cfg(feature = "jinja")]
+    Jinja(JinjaError),
+    /// The server couldn't bind to its address
+    CantBind,
+    /// A setting (like an environment variable) had a value that can't be
+    /// used, which is explained
+    InvalidConfig(String),
+}
+
+/// A shorter name for `RustedFlaskError`
+pub type Error = RustedFlaskError;
+
+impl Display for RustedFlaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RustedFlaskError::Http(error) => write!(f, "HTTP error: {:?}", error),
+            #[cfg(feature = "jinja")]
+            RustedFlaskError::Jinja(error) => write!(f, "Jinja error: {:?}", error),
+            RustedFlaskError::CantBind => write!(f, "couldn't bind to the address"),
+            RustedFlaskError::InvalidConfig(why) => write!(f, "invalid configuration: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for RustedFlaskError {}
+
+impl From<http::Error> for RustedFlaskError {
+    fn from(error: http::Error) -> Self {
+        RustedFlaskError::Http(error)
+    }
+}
+
+#[cfg(feature = "jinja")]
+impl From<JinjaError> for RustedFlaskError {
+    fn from(error: JinjaError) -> Self {
+        RustedFlaskError::Jinja(error)
+    }
+}
+
+impl From<CantBind> for RustedFlaskError {
+    fn from(_: CantBind) -> Self {
+        RustedFlaskError::CantBind
+    }
+}
@@ -0,0 +1,46 @@
+//! Backing off when accepting connections keeps failing, like when the
+//! process runs out of file descriptors
+
+use std::{io, time::Duration};
+
+/// How long the accept loop waits after the first failure in a row
+const MIN_BACKOFF: Duration = Duration::from_millis(10);
+
+/// The longest the accept loop waits between failures
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Whether an accept error only affected one connection, so accepting
+/// again can go ahead straight away
+fn is_retryable(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+    )
+}
+
+/// How long to wait before accepting again, which doubles with each
+/// failure in a row (up to `MAX_BACKOFF`), so the loop doesn't spin while
+/// nothing can be accepted
+#[derive(Debug, Default)]
+pub(crate) struct AcceptBackoff {
+    failures: u32,
+}
+
+impl AcceptBackoff {
+    /// Starts over after a connection was accepted
+    pub(crate) fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+    /// How long to wait after accepting failed with `error`
+    pub(crate) fn failed(&mut self, error: &io::Error) -> Duration {
+        if is_retryable(error) {
+            return Duration::ZERO;
+        }
+        let backoff = MIN_BACKOFF.saturating_mul(1 << self.failures.min(16));
+        self.failures = self.failures.saturating_add(1);
+        backoff.min(MAX_BACKOFF)
+    }
+}
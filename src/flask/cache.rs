@@ -0,0 +1,86 @@
+//! Keeping copies of responses to `GET` requests for a while, so the
+//! handler doesn't have to run again for each one
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::core::http::{get_header, headers, HTTPRequest, HTTPResponse};
+
+/// Whether a `Cache-Control` header forbids storing the message
+fn is_no_store(headers: &HashMap<String, String>) -> bool {
+    match get_header(headers, headers::CACHE_CONTROL) {
+        Some(cache_control) => cache_control
+            .split(',')
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-store")),
+        None => false,
+    }
+}
+
+/// What responses are cached by: the method and path of the request
+pub(crate) type CacheKey = (Vec<u8>, Vec<u8>);
+
+/// The responses that were cached, by method and path
+pub(crate) struct ResponseCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<CacheKey, (HTTPResponse, Instant)>>,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(ttl: Duration) -> ResponseCache {
+        ResponseCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The key the response to `request` is cached under, if it can be
+    /// answered from, or its response stored in, the cache
+    pub(crate) fn key_for(request: &HTTPRequest) -> Option<CacheKey> {
+        if request.method == b"GET" && !is_no_store(&request.headers) {
+            Some((request.method.clone(), request.path.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// The response cached under `key`, if there's one that hasn't expired
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<HTTPResponse> {
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match entries.get(key) {
+            Some((response, expiry)) if *expiry > Instant::now() => Some(response.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores `response` under `key`, if it can be cached
+    ///
+    /// Only `200 OK`s without a `Set-Cookie` or `Cache-Control: no-store`
+    /// are, and only if the body can be sent more than once (isn't streamed).
+    pub(crate) fn store(&self, key: CacheKey, response: &HTTPResponse) {
+        if response.statuscode.status_code_number() != 200
+            || response.stream.is_some()
+            || get_header(&response.headers, headers::SET_COOKIE).is_some()
+            || is_no_store(&response.headers)
+        {
+            return;
+        }
+        let now = Instant::now();
+        let mut entries = self
+            .entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // don't let entries for paths that are never asked for again pile up
+        entries.retain(|_, (_, expiry)| *expiry > now);
+        entries.insert(key, (response.clone(), now + self.ttl));
+    }
+}
@@ -0,0 +1,187 @@
+//! Letting pages on other origins call the app from the browser (CORS)
+
+use crate::core::http::{get_header, headers, HTTPRequest, HTTPResponse, HttpStatusCodes};
+
+/// Which other origins can call the app from the browser, and how
+/// (see `App::cors`)
+///
+/// Requests from origins that aren't allowed get responses without any
+/// CORS headers, which makes the browser hide them from the page.
+///
+/// # Examples
+/// ```
+/// # use rustedflask::flask::{App, Cors};
+/// let mut app = App::new("name".to_string());
+/// app.cors(
+///     Cors::new()
+///         .allow_origin("https://example.com")
+///         .allow_origin("https://*.example.com")
+///         .allow_credentials(true),
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: Vec<String>,
+    credentials: bool,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors::new()
+    }
+}
+
+/// Whether `origin` matches `pattern`, which is either an exact origin, `*`
+/// for any origin, or has a `*` standing in for any subdomains, like
+/// `https://*.example.com`
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let (prefix, suffix) = match pattern.split_once('*') {
+        Some(parts) => parts,
+        None => return pattern.eq_ignore_ascii_case(origin),
+    };
+    if origin.len() < prefix.len() + suffix.len() {
+        return false;
+    }
+    let (start, rest) = origin.split_at(prefix.len());
+    let (subdomains, end) = rest.split_at(rest.len() - suffix.len());
+    start.eq_ignore_ascii_case(prefix)
+        && end.eq_ignore_ascii_case(suffix)
+        && !subdomains.is_empty()
+        // so the `*` can't swallow a port, path or another host
+        && subdomains
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'.')
+}
+
+impl Cors {
+    /// Makes a config that doesn't allow any origins yet, and allows
+    /// `GET`, `HEAD` and `POST` requests once it does
+    pub fn new() -> Cors {
+        Cors {
+            origins: Vec::new(),
+            methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+            credentials: false,
+        }
+    }
+
+    /// Allows requests from `origin`, like `https://example.com`
+    ///
+    /// A `*` matches any subdomains (`https://*.example.com` allows
+    /// `https://api.example.com`, but not `https://example.com`), and `*`
+    /// on its own allows every origin.
+    pub fn allow_origin(self, origin: &str) -> Cors {
+        let mut returnval = self;
+        returnval.origins.push(origin.to_string());
+        returnval
+    }
+
+    /// Sets the methods pages on allowed origins can use
+    pub fn allow_methods(self, methods: Vec<String>) -> Cors {
+        Cors { methods, ..self }
+    }
+
+    /// Lets pages on allowed origins send cookies with their requests
+    /// and read the responses to them
+    pub fn allow_credentials(self, credentials: bool) -> Cors {
+        Cors {
+            credentials,
+            ..self
+        }
+    }
+
+    /// The `Origin` of `request`, if it's one that's allowed
+    pub(crate) fn allowed_origin(&self, request: &HTTPRequest) -> Option<String> {
+        let origin = get_header(&request.headers, headers::ORIGIN)?;
+        if self
+            .origins
+            .iter()
+            .any(|pattern| origin_matches(pattern, origin))
+        {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Whether `request` is the browser asking whether it can make a
+    /// request, rather than the request itself
+    pub(crate) fn is_preflight(request: &HTTPRequest) -> bool {
+        request.method == b"OPTIONS"
+            && get_header(&request.headers, headers::ACCESS_CONTROL_REQUEST_METHOD).is_some()
+    }
+
+    /// The answer to a preflight `request` from the allowed `origin`
+    pub(crate) fn preflight_response(&self, request: &HTTPRequest, origin: &str) -> HTTPResponse {
+        let mut response = HTTPResponse::new()
+            .with_statuscode(
+                HttpStatusCodes::NoContent,
+                Box::new(b"No Content".to_owned()),
+            )
+            .with_header(
+                headers::ACCESS_CONTROL_ALLOW_METHODS.to_string(),
+                self.methods.join(", "),
+            );
+        response.headers.remove(headers::CONTENT_LENGTH);
+        if let Some(requested) =
+            get_header(&request.headers, headers::ACCESS_CONTROL_REQUEST_HEADERS)
+        {
+            response.headers.insert(
+                headers::ACCESS_CONTROL_ALLOW_HEADERS.to_string(),
+                requested.clone(),
+            );
+        }
+        self.add_headers(&mut response, origin);
+        response
+    }
+
+    /// Adds the headers that let the page on `origin` read `response`
+    pub(crate) fn add_headers(&self, response: &mut HTTPResponse, origin: &str) {
+        // `*` can't be used with credentials, so the origin is sent back instead
+        let allow_origin = if !self.credentials && self.origins.iter().any(|pattern| pattern == "*")
+        {
+            "*".to_string()
+        } else {
+            add_vary(response, "Origin");
+            origin.to_string()
+        };
+        response.headers.insert(
+            headers::ACCESS_CONTROL_ALLOW_ORIGIN.to_string(),
+            allow_origin,
+        );
+        if self.credentials {
+            response.headers.insert(
+                headers::ACCESS_CONTROL_ALLOW_CREDENTIALS.to_string(),
+                "true".to_string(),
+            );
+        }
+    }
+}
+
+/// Adds `header` to the `Vary` of `response`, keeping whatever it already
+/// varies on (like `Accept-Encoding` for compressed static files)
+fn add_vary(response: &mut HTTPResponse, header: &str) {
+    let existing = response
+        .headers
+        .iter_mut()
+        .find(|(name, _)| name.eq_ignore_ascii_case(headers::VARY));
+    match existing {
+        Some((_, value))
+            if value
+                .split(',')
+                .any(|listed| listed.trim().eq_ignore_ascii_case(header)) => {}
+        Some((_, value)) if !value.trim().is_empty() => {
+            value.push_str(", ");
+            value.push_str(header);
+        }
+        Some((_, value)) => *value = header.to_string(),
+        None => {
+            response
+                .headers
+                .insert(headers::VARY.to_string(), header.to_string());
+        }
+    }
+}
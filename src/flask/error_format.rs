@@ -0,0 +1,100 @@
+//! Writing the bodies of the error responses the framework makes itself
+
+/// What the bodies of the error responses the framework makes itself (like
+/// 404s for paths without a route) look like (see `App::error_format`)
+///
+/// Error templates (see `App::error_template`) are used over this.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Plain text, like `404 Not Found`
+    #[default]
+    Text,
+    /// A small HTML page, with the message under the status
+    Html,
+    /// A JSON object, like `{"status":404,"error":"Not Found"}`, for APIs
+    Json,
+}
+
+/// Escapes `text` so it can go in HTML as text
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            character => out.push(character),
+        }
+    }
+    out
+}
+
+/// Writes `text` as a JSON string, quotes included
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for character in text.chars() {
+        match character {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            character if character.is_control() => {
+                out.push_str(&format!("\\u{:04x}", u32::from(character)))
+            }
+            character => out.push(character),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl ErrorFormat {
+    /// The body of an error response in this format, along with its
+    /// `Content-Type`, if it should have one
+    ///
+    /// `details` are only passed in debug mode.
+    pub(crate) fn body(
+        self,
+        code: u16,
+        reason: &str,
+        message: &str,
+        details: Option<&str>,
+    ) -> (String, Option<&'static str>) {
+        match self {
+            ErrorFormat::Text => {
+                let mut content = format!("{} {}", code, reason);
+                if let Some(details) = details {
+                    content.push_str("\n\n");
+                    content.push_str(details);
+                }
+                (content, None)
+            }
+            ErrorFormat::Html => {
+                let mut content = format!(
+                    "<!doctype html>\n<html lang=en>\n<title>{code} {reason}</title>\n\
+                     <h1>{reason}</h1>\n<p>{message}</p>\n",
+                    code = code,
+                    reason = escape_html(reason),
+                    message = escape_html(message),
+                );
+                if let Some(details) = details {
+                    content.push_str(&format!("<pre>{}</pre>\n", escape_html(details)));
+                }
+                (content, Some("text/html; charset=utf-8"))
+            }
+            ErrorFormat::Json => {
+                let mut content =
+                    format!("{{\"status\":{},\"error\":{}", code, json_string(reason));
+                if let Some(details) = details {
+                    content.push_str(&format!(",\"details\":{}", json_string(details)));
+                }
+                content.push('}');
+                (content, Some("application/json"))
+            }
+        }
+    }
+}
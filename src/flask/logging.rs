@@ -0,0 +1,46 @@
+//! Where the server sends its diagnostics
+
+/// Receives the diagnostics the server logs, like access log lines
+/// and connection errors
+///
+/// # Examples
+/// ```
+/// # use rustedflask::flask::{App, Logger};
+/// struct QuietLogger;
+///
+/// impl Logger for QuietLogger {
+///     fn info(&self, _message: &str) {}
+///     fn warn(&self, _message: &str) {}
+///     fn error(&self, message: &str) {
+///         eprintln!("{}", message);
+///     }
+/// }
+///
+/// let mut app = App::new("name".to_string());
+/// app.set_logger(QuietLogger);
+/// ```
+pub trait Logger: Send + Sync {
+    /// Logs something that's expected to happen, like a request being served
+    fn info(&self, message: &str);
+    /// Logs something that went wrong because of a client, like an unreadable request
+    fn warn(&self, message: &str);
+    /// Logs something that went wrong on the server's end
+    fn error(&self, message: &str);
+}
+
+/// The default `Logger`, which prints everything to stderr
+pub struct StderrLogger;
+
+impl Logger for StderrLogger {
+    fn info(&self, message: &str) {
+        eprintln!("[INFO] {}", message);
+    }
+
+    fn warn(&self, message: &str) {
+        eprintln!("[WARN] {}", message);
+    }
+
+    fn error(&self, message: &str) {
+        eprintln!("[ERROR] {}", message);
+    }
+}
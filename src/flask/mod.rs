@@ -1,31 +1,391 @@
-use crate::core::http::{HTTPRequest, HTTPResponse, HttpStatusCodes};
+use crate::core::http::{
+    self, get_header, headers, BodySource, HTTPRequest, HTTPResponse, HttpStatusCodes, ParsedBody,
+    RequestLimits,
+};
+use crate::error::RustedFlaskError;
+use crate::internals::media_type;
+#[cfg(feature = "jinja")]
+use crate::jinja::{render_template, JinjaError, JinjaFunction, JinjaValue};
 use std::{
-    io::Write,
-    net::{TcpListener, TcpStream},
-    sync::Arc,
+    any::Any,
+    collections::HashMap,
+    error::Error,
+    io::{Read, Write},
+    net::{TcpListener, ToSocketAddrs},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc,
+    },
     thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+mod logging;
+pub use logging::{Logger, StderrLogger};
+
+mod static_files;
+use static_files::StaticFolder;
+
+mod cache;
+use cache::ResponseCache;
+
+mod error_format;
+pub use error_format::ErrorFormat;
+
+mod cors;
+pub use cors::Cors;
+
+mod test_client;
+pub use test_client::TestRequest;
+
+mod suggestions;
+
+mod well_known;
+use well_known::WellKnownFile;
+
+mod route_pattern;
+use route_pattern::RoutePattern;
+
+mod accept;
+pub(crate) use accept::AcceptBackoff;
+
+mod shutdown;
+use shutdown::ServerState;
+pub use shutdown::ShutdownHandle;
+
+/// How long `App::run` waits for a client to send something before hanging up
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often `App::serve` checks whether it's been shut down while no
+/// clients are connecting
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The header request IDs are read from and echoed in
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// How many request IDs this process has generated, so IDs
+/// generated in the same nanosecond still differ
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
 /// A callback function for when a route is accessed
 pub type RouteFn = Arc<Box<dyn Fn(HTTPRequest) -> HTTPResponse + Sync + Send>>;
 
+/// Decides whether a route added with `App::route_matcher` handles a request
+pub type RouteMatcher = Arc<dyn Fn(&HTTPRequest) -> bool + Sync + Send>;
+
+/// An error returned by a route added with `App::try_route`
+pub type HandlerError = Box<dyn Error + Send + Sync>;
+
+/// A callback function for when a route that can fail is accessed
+pub type FallibleRouteFn =
+    Arc<Box<dyn Fn(HTTPRequest) -> Result<HTTPResponse, HandlerError> + Sync + Send>>;
+
+/// Turns an error returned by a route into the response sent for it
+pub type ErrorMapper = Arc<dyn Fn(HandlerError) -> HTTPResponse + Sync + Send>;
+
+/// Parses the body of a request with the content type it was registered for
+/// (see `App::body_parser`)
+pub type BodyParser = Arc<dyn Fn(&HTTPRequest) -> Result<ParsedBody, HandlerError> + Sync + Send>;
+
+/// A function that gets to look at and change every response before it's sent
+pub type AfterRequestFn = Arc<dyn Fn(&mut HTTPResponse) + Sync + Send>;
+
+#[derive(Clone)]
+enum Handler {
+    Infallible(RouteFn),
+    Fallible(FallibleRouteFn),
+}
+
+/// What `App::bind_address_from_env` reads the whole address from
+pub const BIND_VAR: &str = "RF_BIND";
+
+/// What `App::bind_address_from_env` reads just the port from
+pub const PORT_VAR: &str = "PORT";
+
+/// Where `App::run_from_env` runs when the environment doesn't say
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:5000";
+
+/// Allows every method when it's in a route's allowed methods
+const ANY_METHOD: &str = "*";
+
 #[derive(Clone)]
 struct Route {
     pub path: String,
-    pub func: RouteFn,
+    pub handler: Handler,
     pub allowed_methods: Vec<String>,
+    /// The parameters in `path`, if it has any
+    pub pattern: Option<RoutePattern>,
 }
 
 /// An app (similar to Python's `flask.Flask`)
+#[derive(Clone)]
 pub struct App {
     /// The name of this app
     pub name: String,
+    /// Whether to show details about errors to clients
+    ///
+    /// Never turn this on in production!
+    pub debug: bool,
     routes: Vec<Route>,
+    matched_routes: Vec<(RouteMatcher, Route)>,
+    fallback: Option<Route>,
+    static_folders: Vec<StaticFolder>,
+    well_known_files: Vec<WellKnownFile>,
+    response_cache: Option<Arc<ResponseCache>>,
+    trust_request_id: bool,
+    trust_forwarded_proto: bool,
+    strict_methods: bool,
+    lazy_bodies: bool,
+    handler_timeout: Option<Duration>,
+    default_methods: Vec<String>,
+    error_mapper: Option<ErrorMapper>,
+    error_format: ErrorFormat,
+    cors: Option<Cors>,
+    body_parsers: Vec<(String, BodyParser)>,
+    after_request: Vec<AfterRequestFn>,
+    request_limits: RequestLimits,
+    http_version: Option<(i32, i32)>,
+    logger: Arc<dyn Logger>,
+    server: Arc<ServerState>,
+    #[cfg(feature = "jinja")]
+    error_templates: HashMap<u16, String>,
 }
 
 /// Could not bind to the given address
+#[derive(Debug)]
 pub struct CantBind;
 
+/// Gets the message a panic was started with
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "(no message)".to_string()
+    }
+}
+
+/// Makes a new, unique request ID
+fn generate_request_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos())
+        .unwrap_or(0);
+    let count = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}", nanos, count)
+}
+
+/// Checks that an incoming request ID is short and only contains
+/// characters that are safe to put in logs and headers
+fn is_valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= 128
+        && request_id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.".contains(&byte))
+}
+
+/// Decodes the `%XX` escapes in the path of a request target
+///
+/// The query string, if there is one, is left alone. Escapes that aren't
+/// followed by two hex digits are kept as-is, and so is `%3F`, as a `?`
+/// would start the query string of the decoded path (path parameters
+/// still get it decoded, see `HTTPRequest::path_param`).
+///
+/// # Examples
+/// ```
+/// # use rustedflask::flask::percent_decode_path;
+/// assert_eq!(percent_decode_path(b"/a%20b/c%2Fd"), b"/a b/c/d");
+/// assert_eq!(percent_decode_path(b"/100%?q=%20"), b"/100%?q=%20");
+/// assert_eq!(percent_decode_path(b"/what%3F?q"), b"/what%3F?q");
+/// ```
+pub fn percent_decode_path(path: &[u8]) -> Vec<u8> {
+    let (path, query) = match path.iter().position(|&byte| byte == b'?') {
+        Some(query_start) => path.split_at(query_start),
+        None => (path, &b""[..]),
+    };
+    let hex_value = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let mut decoded = Vec::with_capacity(path.len() + query.len());
+    let mut position = 0;
+    while position < path.len() {
+        if path[position] == b'%' && position + 2 < path.len() {
+            if let (Some(high), Some(low)) =
+                (hex_value(path[position + 1]), hex_value(path[position + 2]))
+            {
+                let byte = high << 4 | low;
+                if byte != b'?' {
+                    decoded.push(byte);
+                    position += 3;
+                    continue;
+                }
+            }
+        }
+        decoded.push(path[position]);
+        position += 1;
+    }
+    decoded.extend(query);
+    decoded
+}
+
+/// Normalizes a request path so it can be matched against routes
+///
+/// Duplicate slashes are collapsed, `.` segments are dropped and `..` segments
+/// drop the segment before them, but can't go above `/`. An empty path becomes `/`.
+/// The query string, if there is one, is left alone, as are targets that aren't
+/// paths (like `*`).
+///
+/// # Examples
+/// ```
+/// # use rustedflask::flask::normalize_path;
+/// assert_eq!(normalize_path(b"//a/./b/../c"), b"/a/c");
+/// assert_eq!(normalize_path(b"/../.."), b"/");
+/// ```
+pub fn normalize_path(path: &[u8]) -> Vec<u8> {
+    if !path.is_empty() && path[0] != b'/' {
+        return path.to_vec();
+    }
+    let (path, query) = match path.iter().position(|&byte| byte == b'?') {
+        Some(query_start) => path.split_at(query_start),
+        None => (path, &b""[..]),
+    };
+    let mut segments: Vec<&[u8]> = Vec::new();
+    for segment in path.split(|&byte| byte == b'/') {
+        match segment {
+            b"" | b"." => {}
+            b".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut normalized = Vec::new();
+    for segment in &segments {
+        normalized.push(b'/');
+        normalized.extend(*segment);
+    }
+    // keep the trailing slash, as `/a/` and `/a` can be different routes
+    let ends_in_directory = path.ends_with(b"/") || path.ends_with(b"/.") || path.ends_with(b"/..");
+    if normalized.is_empty() || ends_in_directory {
+        normalized.push(b'/');
+    }
+    normalized.extend(query);
+    normalized
+}
+
+#[cfg(feature = "jinja")]
+/// Renders `template` (from the templates folder, see
+/// `jinja::render_template`) straight into the body of a `200 OK` response
+///
+/// The rendered template becomes the body as-is, so unlike
+/// `HTTPResponse::from(&*render_template(...)?)`, the page isn't copied
+/// from a `String` into the body afterwards.
+///
+/// The `Content-Type` is set from the extension before `.jinja2`
+/// (see `template_content_type`), and left unset for other templates.
+///
+/// # Examples
+/// ```no_run
+/// # use rustedflask::{core::http::{HTTPRequest, HTTPResponse}, flask};
+/// # use std::collections::HashMap;
+/// fn index(_request: HTTPRequest) -> HTTPResponse {
+///     match flask::render("index.html.jinja2", HashMap::new(), None) {
+///         Ok(response) => response,
+///         Err(why) => HTTPResponse::from(&*format!("{:?}", why)),
+///     }
+/// }
+/// ```
+pub fn render<'a>(
+    template: &'a str,
+    variables: HashMap<&'a str, JinjaValue>,
+    functions: Option<HashMap<&'a str, JinjaFunction>>,
+) -> Result<HTTPResponse, JinjaError> {
+    let mut response = render_template(template, variables, functions).map(HTTPResponse::from)?;
+    if let Some(content_type) = template_content_type(template) {
+        response
+            .headers
+            .insert(headers::CONTENT_TYPE.to_string(), content_type.to_string());
+    }
+    Ok(response)
+}
+
+#[cfg(feature = "jinja")]
+/// The `Content-Type` of what `template` renders to, going by its inner
+/// extension, like the `html` of `index.html.jinja2`
+///
+/// # Examples
+/// ```
+/// # use rustedflask::flask::template_content_type;
+/// assert_eq!(template_content_type("index.html.jinja2"), Some("text/html"));
+/// assert_eq!(template_content_type("api/user.json.jinja2"), Some("application/json"));
+/// assert_eq!(template_content_type("index.jinja2"), None);
+/// ```
+pub fn template_content_type(template: &str) -> Option<&'static str> {
+    let inner = template.strip_suffix(".jinja2").unwrap_or(template);
+    let (_, extension) = inner.rsplit_once('.')?;
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => Some("text/html"),
+        "json" => Some("application/json"),
+        "txt" => Some("text/plain"),
+        _ => None,
+    }
+}
+
+/// Runs `run` on a thread of its own, giving up on it (and leaving it
+/// running) if it hasn't finished after `timeout`
+fn run_with_timeout<T: Send + 'static>(
+    run: impl FnOnce() -> T + Send + 'static,
+    timeout: Duration,
+) -> Option<T> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // the receiver is gone if this took too long
+        let _ = sender.send(run());
+    });
+    receiver.recv_timeout(timeout).ok()
+}
+
+/// Whether the `Connection` header of `request` has `option` in it
+fn has_connection_option(request: &HTTPRequest, option: &str) -> bool {
+    match get_header(&request.headers, headers::CONNECTION) {
+        Some(connection) => connection
+            .split(',')
+            .any(|sent| sent.trim().eq_ignore_ascii_case(option)),
+        None => false,
+    }
+}
+
+/// Whether the connection should be closed after responding to `request`
+///
+/// HTTP/1.1 connections are kept alive unless the client sends
+/// `Connection: close`, HTTP/1.0 ones are closed unless it sends
+/// `Connection: keep-alive`.
+fn wants_close(request: &HTTPRequest) -> bool {
+    if request.httpversion < (1, 1) {
+        !has_connection_option(request, "keep-alive")
+    } else {
+        has_connection_option(request, "close")
+    }
+}
+
+/// Builds the access log line for a request
+pub(crate) fn access_log_line(
+    method: &str,
+    path: &str,
+    response: &HTTPResponse,
+    request_id: &str,
+) -> String {
+    format!(
+        "{} {} {} (request ID: {})",
+        method,
+        path,
+        response.statuscode.status_code_number(),
+        request_id
+    )
+}
+
 impl App {
     /// Makes a new app
     ///
@@ -42,116 +402,1002 @@ impl App {
     pub fn new(name: String) -> App {
         App {
             name,
+            debug: false,
             routes: Vec::new(),
+            matched_routes: Vec::new(),
+            fallback: None,
+            static_folders: Vec::new(),
+            well_known_files: Vec::new(),
+            response_cache: None,
+            trust_request_id: false,
+            trust_forwarded_proto: false,
+            strict_methods: false,
+            lazy_bodies: false,
+            handler_timeout: None,
+            default_methods: vec!["GET".to_string()],
+            error_mapper: None,
+            error_format: ErrorFormat::default(),
+            cors: None,
+            body_parsers: Vec::new(),
+            after_request: Vec::new(),
+            request_limits: RequestLimits::default(),
+            http_version: None,
+            logger: Arc::new(StderrLogger),
+            server: Arc::new(ServerState::default()),
+            #[cfg(feature = "jinja")]
+            error_templates: HashMap::new(),
         }
+        .with_builtin_body_parsers()
     }
 
-    fn handle(&mut self, request: HTTPRequest, mut client: TcpStream) {
-        let proper_request_path = request.path.to_vec();
-        let route_string = String::from_utf8(proper_request_path);
+    /// Registers the body parsers every app starts with
+    fn with_builtin_body_parsers(mut self) -> App {
+        self.body_parser("multipart/form-data", |request| {
+            let parts = request
+                .multipart()
+                .map_err(|error| format!("Unreadable multipart body: {:?}", error))?;
+            Ok(ParsedBody::new("multipart/form-data", parts))
+        });
+        self
+    }
 
-        if route_string.is_err() {
-            return;
+    /// Turns debug mode on or off (see `App.debug`)
+    ///
+    /// In debug mode, 500 responses say what went wrong (like the message
+    /// a route panicked with), otherwise they only have a generic message.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let app = App::new("name".to_string()).with_debug(true);
+    /// ```
+    pub fn with_debug(self, debug: bool) -> App {
+        App { debug, ..self }
+    }
+
+    /// Sets how errors returned by routes added with `App::try_route` are
+    /// turned into responses
+    ///
+    /// By default, they're logged and turned into a 500 response, which only
+    /// says what the error was in debug mode. The mapper gets the error boxed,
+    /// so it can downcast it to pick a response for each kind of error.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::{core::http::{HTTPResponse, HttpStatusCodes}, flask::App};
+    /// # use std::num::ParseIntError;
+    /// let mut app = App::new("name".to_string());
+    /// app.error_mapper(|error| match error.downcast_ref::<ParseIntError>() {
+    ///     Some(_) => HTTPResponse::from("Not a number").with_statuscode(
+    ///         HttpStatusCodes::BadRequest,
+    ///         Box::new(b"Bad Request".to_owned()),
+    ///     ),
+    ///     None => HTTPResponse::from("Something went wrong").with_statuscode(
+    ///         HttpStatusCodes::InternalServerError,
+    ///         Box::new(b"Internal Server Error".to_owned()),
+    ///     ),
+    /// });
+    /// ```
+    pub fn error_mapper(
+        &mut self,
+        mapper: impl Fn(HandlerError) -> HTTPResponse + Sync + Send + 'static,
+    ) {
+        self.error_mapper = Some(Arc::new(mapper));
+    }
+
+    /// Sets what the error responses the framework makes itself (like 404s
+    /// for paths without a route) look like, which is plain text by default
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::{App, ErrorFormat};
+    /// let mut app = App::new("name".to_string());
+    /// app.error_format(ErrorFormat::Json);
+    ///
+    /// let response = app.test().path("/missing").send();
+    /// assert_eq!(response.content, br#"{"status":404,"error":"Not Found"}"#);
+    /// ```
+    pub fn error_format(&mut self, format: ErrorFormat) {
+        self.error_format = format;
+    }
+
+    /// Registers `parser` to parse the bodies of requests with `content_type`
+    /// (just the media type, like `application/msgpack`), replacing any
+    /// parser already registered for it
+    ///
+    /// Bodies are parsed before the route is called, and what the parser
+    /// makes is available to it with `HTTPRequest::parsed_body`. If the parser
+    /// fails, the client gets a 400 instead. `multipart/form-data` bodies are
+    /// parsed into a `Vec<MultipartPart>` out of the box.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::{core::http::ParsedBody, flask::App};
+    /// let mut app = App::new("name".to_string());
+    /// app.body_parser("text/csv", |request| {
+    ///     let text = String::from_utf8(request.content.clone())?;
+    ///     let rows: Vec<Vec<String>> = text
+    ///         .lines()
+    ///         .map(|line| line.split(',').map(str::to_string).collect())
+    ///         .collect();
+    ///     Ok(ParsedBody::new("text/csv", rows))
+    /// });
+    /// app.route_with_allowed_methods(
+    ///     "/import",
+    ///     |request| match request.parsed_body::<Vec<Vec<String>>>() {
+    ///         Some(rows) => format!("Imported {} rows", rows.len()).into(),
+    ///         None => "Send a CSV file".into(),
+    ///     },
+    ///     vec!["POST".to_string()],
+    /// );
+    /// ```
+    pub fn body_parser(
+        &mut self,
+        content_type: &str,
+        parser: impl Fn(&HTTPRequest) -> Result<ParsedBody, HandlerError> + Sync + Send + 'static,
+    ) {
+        let content_type = media_type(content_type);
+        self.body_parsers
+            .retain(|(registered, _)| *registered != content_type);
+        self.body_parsers.push((content_type, Arc::new(parser)));
+    }
+
+    /// Parses the body of `request` with the parser for its content type,
+    /// if there is one, returning the response to send instead if it fails
+    fn parse_body(&self, request: &mut HTTPRequest) -> Option<HTTPResponse> {
+        let content_type = media_type(get_header(&request.headers, headers::CONTENT_TYPE)?);
+        let parser = match self
+            .body_parsers
+            .iter()
+            .find(|(registered, _)| *registered == content_type)
+        {
+            Some((_, parser)) => parser,
+            None => return None,
+        };
+        // parsers need the whole body
+        if request.read_body().is_err() {
+            return Some(self.error_response(
+                HttpStatusCodes::BadRequest,
+                "Bad Request",
+                "The request body could not be read.",
+                None,
+            ));
+        }
+        match parser(request) {
+            Ok(parsed_body) => {
+                request.parsed_body = Some(parsed_body);
+                None
+            }
+            Err(error) => Some(self.error_response(
+                HttpStatusCodes::BadRequest,
+                "Bad Request",
+                "The request body could not be read.",
+                Some(format!("Can't parse {} body: {}", content_type, error)),
+            )),
+        }
+    }
+
+    /// Lets pages on the origins `cors` allows call this app from the
+    /// browser (see `Cors`)
+    pub fn cors(&mut self, cors: Cors) {
+        self.cors = Some(cors);
+    }
+
+    /// Adds a function that's run on every response before it's sent,
+    /// in the order they were added
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.after_request(|response| {
+    ///     if response.iter_headers().all(|(header, _)| header != "Cache-Control") {
+    ///         response
+    ///             .headers
+    ///             .insert("Cache-Control".to_string(), "no-cache".to_string());
+    ///     }
+    /// });
+    /// ```
+    pub fn after_request(&mut self, function: impl Fn(&mut HTTPResponse) + Sync + Send + 'static) {
+        self.after_request.push(Arc::new(function));
+    }
+
+    /// Sets the limits on what requests clients can send (see `RequestLimits`)
+    ///
+    /// Requests with too many headers get a 431 response.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::{core::http::RequestLimits, flask::App};
+    /// let app = App::new("name".to_string()).with_request_limits(RequestLimits {
+    ///     max_headers: 50,
+    ///     ..RequestLimits::default()
+    /// });
+    /// ```
+    pub fn with_request_limits(self, request_limits: RequestLimits) -> App {
+        App {
+            request_limits,
+            ..self
+        }
+    }
+
+    /// Sends every response with the HTTP version `version`
+    ///
+    /// By default, responses use the same version as the request they're
+    /// answering (so HTTP/1.0 clients get `HTTP/1.0` responses), or HTTP/1.1
+    /// if the request's version isn't one the server speaks.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let app = App::new("name".to_string()).with_http_version((1, 1));
+    /// ```
+    pub fn with_http_version(self, version: (i32, i32)) -> App {
+        App {
+            http_version: Some(version),
+            ..self
+        }
+    }
+
+    /// The HTTP version to answer a request with the version `request_version` with
+    fn response_version(&self, request_version: (i32, i32)) -> (i32, i32) {
+        match (self.http_version, request_version) {
+            (Some(version), _) => version,
+            (None, (1, 0)) | (None, (1, 1)) => request_version,
+            (None, _) => (1, 1),
         }
+    }
+
+    #[cfg(feature = "jinja")]
+    /// Renders `template` (from the `templates` folder) as the body of error
+    /// responses with the status `statuscode` that the framework makes, like
+    /// 404s for missing routes or 500s for routes that panicked
+    ///
+    /// The template gets the `status_code`, the `reason` (like "Not Found"),
+    /// a `message` that's safe to show to clients, and the `details` of the error,
+    /// which are empty unless the app is in debug mode. If it can't be rendered,
+    /// a plain text response is sent instead.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::{core::http::HttpStatusCodes, flask::App};
+    /// let mut app = App::new("name".to_string());
+    /// app.error_template(HttpStatusCodes::InternalServerError, "errors/500.html.jinja2");
+    /// ```
+    pub fn error_template(&mut self, statuscode: HttpStatusCodes, template: &str) {
+        self.error_templates
+            .insert(statuscode.status_code_number(), template.to_string());
+    }
+
+    /// Sends the server's diagnostics (access logs, errors, etc.,) to `logger`
+    /// instead of stderr
+    pub fn set_logger(&mut self, logger: impl Logger + 'static) {
+        self.logger = Arc::new(logger);
+    }
+
+    /// Whether to reuse the `X-Request-Id` header sent by the client
+    /// instead of generating a new ID for each request
+    ///
+    /// Only turn this on when the server is behind a proxy that sets
+    /// (or strips) the header, as clients can send any ID they like.
+    /// Malformed IDs are always replaced.
+    pub fn trust_request_id(&mut self, trust: bool) {
+        self.trust_request_id = trust;
+    }
+
+    /// Whether to believe the `X-Forwarded-Proto` header when working out
+    /// if a request was made over HTTPS (see `HTTPRequest::is_secure`)
+    ///
+    /// Only turn this on when the server is behind a proxy that sets
+    /// (or strips) the header, as clients can send anything they like.
+    pub fn trust_forwarded_proto(&mut self, trust: bool) {
+        self.trust_forwarded_proto = trust;
+    }
+
+    /// Whether to answer requests with methods HTTP doesn't define (see
+    /// `http::KNOWN_METHODS`) with `501 Not Implemented`
+    ///
+    /// Otherwise, they're treated like any other method a route doesn't
+    /// allow, and get a `405 Method Not Allowed`.
+    pub fn strict_methods(&mut self, strict: bool) {
+        self.strict_methods = strict;
+    }
+
+    /// Serves `icon` (an `.ico` file) at `/favicon.ico`, so browsers asking
+    /// for it don't fill the logs with 404s
+    ///
+    /// A route or static file at `/favicon.ico` is served instead, if there is one.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.default_favicon(std::fs::read("favicon.ico").unwrap());
+    /// ```
+    pub fn default_favicon(&mut self, icon: Vec<u8>) {
+        self.add_well_known_file(WellKnownFile::new("/favicon.ico", icon, "image/x-icon"));
+    }
+
+    /// Serves `content` at `/robots.txt`, for crawlers
+    ///
+    /// A route or static file at `/robots.txt` is served instead, if there is one.
+    ///
+    /// # Examples
+    /// ```rust
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.robots_txt("User-agent: *\nDisallow: /admin\n");
+    /// ```
+    pub fn robots_txt(&mut self, content: &str) {
+        self.add_well_known_file(WellKnownFile::new(
+            "/robots.txt",
+            content.as_bytes().to_vec(),
+            "text/plain",
+        ));
+    }
+
+    /// Adds `file`, replacing any other file at its path
+    fn add_well_known_file(&mut self, file: WellKnownFile) {
+        self.well_known_files
+            .retain(|existing| existing.path != file.path);
+        self.well_known_files.push(file);
+    }
+
+    /// Whether to leave request bodies on the connection until the handler
+    /// reads them (see `HTTPRequest::read_body`), instead of reading them
+    /// before the handler runs
+    ///
+    /// That way, a handler that doesn't need the body (like one that turns
+    /// away unauthorized uploads) can answer without waiting for all of it.
+    /// Whatever isn't read is skipped after the response is sent. While this
+    /// is on, `content` is empty until the body has been read, except for
+    /// chunked bodies, which are always read first.
+    pub fn lazy_bodies(&mut self, lazy: bool) {
+        self.lazy_bodies = lazy;
+    }
+
+    /// How long handlers get to respond before the client is sent a
+    /// `504 Gateway Timeout` instead, or `None` (the default) to wait for
+    /// as long as they take
+    ///
+    /// A thread can't be stopped from the outside, so a handler that times
+    /// out keeps running in the background until it returns, and what it
+    /// returns is thrown away. Handlers that can get stuck for good still
+    /// need fixing, as each one ties up a thread forever.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// # use std::time::Duration;
+    /// let mut app = App::new("name".to_string());
+    /// app.handler_timeout(Some(Duration::from_secs(30)));
+    /// ```
+    pub fn handler_timeout(&mut self, timeout: Option<Duration>) {
+        self.handler_timeout = timeout;
+    }
+
+    /// Sets the methods that routes added with `App::route` and
+    /// `App::try_route` from now on allow, which is just `GET` at first
+    ///
+    /// A `*` allows every method (see `App::route_any`).
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.default_methods(vec!["POST".to_string()]);
+    /// // only answers POSTs
+    /// app.route("/api/messages", |_| "Sent".into());
+    /// ```
+    pub fn default_methods(&mut self, methods: Vec<String>) {
+        self.default_methods = methods;
+    }
+
+    /// Reads requests from `stream` and sends the responses back over it,
+    /// until the client closes the connection or asks for it to be closed
+    ///
+    /// HTTP/1.1 connections are kept alive between requests, unless the client
+    /// sends `Connection: close`. HTTP/1.0 connections are closed after the
+    /// first response, unless the client sends `Connection: keep-alive`.
+    /// Every response says which it is with a `Connection` header.
+    ///
+    /// The requests on a connection are handled one at a time, so responses
+    /// to pipelined requests (sent without waiting for the response to the
+    /// one before) are always sent back in the order the requests were.
+    /// Different connections are still handled at the same time by
+    /// `App::run` and `App::serve`, which give each its own thread.
+    ///
+    /// This is what `App::run` does with each client, but it works with
+    /// any stream, so it can be used to serve over something other than TCP.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::flask::App;
+    /// # use std::net::TcpListener;
+    /// let app = App::new("name".to_string());
+    /// let listener = TcpListener::bind("127.0.0.1:5000").unwrap();
+    /// let (mut client, _) = listener.accept().unwrap();
+    /// app.handle_connection(&mut client);
+    /// ```
+    pub fn handle_connection(&self, stream: &mut (impl Read + Write)) {
+        self.serve_connection(stream, false)
+    }
 
-        let route = self.find_route_for_path(route_string.clone().unwrap().as_str());
-
-        if route.is_none() {
-            let notfoundroute_wrapped = self.find_route_for_path("!404");
-            if let Some(notfoundroute) = notfoundroute_wrapped {
-                thread::spawn(move || {
-                    let response: Vec<u8> = (notfoundroute.func)(request).into();
-                    let buf = &mut [0_u8];
-                    for byte in response {
-                        buf[0] = byte;
-                        let err = client.write(buf);
-                        if err.is_err() {
-                            panic!("{:?}", err.unwrap_err())
-                        };
-                    }
-                });
+    /// Like `App::handle_connection`, but for a stream that's already been
+    /// through a TLS handshake, so every request on it is secure
+    /// (see `HTTPRequest::is_secure`)
+    ///
+    /// `stream` should give the decrypted requests and encrypt the responses.
+    pub fn handle_tls_connection(&self, stream: &mut (impl Read + Write)) {
+        self.serve_connection(stream, true)
+    }
+
+    /// Serves the requests on a connection, marking them as secure if it's
+    /// encrypted
+    fn serve_connection(&self, stream: &mut (impl Read + Write), tls: bool) {
+        // kept for the whole connection, as it can hold the start of the next request
+        let stream = &mut http::BufferedStream::new(stream);
+        let mut first_request = true;
+        // the next request isn't read until this one's response has been
+        // sent, which keeps pipelined responses in order
+        loop {
+            let read = if self.lazy_bodies {
+                HTTPRequest::read_http_request_lazily(stream, &self.request_limits)
             } else {
-                let mut response_http = HTTPResponse::from("404 Not Found");
-                response_http.statuscode = HttpStatusCodes::NotFound;
-                response_http.reason = Box::new(b"Not Found".to_owned());
-                let response: Vec<u8> = response_http.into();
-                let buf = &mut [0_u8];
-                for byte in response {
-                    buf[0] = byte;
-                    let err = client.write(buf);
-                    if err.is_err() {
-                        println!("Erorr sending data to client: {:?}", err.unwrap_err())
-                    };
+                HTTPRequest::read_http_request_with_limits(stream, &self.request_limits)
+                    .map(|request| (request, None))
+            };
+            let (mut request, mut body) = match read {
+                Ok(read) => read,
+                // the client hung up between requests
+                Err(http::Error::StreamReadError) if !first_request => return,
+                Err(http::Error::TooManyHeaders) => {
+                    return self.refuse_request(
+                        stream,
+                        HttpStatusCodes::RequestHeaderFieldsTooLarge,
+                        "Request Header Fields Too Large",
+                        "The request has too many headers.",
+                    )
+                }
+                Err(http::Error::BodyTooLarge) => {
+                    return self.refuse_request(
+                        stream,
+                        HttpStatusCodes::PayloadTooLarge,
+                        "Payload Too Large",
+                        "The request body is too large.",
+                    )
+                }
+                Err(http::Error::ConflictingFraming) => {
+                    return self.refuse_request(
+                        stream,
+                        HttpStatusCodes::BadRequest,
+                        "Bad Request",
+                        "The request's length is ambiguous.",
+                    )
+                }
+                Err(http::Error::HeaderTimeout) => {
+                    return self.refuse_request(
+                        stream,
+                        HttpStatusCodes::RequestTimeout,
+                        "Request Timeout",
+                        "The request took too long to arrive.",
+                    )
+                }
+                Err(why) => {
+                    self.logger
+                        .warn(&format!("Can't read request... {:?}", why));
+                    return;
                 }
             };
-            return;
+            first_request = false;
+            let in_flight = self.server.start_request();
+            request.secure = tls;
+            let close = wants_close(&request);
+            let response = match body {
+                Some(ref mut body) => self.respond_reading_body(request, body, stream),
+                None => self.respond(request),
+            };
+            // don't keep connections open past a shutdown, even one
+            // that started while the request was being handled
+            let close = close || self.server.is_shutting_down();
+            let mut response = response;
+            response.headers.insert(
+                headers::CONNECTION.to_string(),
+                if close { "close" } else { "keep-alive" }.to_string(),
+            );
+            let sent = response.write_to(stream);
+            drop(in_flight);
+            if let Err(why) = sent {
+                self.logger
+                    .error(&format!("Error sending data to client: {:?}", why));
+                return;
+            }
+            if close {
+                return;
+            }
+            if let Some(body) = body {
+                if let Err(why) = body.drain(stream) {
+                    self.logger
+                        .warn(&format!("Can't read the rest of the body... {:?}", why));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Responds to `request` on another thread, while this one reads its
+    /// body from `stream` whenever the handler asks for it
+    fn respond_reading_body(
+        &self,
+        request: HTTPRequest,
+        body: &mut BodySource,
+        stream: &mut impl Read,
+    ) -> HTTPResponse {
+        let handled = body.handled();
+        thread::scope(|scope| {
+            let handler = scope.spawn(move || {
+                let _handled = handled;
+                self.respond(request)
+            });
+            body.serve(stream);
+            match handler.join() {
+                Ok(response) => response,
+                Err(payload) => std::panic::resume_unwind(payload),
+            }
+        })
+    }
+
+    /// Answers a request that couldn't be read with an error, and closes the connection
+    fn refuse_request(
+        &self,
+        stream: &mut impl Write,
+        statuscode: HttpStatusCodes,
+        reason: &str,
+        description: &str,
+    ) {
+        self.logger
+            .warn(&format!("Refused request: {}", description));
+        let response = self
+            .error_response(statuscode, reason, description, None)
+            .with_header(headers::CONNECTION.to_string(), "close".to_string());
+        // the rest of the request is never read, so the connection can't be reused
+        let _ = response.write_to(stream);
+    }
+
+    /// Starts building a request to send straight to this app, for tests
+    /// (see `TestRequest`)
+    pub fn test(&self) -> TestRequest<'_> {
+        TestRequest::new(self)
+    }
+
+    /// Gets the ID for `request`, generating one unless a trusted one was sent
+    fn request_id_for(&self, request: &HTTPRequest) -> String {
+        if self.trust_request_id {
+            let incoming = request
+                .headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(REQUEST_ID_HEADER));
+            if let Some((_, request_id)) = incoming {
+                if is_valid_request_id(request_id) {
+                    return request_id.clone();
+                }
+            }
+        }
+        generate_request_id()
+    }
+
+    /// Whether a trusted proxy says the client connected over HTTPS
+    fn forwarded_https(&self, request: &HTTPRequest) -> bool {
+        if !self.trust_forwarded_proto {
+            return false;
+        }
+        // each proxy adds its own scheme, the first is the client's
+        match get_header(&request.headers, headers::X_FORWARDED_PROTO) {
+            Some(proto) => proto
+                .split(',')
+                .next()
+                .is_some_and(|scheme| scheme.trim().eq_ignore_ascii_case("https")),
+            None => false,
+        }
+    }
+
+    /// Tags `request` with a request ID, runs it through the routes
+    /// and logs it
+    pub(crate) fn respond(&self, mut request: HTTPRequest) -> HTTPResponse {
+        let request_id = self.request_id_for(&request);
+        request.request_id = Some(request_id.clone());
+        request.secure = request.secure || self.forwarded_https(&request);
+        // decode before normalizing, so encoded `..`s can't get past it
+        request.path = normalize_path(&percent_decode_path(&request.path));
+        let method = String::from_utf8_lossy(&request.method).to_string();
+        let path = String::from_utf8_lossy(&request.path).to_string();
+        let httpversion = self.response_version(request.httpversion);
+        let cors_origin = self
+            .cors
+            .as_ref()
+            .and_then(|cors| cors.allowed_origin(&request));
+
+        let response = match (&self.cors, &cors_origin) {
+            (Some(cors), Some(origin)) if Cors::is_preflight(&request) => {
+                cors.preflight_response(&request, origin)
+            }
+            (Some(cors), Some(origin)) => {
+                let mut response = self.cached_route_request(request);
+                cors.add_headers(&mut response, origin);
+                response
+            }
+            _ => self.cached_route_request(request),
         };
-        let methnotallowed_route = self.find_route_for_path("!405");
-        thread::spawn(move || {
-            if route
-                .clone()
-                .unwrap()
-                .allowed_methods
-                .contains(&String::from_utf8(request.clone().method).unwrap())
-            {
-                let response: Vec<u8> = (route.unwrap().func)(request).into();
-                let buf = &mut [0_u8];
-                for byte in response {
-                    buf[0] = byte;
-                    let err = client.write(buf);
-                    if err.is_err() {
-                        panic!("{:?}", err.unwrap_err())
-                    }
+        let mut response = response;
+        response
+            .headers
+            .insert(REQUEST_ID_HEADER.to_string(), request_id.clone());
+        if !response.keep_version {
+            response.httpversion = httpversion;
+        }
+        for after_request in &self.after_request {
+            after_request(&mut response);
+        }
+        self.logger
+            .info(&access_log_line(&method, &path, &response, &request_id));
+        response
+    }
+
+    fn route_request(&self, mut request: HTTPRequest) -> HTTPResponse {
+        if self.strict_methods && !http::is_known_method(&request.method) {
+            return self.error_response(
+                HttpStatusCodes::NotImplemented,
+                "Not Implemented",
+                "The server does not support the method used.",
+                None,
+            );
+        }
+        if let Some((_, route)) = self
+            .matched_routes
+            .iter()
+            .find(|(matcher, _)| matcher(&request))
+        {
+            return self.call_route(route, request);
+        }
+        // routes without parameters win over ones with them that also match
+        let route = match String::from_utf8(request.path.clone()) {
+            Ok(path) => self.find_route_for_path(&path).or_else(|| {
+                let (route, captured) = self.find_route_with_parameters(&path)?;
+                request.path_params = captured;
+                Some(route)
+            }),
+            Err(_) => None,
+        };
+
+        let route = match route {
+            Some(route) => route,
+            None => {
+                if let Some(response) = self.serve_static(&request) {
+                    return response;
                 }
-            } else {
-                let response = match methnotallowed_route {
-                    None => Vec::<u8>::from(
-                        HTTPResponse::new()
-                            .with_statuscode(
-                                HttpStatusCodes::MethodNotAllowed,
-                                Box::new(b"Method Not Allowed".to_owned()),
-                            )
-                            .with_content("405 Method Not Allowed".to_string().into_bytes()),
+                if let Some(response) = self.serve_well_known(&request) {
+                    return response;
+                }
+                return match (self.find_route_for_path("!404"), &self.fallback) {
+                    (Some(notfoundroute), _) => self.call_route(&notfoundroute, request),
+                    (None, Some(fallback)) => self.call_route(fallback, request),
+                    (None, None) => self.error_response(
+                        HttpStatusCodes::NotFound,
+                        "Not Found",
+                        "The requested URL was not found on the server.",
+                        self.route_suggestions(&request),
                     ),
-                    Some(route) => Vec::<u8>::from((route.func)(request)),
                 };
-                let buf = &mut [0_u8];
-                for byte in response {
-                    buf[0] = byte;
-                    let err = client.write(buf);
-                    if err.is_err() {
-                        panic!("{:?}", err.unwrap_err())
-                    }
+            }
+        };
+
+        let method = String::from_utf8_lossy(&request.method);
+        if route
+            .allowed_methods
+            .iter()
+            .any(|allowed| allowed == ANY_METHOD || *allowed == method)
+        {
+            return self.call_route(&route, request);
+        }
+        match (self.find_route_for_path("!405"), &self.fallback) {
+            (None, Some(fallback)) => self.call_route(fallback, request),
+            (None, None) => self.error_response(
+                HttpStatusCodes::MethodNotAllowed,
+                "Method Not Allowed",
+                "The method is not allowed for the requested URL.",
+                None,
+            ),
+            (Some(route), _) => self.call_route(&route, request),
+        }
+    }
+
+    /// Runs `request` through the routes, unless there's a cached response
+    /// to it (see `App::cache_responses`)
+    fn cached_route_request(&self, request: HTTPRequest) -> HTTPResponse {
+        let cache = match &self.response_cache {
+            Some(cache) => cache,
+            None => return self.route_request(request),
+        };
+        let key = match ResponseCache::key_for(&request) {
+            Some(key) => key,
+            None => return self.route_request(request),
+        };
+        if let Some(response) = cache.get(&key) {
+            return response;
+        }
+        let response = self.route_request(request);
+        cache.store(key, &response);
+        response
+    }
+
+    /// Lists the routes with paths close to the one `request` is for,
+    /// to help with typos (only worked out in debug mode)
+    fn route_suggestions(&self, request: &HTTPRequest) -> Option<String> {
+        if !self.debug {
+            return None;
+        }
+        let path = String::from_utf8_lossy(&request.path);
+        let path = path.split('?').next().unwrap_or("");
+        let routes = self
+            .routes
+            .iter()
+            .map(|route| route.path.as_str())
+            // `!404` and the like aren't paths
+            .filter(|route| !route.starts_with('!'));
+        let suggestions = suggestions::closest_routes(path, routes);
+        if suggestions.is_empty() {
+            return None;
+        }
+        Some(format!("Did you mean:\n{}", suggestions.join("\n")))
+    }
+
+    /// Serves the file `request` is for from the static folders, if there is one
+    fn serve_static(&self, request: &HTTPRequest) -> Option<HTTPResponse> {
+        let path = String::from_utf8_lossy(&request.path);
+        let file = self
+            .static_folders
+            .iter()
+            .find_map(|folder| folder.file_for(&path))?;
+        if request.method != b"GET" {
+            return Some(self.error_response(
+                HttpStatusCodes::MethodNotAllowed,
+                "Method Not Allowed",
+                "The method is not allowed for the requested URL.",
+                None,
+            ));
+        }
+        let accept_encoding = get_header(&request.headers, headers::ACCEPT_ENCODING);
+        Some(
+            match static_files::file_response(&file, accept_encoding.map(|value| &**value)) {
+                Ok(response) => response,
+                Err(why) => self.error_response(
+                    HttpStatusCodes::InternalServerError,
+                    "Internal Server Error",
+                    "The server encountered an internal error.",
+                    Some(format!("Can't read {}: {}", file.display(), why)),
+                ),
+            },
+        )
+    }
+
+    /// Serves the well-known file `request` is for (see `App::default_favicon`
+    /// and `App::robots_txt`), if there is one
+    fn serve_well_known(&self, request: &HTTPRequest) -> Option<HTTPResponse> {
+        if request.method != b"GET" {
+            return None;
+        }
+        let path = String::from_utf8_lossy(&request.path);
+        self.well_known_files
+            .iter()
+            .find(|file| file.is_for(&path))
+            .map(WellKnownFile::response)
+    }
+
+    /// Calls `route`, turning a panic into a 500 response, and an error
+    /// into a response with the error mapper
+    fn call_route(&self, route: &Route, mut request: HTTPRequest) -> HTTPResponse {
+        if let Some(response) = self.parse_body(&mut request) {
+            return response;
+        }
+        let handler = route.handler.clone();
+        let run = move || {
+            panic::catch_unwind(AssertUnwindSafe(|| match handler {
+                Handler::Infallible(ref func) => Ok(func(request)),
+                Handler::Fallible(ref func) => func(request),
+            }))
+        };
+        let result = match self.handler_timeout {
+            None => run(),
+            Some(timeout) => match run_with_timeout(run, timeout) {
+                Some(result) => result,
+                None => {
+                    let details = format!("Route {} took longer than {:?}", route.path, timeout);
+                    self.logger.warn(&details);
+                    return self.error_response(
+                        HttpStatusCodes::GatewayTimeout,
+                        "Gateway Timeout",
+                        "The server took too long to respond.",
+                        Some(details),
+                    );
+                }
+            },
+        };
+        match result {
+            Ok(Ok(response)) => response,
+            Ok(Err(error)) => match self.error_mapper {
+                Some(ref mapper) => mapper(error),
+                None => {
+                    let details = format!("Route {} returned an error: {}", route.path, error);
+                    self.logger.error(&details);
+                    self.error_response(
+                        HttpStatusCodes::InternalServerError,
+                        "Internal Server Error",
+                        "The server encountered an internal error.",
+                        Some(details),
+                    )
                 }
+            },
+            Err(payload) => {
+                let details = format!(
+                    "Route {} panicked: {}",
+                    route.path,
+                    panic_message(&*payload)
+                );
+                self.logger.error(&details);
+                self.error_response(
+                    HttpStatusCodes::InternalServerError,
+                    "Internal Server Error",
+                    "The server encountered an internal error.",
+                    Some(details),
+                )
             }
-        });
+        }
+    }
+
+    /// Builds the response for an error the framework ran into (like a missing
+    /// route), rendering the error template for `statuscode` if one was registered
+    ///
+    /// `message` is shown to clients, so it shouldn't have any details in it,
+    /// those go in `details`, which are only shown in debug mode.
+    #[cfg_attr(not(feature = "jinja"), allow(unused_variables))]
+    fn error_response(
+        &self,
+        statuscode: HttpStatusCodes,
+        reason: &str,
+        message: &str,
+        details: Option<String>,
+    ) -> HTTPResponse {
+        let code = statuscode.status_code_number();
+        #[cfg(feature = "jinja")]
+        if let Some(template) = self.error_templates.get(&code) {
+            let mut variables = HashMap::new();
+            variables.insert("status_code", JinjaValue::Int(code.into()));
+            variables.insert("reason", reason.into());
+            variables.insert("message", message.into());
+            variables.insert(
+                "details",
+                match details {
+                    Some(ref details) if self.debug => details.as_str().into(),
+                    _ => JinjaValue::from(""),
+                },
+            );
+            match render_template(template, variables, None) {
+                Ok(page) => {
+                    return HTTPResponse::from(page)
+                        .with_statuscode(statuscode, reason.as_bytes().into())
+                        .with_header(headers::CONTENT_TYPE.to_string(), "text/html".to_string())
+                }
+                Err(why) => self.logger.error(&format!(
+                    "Can't render error template {}: {:?}",
+                    template, why
+                )),
+            }
+        }
+        let details = details.filter(|_| self.debug);
+        let (content, content_type) =
+            self.error_format
+                .body(code, reason, message, details.as_deref());
+        let response = HTTPResponse::new()
+            .with_statuscode(statuscode, reason.as_bytes().into())
+            .with_content(content.into_bytes());
+        match content_type {
+            Some(content_type) => {
+                response.with_header(headers::CONTENT_TYPE.to_string(), content_type.to_string())
+            }
+            None => response,
+        }
     }
 
-    fn find_route_for_path(&mut self, path: &str) -> Option<Route> {
+    fn find_route_for_path(&self, path: &str) -> Option<Route> {
         for route in &self.routes {
-            if route.path == *path {
+            if route.pattern.is_none() && route.path == *path {
                 return Some(route.clone());
             };
         }
         None
     }
 
+    /// Finds the first route with parameters in its path that `path`
+    /// matches, along with the parameters
+    fn find_route_with_parameters(&self, path: &str) -> Option<(Route, HashMap<String, String>)> {
+        self.routes.iter().find_map(|route| {
+            let captured = route.pattern.as_ref()?.captures(path)?;
+            Some((route.clone(), captured))
+        })
+    }
+
     /// Creates a route for `path`, calling `func` when
     /// the route is accessed
+    ///
+    /// Segments of `path` in angle brackets, like `/post/<year>/<slug>`,
+    /// match any segment, which the handler gets with
+    /// `HTTPRequest::path_param`. A route without parameters wins over one
+    /// with them when both match, and otherwise the first one added does.
     pub fn route(
         &mut self,
         path: &str,
         func: impl Fn(HTTPRequest) -> HTTPResponse + Sync + Send + 'static,
     ) {
-        self.routes.push(Route {
-            path: path.to_string(),
-            func: Arc::new(Box::new(func)),
-            allowed_methods: vec!["GET".to_string()],
-        })
+        self.route_with_allowed_methods(path, func, self.default_methods.clone())
+    }
+
+    /// Creates a route for `path` that `func` handles whatever the method is
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.route_any("/echo", |request| {
+    ///     String::from_utf8_lossy(&request.method).to_string().into()
+    /// });
+    /// ```
+    pub fn route_any(
+        &mut self,
+        path: &str,
+        func: impl Fn(HTTPRequest) -> HTTPResponse + Sync + Send + 'static,
+    ) {
+        self.route_with_allowed_methods(path, func, vec![ANY_METHOD.to_string()])
+    }
+
+    /// Serves the files in `folder` (and the folders in it) under `prefix`,
+    /// so `app.static_folder("/static", "./static")` serves `./static/style.css`
+    /// at `/static/style.css`
+    ///
+    /// Routes take priority over static files. If a file has a gzipped copy
+    /// next to it (`style.css.gz`), that's served instead to clients that accept gzip.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.static_folder("/static", "./static");
+    /// ```
+    pub fn static_folder(&mut self, prefix: &str, folder: impl Into<PathBuf>) {
+        self.static_folders
+            .push(StaticFolder::new(prefix, folder.into()));
+    }
+
+    /// Keeps a copy of the responses to `GET` requests for `ttl`, and sends
+    /// the copy instead of running the handler again until it expires
+    ///
+    /// Responses are cached by path, query string included. Only `200 OK`s
+    /// without a `Set-Cookie` header are, and a `Cache-Control: no-store`
+    /// on either the request or the response keeps it out of the cache.
+    /// Only turn this on if the `GET` routes don't depend on anything but the path.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// # use std::time::Duration;
+    /// let mut app = App::new("name".to_string());
+    /// app.cache_responses(Duration::from_secs(30));
+    /// ```
+    pub fn cache_responses(&mut self, ttl: Duration) {
+        self.response_cache = Some(Arc::new(ResponseCache::new(ttl)));
     }
 
     /// Same as route, but also allows you to set what methods are and aren't allowed
-    /// for this path
+    /// for this path, where `*` allows all of them
     ///
     /// Will be removed at some point and merged into the main `App.route` function
     pub fn route_with_allowed_methods(
@@ -162,33 +1408,364 @@ impl App {
     ) {
         self.routes.push(Route {
             path: path.to_string(),
-            func: Arc::new(Box::new(func)),
+            handler: Handler::Infallible(Arc::new(Box::new(func))),
             allowed_methods,
+            pattern: RoutePattern::parse(path),
         })
     }
 
-    /// Runs the (debug!) webserver
-    pub fn run(&mut self, bind_address: &str) -> CantBind {
-        let serversock_wrapped = TcpListener::bind(bind_address);
-
-        if serversock_wrapped.is_err() {
-            return CantBind;
+    /// Creates a route that handles every request `matcher` says it does,
+    /// whatever its path and method
+    ///
+    /// Matchers are tried in the order they were added, before the routes
+    /// added by path, and the first one to match handles the request.
+    /// Requests no matcher matches are routed by their path as usual.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.route_matcher(
+    ///     |request| request.header("X-Api-Version") == Some("2"),
+    ///     |_| "Version 2".into(),
+    /// );
+    /// ```
+    pub fn route_matcher(
+        &mut self,
+        matcher: impl Fn(&HTTPRequest) -> bool + Sync + Send + 'static,
+        func: impl Fn(HTTPRequest) -> HTTPResponse + Sync + Send + 'static,
+    ) {
+        let route = Route {
+            // only used to tell the route apart in logs
+            path: format!("(matcher {})", self.matched_routes.len() + 1),
+            handler: Handler::Infallible(Arc::new(Box::new(func))),
+            allowed_methods: Vec::new(),
+            pattern: None,
         };
-        let serversock = serversock_wrapped.unwrap();
+        self.matched_routes.push((Arc::new(matcher), route));
+    }
 
-        println!("OK. Server active on addres: {}", bind_address);
+    /// Sets a handler for every request no route matches, instead of
+    /// answering them with `404 Not Found` or `405 Method Not Allowed`,
+    /// like for a single-page app or a proxy
+    ///
+    /// Routes, static files and the `!404` and `!405` routes are all
+    /// tried before it.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.route("/api/status", |_| "OK".into());
+    /// // everything else gets the app's page, which does its own routing
+    /// app.fallback(|_| "<div id=\"app\"></div>".into());
+    /// ```
+    pub fn fallback(&mut self, func: impl Fn(HTTPRequest) -> HTTPResponse + Sync + Send + 'static) {
+        self.fallback = Some(Route {
+            // only used to tell the route apart in logs
+            path: "(fallback)".to_string(),
+            handler: Handler::Infallible(Arc::new(Box::new(func))),
+            allowed_methods: Vec::new(),
+            pattern: None,
+        });
+    }
 
-        loop {
-            // await for a client
-            let mut client = serversock.accept();
-            if client.is_ok() {
-                let request = HTTPRequest::read_http_request(&mut client.as_mut().unwrap().0);
-                if request.is_err() {
-                    println!("Can't read request... {:?}", request.unwrap_err());
+    /// Creates a route for `path` whose `func` can fail, turning the errors
+    /// it returns into responses with the error mapper (see `App::error_mapper`)
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.try_route("/number", |request| {
+    ///     let number: i32 = String::from_utf8(request.content)?.parse()?;
+    ///     Ok::<_, Box<dyn std::error::Error + Send + Sync>>((&*number.to_string()).into())
+    /// });
+    /// ```
+    pub fn try_route<E: Into<HandlerError>>(
+        &mut self,
+        path: &str,
+        func: impl Fn(HTTPRequest) -> Result<HTTPResponse, E> + Sync + Send + 'static,
+    ) {
+        self.try_route_with_allowed_methods(path, func, self.default_methods.clone())
+    }
+
+    /// Same as try_route, but also allows you to set what methods are and aren't allowed
+    /// for this path
+    pub fn try_route_with_allowed_methods<E: Into<HandlerError>>(
+        &mut self,
+        path: &str,
+        func: impl Fn(HTTPRequest) -> Result<HTTPResponse, E> + Sync + Send + 'static,
+        allowed_methods: Vec<String>,
+    ) {
+        self.routes.push(Route {
+            path: path.to_string(),
+            handler: Handler::Fallible(Arc::new(Box::new(move |request| {
+                func(request).map_err(Into::into)
+            }))),
+            allowed_methods,
+            pattern: RoutePattern::parse(path),
+        })
+    }
+
+    /// Gets a handle that shuts down the servers started with `App::serve`
+    /// on this app (or a clone of it)
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            state: Arc::clone(&self.server),
+        }
+    }
+
+    /// Serves clients connecting to `listener` until the app is shut down
+    /// (see `App::shutdown_handle`)
+    ///
+    /// Returns as soon as it stops accepting connections, while the requests
+    /// that were already being handled finish in the background.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::flask::App;
+    /// # use std::{net::TcpListener, thread, time::Duration};
+    /// let app = App::new("name".to_string());
+    /// let shutdown = app.shutdown_handle();
+    /// let listener = TcpListener::bind("127.0.0.1:5000").unwrap();
+    /// let server = thread::spawn(move || app.serve(listener));
+    ///
+    /// // later, give the requests being handled up to 10 seconds to finish
+    /// let unfinished = shutdown.shutdown(Duration::from_secs(10));
+    /// server.join().unwrap();
+    /// ```
+    pub fn serve(&self, listener: TcpListener) {
+        self.accept_connections(listener, true)
+    }
+
+    /// Like `App::serve`, but handles each connection on this thread, one
+    /// after the other, instead of starting a thread for each
+    ///
+    /// Requests are handled in the order their connections were accepted,
+    /// which makes for simpler tests and less overhead on small devices.
+    /// A client that keeps its connection alive holds up the next one until
+    /// it closes it or goes quiet for a few seconds.
+    pub fn serve_single_threaded(&self, listener: TcpListener) {
+        self.accept_connections(listener, false)
+    }
+
+    /// Logs that accepting a connection failed with `error`, and waits
+    /// as long as `backoff` says to before the next try
+    ///
+    /// The accept loops never stop because of an error, as most (like
+    /// running out of file descriptors) go away on their own.
+    pub(crate) fn accept_failed(&self, error: &std::io::Error, backoff: &mut AcceptBackoff) {
+        let wait = backoff.failed(error);
+        if wait.is_zero() {
+            self.logger
+                .warn(&format!("Couldn't accept a connection: {}", error));
+            return;
+        }
+        self.logger.error(&format!(
+            "Couldn't accept a connection: {}, trying again in {:?}",
+            error, wait
+        ));
+        thread::sleep(wait);
+    }
+
+    /// Accepts connections on `listener` until the app is shut down, handling
+    /// each on a thread of its own if `threaded`, otherwise on this one
+    fn accept_connections(&self, listener: TcpListener, threaded: bool) {
+        // accept doesn't block, so the loop can notice a shutdown
+        if let Err(why) = listener.set_nonblocking(true) {
+            self.logger
+                .error(&format!("Can't check for shutdowns: {:?}", why));
+            return;
+        }
+        let app = Arc::new(self.clone());
+        let mut backoff = AcceptBackoff::default();
+        while !self.server.is_shutting_down() {
+            let mut client = match listener.accept() {
+                Ok((client, _)) => client,
+                Err(why) if why.kind() == std::io::ErrorKind::WouldBlock => {
+                    thread::sleep(SHUTDOWN_POLL_INTERVAL);
                     continue;
-                };
-                self.handle(request.unwrap(), client.unwrap().0);
+                }
+                Err(why) => {
+                    self.accept_failed(&why, &mut backoff);
+                    continue;
+                }
+            };
+            backoff.succeeded();
+            // some platforms pass non-blocking on to accepted connections
+            let _ = client.set_nonblocking(false);
+            let _ = client.set_read_timeout(Some(KEEP_ALIVE_TIMEOUT));
+            if !threaded {
+                self.handle_connection(&mut client);
+                continue;
+            }
+            let app = Arc::clone(&app);
+            thread::spawn(move || app.handle_connection(&mut client));
+        }
+    }
+
+    /// Runs the (debug!) webserver, handling every connection on this thread
+    /// (see `App::serve_single_threaded`)
+    ///
+    /// Unlike `App::run`, this returns once the app is shut down
+    /// (see `App::shutdown_handle`).
+    pub fn run_single_threaded(
+        &mut self,
+        bind_address: impl ToSocketAddrs,
+    ) -> Result<(), CantBind> {
+        let listener = self.bind(bind_address)?;
+        self.serve_single_threaded(listener);
+        Ok(())
+    }
+
+    /// Binds a listener to `bind_address`, and logs where the server is
+    fn bind(&self, bind_address: impl ToSocketAddrs) -> Result<TcpListener, CantBind> {
+        let listener = match TcpListener::bind(bind_address) {
+            Ok(listener) => listener,
+            Err(_) => return Err(CantBind),
+        };
+        match listener.local_addr() {
+            Ok(address) => self
+                .logger
+                .info(&format!("OK. Server active on address: {}", address)),
+            Err(_) => self.logger.info("OK. Server active"),
+        }
+        Ok(listener)
+    }
+
+    /// Runs the (debug!) webserver
+    ///
+    /// `bind_address` can be anything a `TcpListener` can bind to, like
+    /// `"0.0.0.0:5000"` or an IPv6 address in brackets like `"[::]:5000"`
+    /// (which on most systems takes IPv4 connections too). To listen on
+    /// more than one address, use `App::run_on`.
+    ///
+    /// This only returns if it can't bind to `bind_address`; to be able to
+    /// shut the server down, use `App::serve`.
+    pub fn run(&mut self, bind_address: impl ToSocketAddrs) -> CantBind {
+        match self.bind(bind_address) {
+            Ok(listener) => self.run_listeners(vec![listener]),
+            Err(cant_bind) => cant_bind,
+        }
+    }
+
+    /// The address `App::run_from_env` binds to: `RF_BIND` if it's set,
+    /// otherwise every interface on `PORT` if that's set, otherwise
+    /// `127.0.0.1:5000`
+    ///
+    /// Fails with `InvalidConfig` if `RF_BIND` isn't an address with a port
+    /// (like `0.0.0.0:8000` or `[::]:8000`), or `PORT` isn't a port number.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::flask::App;
+    /// // with neither variable set
+    /// # std::env::remove_var("RF_BIND");
+    /// # std::env::remove_var("PORT");
+    /// assert_eq!(App::bind_address_from_env().unwrap(), "127.0.0.1:5000");
+    /// ```
+    pub fn bind_address_from_env() -> Result<String, RustedFlaskError> {
+        if let Some(bind) = std::env::var_os(BIND_VAR) {
+            let bind = bind.to_string_lossy();
+            if bind.to_socket_addrs().is_err() {
+                return Err(RustedFlaskError::InvalidConfig(format!(
+                    "{} is set to {:?}, which isn't an address with a port",
+                    BIND_VAR, bind
+                )));
             }
+            return Ok(bind.into_owned());
         }
+        if let Some(port) = std::env::var_os(PORT_VAR) {
+            let port = port.to_string_lossy();
+            return match port.trim().parse::<u16>() {
+                Ok(port) => Ok(format!("0.0.0.0:{}", port)),
+                Err(_) => Err(RustedFlaskError::InvalidConfig(format!(
+                    "{} is set to {:?}, which isn't a port number",
+                    PORT_VAR, port
+                ))),
+            };
+        }
+        Ok(DEFAULT_BIND_ADDRESS.to_string())
+    }
+
+    /// Runs the (debug!) webserver on the address the environment gives
+    /// (see `App::bind_address_from_env`)
+    ///
+    /// Like `App::run`, this only returns if something went wrong: the
+    /// address is invalid, or it can't be bound to.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// // PORT=8000
+    /// eprintln!("{}", app.run_from_env());
+    /// ```
+    pub fn run_from_env(&mut self) -> RustedFlaskError {
+        match App::bind_address_from_env() {
+            Ok(bind_address) => self.run(bind_address.as_str()).into(),
+            Err(why) => why,
+        }
+    }
+
+    /// Runs the (debug!) webserver on every one of `bind_addresses` at once,
+    /// like several interfaces or an IPv4 and an IPv6 address
+    ///
+    /// Each address gets a thread of its own accepting connections.
+    /// This only returns if it can't bind to one of them, in which case
+    /// it doesn't listen on any.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::flask::App;
+    /// let mut app = App::new("name".to_string());
+    /// app.run_on(&["127.0.0.1:5000", "[::1]:5000"]);
+    /// ```
+    pub fn run_on(&mut self, bind_addresses: &[&str]) -> CantBind {
+        let mut listeners = Vec::new();
+        for bind_address in bind_addresses {
+            match self.bind(bind_address) {
+                Ok(listener) => listeners.push(listener),
+                Err(cant_bind) => return cant_bind,
+            }
+        }
+        self.run_listeners(listeners)
+    }
+
+    /// Accepts connections on all of `listeners` forever, or returns
+    /// `CantBind` if there are none
+    fn run_listeners(&self, mut listeners: Vec<TcpListener>) -> CantBind {
+        let last = match listeners.pop() {
+            Some(last) => last,
+            None => return CantBind,
+        };
+        let app = Arc::new(self.clone());
+        for listener in listeners {
+            let app = Arc::clone(&app);
+            thread::spawn(move || accept_forever(&app, &listener));
+        }
+        accept_forever(&app, &last)
+    }
+}
+
+/// Accepts connections on `listener` for `app`, handling each on a
+/// thread of its own, and never stops
+fn accept_forever(app: &Arc<App>, listener: &TcpListener) -> ! {
+    let mut backoff = AcceptBackoff::default();
+    loop {
+        // await for a client
+        let mut client = match listener.accept() {
+            Ok((client, _)) => client,
+            Err(why) => {
+                app.accept_failed(&why, &mut backoff);
+                continue;
+            }
+        };
+        backoff.succeeded();
+        // don't wait forever on kept alive connections that went quiet
+        let _ = client.set_read_timeout(Some(KEEP_ALIVE_TIMEOUT));
+        let app = Arc::clone(app);
+        thread::spawn(move || app.handle_connection(&mut client));
     }
 }
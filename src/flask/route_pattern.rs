@@ -0,0 +1,66 @@
+//! Route paths with parameters in them, like `/user/<id>`
+
+use std::collections::HashMap;
+
+/// A segment of a route's path
+#[derive(Clone, Debug)]
+enum Segment {
+    /// A segment that has to be exactly this
+    Fixed(String),
+    /// Any (non-empty) segment, captured under this name
+    Parameter(String),
+}
+
+/// The path of a route with parameters in it, like `/post/<year>/<slug>`
+#[derive(Clone, Debug)]
+pub(crate) struct RoutePattern {
+    segments: Vec<Segment>,
+}
+
+impl RoutePattern {
+    /// Parses `path`, or gives `None` if it doesn't have any parameters
+    pub(crate) fn parse(path: &str) -> Option<RoutePattern> {
+        let segments: Vec<Segment> = path
+            .split('/')
+            .map(|segment| match segment.strip_prefix('<') {
+                Some(name) if name.len() > 1 && name.ends_with('>') => {
+                    Segment::Parameter(name[..name.len() - 1].to_string())
+                }
+                _ => Segment::Fixed(segment.to_string()),
+            })
+            .collect();
+        if !segments
+            .iter()
+            .any(|segment| matches!(segment, Segment::Parameter(_)))
+        {
+            return None;
+        }
+        Some(RoutePattern { segments })
+    }
+
+    /// The parameters captured from `path` (without its query string),
+    /// if it matches
+    ///
+    /// `path` is expected to be decoded already, apart from the `%3F`s
+    /// that `percent_decode_path` keeps, which are decoded here.
+    pub(crate) fn captures(&self, path: &str) -> Option<HashMap<String, String>> {
+        let path = path.split('?').next().unwrap_or("");
+        let mut parts = path.split('/');
+        let mut captured = HashMap::new();
+        for segment in &self.segments {
+            let part = parts.next()?;
+            match segment {
+                Segment::Fixed(fixed) if fixed == part => {}
+                Segment::Parameter(name) if !part.is_empty() => {
+                    let value = part.replace("%3F", "?").replace("%3f", "?");
+                    captured.insert(name.clone(), value);
+                }
+                _ => return None,
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(captured),
+        }
+    }
+}
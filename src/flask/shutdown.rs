@@ -0,0 +1,88 @@
+//! Stopping a server, while letting the requests it's in the middle of finish
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+/// What a server and the handles to shut it down share
+#[derive(Default)]
+pub(crate) struct ServerState {
+    shutting_down: AtomicBool,
+    /// How many requests are being handled right now
+    in_flight: Mutex<usize>,
+    /// Notified whenever a request is done
+    request_done: Condvar,
+}
+
+impl ServerState {
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    fn in_flight(&self) -> MutexGuard<'_, usize> {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped
+    pub(crate) fn start_request(&self) -> InFlightRequest<'_> {
+        *self.in_flight() += 1;
+        InFlightRequest { state: self }
+    }
+}
+
+/// A request that's being handled (see `ServerState::start_request`)
+pub(crate) struct InFlightRequest<'a> {
+    state: &'a ServerState,
+}
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        *self.state.in_flight() -= 1;
+        self.state.request_done.notify_all();
+    }
+}
+
+/// Shuts down a server started with `App::serve`, from any thread
+/// (see `App::shutdown_handle`)
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    pub(crate) state: Arc<ServerState>,
+}
+
+impl ShutdownHandle {
+    /// Stops the server from accepting connections, then waits up to
+    /// `drain_timeout` for the requests it's handling to finish
+    ///
+    /// Returns how many requests were still being handled when the timeout
+    /// ran out, which is 0 if they all finished. Connections that are kept
+    /// alive are closed after the request they're on.
+    pub fn shutdown(&self, drain_timeout: Duration) -> usize {
+        self.state.shutting_down.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + drain_timeout;
+        let mut in_flight = self.state.in_flight();
+        while *in_flight > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            in_flight = self
+                .state
+                .request_done
+                .wait_timeout(in_flight, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+        *in_flight
+    }
+
+    /// How many requests the server is handling right now
+    pub fn in_flight(&self) -> usize {
+        *self.state.in_flight()
+    }
+}
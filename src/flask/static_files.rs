@@ -0,0 +1,126 @@
+//! Serving files from a folder, like stylesheets and images
+
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::core::http::{headers, HTTPResponse};
+
+/// A folder whose files are served under a URL prefix
+#[derive(Clone)]
+pub(crate) struct StaticFolder {
+    prefix: String,
+    folder: PathBuf,
+}
+
+impl StaticFolder {
+    pub(crate) fn new(prefix: &str, folder: PathBuf) -> StaticFolder {
+        StaticFolder {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            folder,
+        }
+    }
+
+    /// The file in this folder that `path` points to, if there is one
+    pub(crate) fn file_for(&self, path: &str) -> Option<PathBuf> {
+        let path = match path.find('?') {
+            Some(query_start) => &path[..query_start],
+            None => path,
+        };
+        let relative = path.strip_prefix(&self.prefix)?.strip_prefix('/')?;
+        let mut file = self.folder.clone();
+        for segment in relative.split('/') {
+            // paths are normalized before they get here, but make sure
+            // nothing outside of the folder can be reached anyway
+            if segment.is_empty() || segment == "." || segment == ".." || segment.contains('\\') {
+                return None;
+            }
+            file.push(segment);
+        }
+        if file.is_file() {
+            Some(file)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether an `Accept-Encoding` header allows gzip
+fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|coding| {
+        let mut parameters = coding.split(';').map(str::trim);
+        let name = parameters.next().unwrap_or("");
+        let refused = parameters.any(|parameter| {
+            parameter
+                .strip_prefix("q=")
+                .and_then(|quality| quality.parse::<f32>().ok())
+                == Some(0.0)
+        });
+        !refused
+            && (name.eq_ignore_ascii_case("gzip")
+                || name.eq_ignore_ascii_case("x-gzip")
+                || name == "*")
+    })
+}
+
+/// The `Content-Type` of a file, going by its extension
+fn content_type(file: &Path) -> &'static str {
+    let extension = file
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html",
+        Some("css") => "text/css",
+        Some("js") | Some("mjs") => "text/javascript",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("wasm") => "application/wasm",
+        Some("pdf") => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Streams `file` as a response
+///
+/// If there's a gzipped copy of it next to it (`file.css.gz` for `file.css`)
+/// and the client accepts gzip, that's sent instead.
+pub(crate) fn file_response(
+    file: &Path,
+    accept_encoding: Option<&str>,
+) -> io::Result<HTTPResponse> {
+    let mut compressed = file.as_os_str().to_owned();
+    compressed.push(".gz");
+    let compressed = PathBuf::from(compressed);
+    let has_compressed = compressed.is_file();
+
+    let mut response = HTTPResponse::new().with_header(
+        headers::CONTENT_TYPE.to_string(),
+        content_type(file).to_string(),
+    );
+    if has_compressed {
+        // caches have to keep both versions apart
+        response = response.with_header(headers::VARY.to_string(), "Accept-Encoding".to_string());
+    }
+    let (file, gzipped) = match accept_encoding {
+        Some(accept_encoding) if has_compressed && accepts_gzip(accept_encoding) => {
+            (File::open(compressed)?, true)
+        }
+        _ => (File::open(file)?, false),
+    };
+    if gzipped {
+        response = response.with_header(headers::CONTENT_ENCODING.to_string(), "gzip".to_string());
+    }
+    let length = file.metadata()?.len();
+    Ok(response.with_body_reader(file, length))
+}
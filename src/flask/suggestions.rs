@@ -0,0 +1,41 @@
+//! Suggesting routes for paths that don't have one, for debug 404 pages
+
+/// How many edits a route can be away from the requested path and still be suggested
+const MAX_DISTANCE: usize = 3;
+
+/// How many routes are suggested at most
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The number of single character insertions, deletions and substitutions
+/// it takes to turn `a` into `b` (the Levenshtein distance)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // the distances between the part of `a` gone through so far and each prefix of `b`
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The routes in `routes` closest to `path`, closest first
+pub(crate) fn closest_routes<'a>(
+    path: &str,
+    routes: impl Iterator<Item = &'a str>,
+) -> Vec<&'a str> {
+    let mut candidates: Vec<(usize, &str)> = routes
+        .map(|route| (edit_distance(path, route), route))
+        .filter(|(distance, _)| *distance <= MAX_DISTANCE)
+        .collect();
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, route)| route)
+        .collect()
+}
@@ -0,0 +1,88 @@
+//! Sending requests straight to an app, without a server, for tests
+
+use std::collections::HashMap;
+
+use super::App;
+use crate::core::http::{headers, HTTPRequest, HTTPResponse};
+
+/// A request that's being built up to be sent to an app (see `App::test`)
+///
+/// It goes through everything a request from a client would, like method
+/// checks and error handlers, but without a connection.
+///
+/// # Examples
+/// ```
+/// # use rustedflask::flask::App;
+/// let mut app = App::new("name".to_string());
+/// app.route("/", |_| "Hello, World!".into());
+///
+/// let response = app.test().path("/").send();
+/// assert_eq!(response.content, b"Hello, World!");
+/// ```
+pub struct TestRequest<'a> {
+    app: &'a App,
+    method: String,
+    path: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl<'a> TestRequest<'a> {
+    pub(crate) fn new(app: &'a App) -> TestRequest<'a> {
+        TestRequest {
+            app,
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Sets the method of the request, which is `GET` by default
+    pub fn method(mut self, method: &str) -> TestRequest<'a> {
+        self.method = method.to_string();
+        self
+    }
+
+    /// Sets the path of the request (along with the query string, if there is one),
+    /// which is `/` by default
+    pub fn path(mut self, path: &str) -> TestRequest<'a> {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Adds a header to the request
+    pub fn header(mut self, header: &str, value: &str) -> TestRequest<'a> {
+        self.headers.insert(header.to_string(), value.to_string());
+        self
+    }
+
+    /// Sets the body of the request, along with its `Content-Length`
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> TestRequest<'a> {
+        self.body = body.into();
+        self.headers.insert(
+            headers::CONTENT_LENGTH.to_string(),
+            self.body.len().to_string(),
+        );
+        self
+    }
+
+    /// Sends the request to the app, returning its response
+    pub fn send(self) -> HTTPResponse {
+        let path = self.path.into_bytes();
+        self.app.respond(HTTPRequest {
+            method: self.method.into_bytes(),
+            raw_path: path.clone(),
+            path,
+            httptag: Box::new(b"HTTP".to_owned()),
+            httpversion: (1, 1),
+            headers: self.headers,
+            content: self.body,
+            request_id: None,
+            secure: false,
+            parsed_body: None,
+            lazy_body: None,
+            path_params: HashMap::new(),
+        })
+    }
+}
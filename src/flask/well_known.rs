@@ -0,0 +1,44 @@
+//! Files browsers and crawlers ask every site for, like `/favicon.ico`
+
+use crate::core::http::{headers, HTTPResponse};
+
+/// How long clients may cache a well-known file, in seconds (a day)
+const MAX_AGE: u32 = 24 * 60 * 60;
+
+/// A file served at a fixed path unless a route handles that path
+/// (see `App::default_favicon` and `App::robots_txt`)
+#[derive(Clone)]
+pub(crate) struct WellKnownFile {
+    pub(crate) path: &'static str,
+    content: Vec<u8>,
+    content_type: &'static str,
+}
+
+impl WellKnownFile {
+    pub(crate) fn new(path: &'static str, content: Vec<u8>, content_type: &'static str) -> Self {
+        WellKnownFile {
+            path,
+            content,
+            content_type,
+        }
+    }
+
+    /// Whether `path` (which may have a query) is this file's
+    pub(crate) fn is_for(&self, path: &str) -> bool {
+        path.split('?').next() == Some(self.path)
+    }
+
+    /// A `200 OK` response with the file in it
+    pub(crate) fn response(&self) -> HTTPResponse {
+        HTTPResponse::new()
+            .with_content(self.content.clone())
+            .with_header(
+                headers::CONTENT_TYPE.to_string(),
+                self.content_type.to_string(),
+            )
+            .with_header(
+                headers::CACHE_CONTROL.to_string(),
+                format!("public, max-age={}", MAX_AGE),
+            )
+    }
+}
@@ -0,0 +1,26 @@
+//! Helpers shared between the modules of this crate, that aren't part of
+//! its public API
+//!
+//! Everything in here is `pub(crate)`, so it can be used from anywhere in
+//! the crate without being documented for (or relied on by) users, and
+//! without needing to satisfy `missing_docs`. Helpers that more than one
+//! module needs belong here, rather than being made `pub` where they're
+//! first used. Anything users should be able to call goes in `core`,
+//! `flask` or `jinja` instead.
+
+use crate::core::http::parse_content_type;
+
+/// The media type of a `Content-Type`, without its parameters, in lowercase
+///
+/// `multipart/form-data; boundary=x` is `multipart/form-data`.
+pub(crate) fn media_type(content_type: &str) -> String {
+    parse_content_type(content_type).0
+}
+
+/// Gets the value of `parameter` from a header like
+/// `form-data; name="file"; filename="a.txt"`, without its quotes
+pub(crate) fn header_parameter(header: &str, parameter: &str) -> Option<String> {
+    parse_content_type(header)
+        .1
+        .remove(&parameter.to_ascii_lowercase())
+}
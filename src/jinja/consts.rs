@@ -13,16 +13,23 @@ macro_rules! load_regex {
     };
 }
 
-load_regex!(REPLACE, r#"\{\{ (?P<variable>.*) \}\}"#);
-
 load_regex!(INCLUDE, r#"\{% include "(?P<filename>.*)" %\}"#);
 
+load_regex!(EXTEND, r#"\{% extends "(?P<filename>.*)" %\}"#);
+
 load_regex!(
-    EXTEND,
-    r#"\{% extends "(?P<filename>.*)" %\}(?P<strip>(.|\n)*)"#
+    EXTEND_VARIABLE,
+    r"\{% extends (?P<variable>[A-Za-z_][A-Za-z0-9_]*) %\}"
 );
 
 load_regex!(
     BLOCK,
-    r"(?ms)\{% block (?P<blockname>.*) %\}\n?(?P<blockcontent>.*)\n?\{% endblock %\}"
+    r"(?ms)\{% block (?P<blockname>[A-Za-z_][A-Za-z0-9_]*) %\}\n?(?P<blockcontent>.*?)\n?\{% endblock(?: [A-Za-z_][A-Za-z0-9_]*)? %\}"
+);
+
+// the inside of a `{% for name, ... in iterable %}`, whose body is parsed
+// separately up to its `{% endfor %}`, as loops can be nested
+load_regex!(
+    FOR,
+    r"(?s)^\s*for\s+(?P<variables>[A-Za-z_][A-Za-z0-9_]*(?:\s*,\s*[A-Za-z_][A-Za-z0-9_]*)*)\s+in\s+(?P<iterable>.+?)\s*$"
 );
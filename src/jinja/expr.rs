@@ -0,0 +1,706 @@
+//! Parsing and evaluating what's inside `{{ }}`
+
+use std::{collections::HashMap, iter::Peekable, vec::IntoIter};
+
+use super::{filters, is_tests, JinjaError, JinjaFunction, JinjaValue};
+
+/// A token in an expression
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// A string literal, like `"foo"`
+    Literal(String),
+    /// A number, like `42` or `-1.5`
+    Number(String),
+    /// A variable or function name
+    Identifier(String),
+    /// `(`
+    Open,
+    /// `)`
+    Close,
+    /// `,`
+    Comma,
+    /// `~`, which joins two values together as strings
+    Tilde,
+    /// `+`
+    Plus,
+    /// `-`
+    Minus,
+    /// `*`
+    Star,
+    /// `/`
+    Slash,
+    /// `//`
+    DoubleSlash,
+    /// `%`
+    Percent,
+    /// `|`, which passes a value through a filter
+    Pipe,
+    /// `=`, in assignments like `{% with a = 1 %}`
+    Assign,
+    /// `.`, which gets an attribute or calls a method
+    Dot,
+}
+
+/// An operator that works out a value from two others
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Operator {
+    /// `~`
+    Concat,
+    /// `+`
+    Add,
+    /// `-`
+    Subtract,
+    /// `*`
+    Multiply,
+    /// `/`, which always gives a float
+    Divide,
+    /// `//`, which rounds down
+    FloorDivide,
+    /// `%`, which takes the sign of the right side (like Python)
+    Modulo,
+}
+
+impl Operator {
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::Concat => "~",
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::FloorDivide => "//",
+            Operator::Modulo => "%",
+        }
+    }
+}
+
+/// A parsed expression
+#[derive(Debug)]
+pub(crate) enum Expr {
+    /// A string or number literal
+    Literal(JinjaValue),
+    /// A variable, looked up when the expression is evaluated
+    Variable(String),
+    /// A function call, with its name and arguments
+    Call(String, Vec<Expr>),
+    /// `-value`
+    Negate(Box<Expr>),
+    /// `value.name`, like a key of a map
+    Attribute(Box<Expr>, String),
+    /// `value.name(arguments)`, like `items()` on a map
+    Method(Box<Expr>, String, Vec<Expr>),
+    /// `value | filter(arguments)`, with the filter's name and arguments
+    Filter(Box<Expr>, String, Vec<Expr>),
+    /// `value is test`, with the test's name and whether it was `is not`
+    Test(Box<Expr>, String, bool),
+    /// `left <operator> right`
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+/// Splits an expression into tokens
+fn tokenize(source: &[u8]) -> Result<Vec<Token>, JinjaError> {
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while position < source.len() {
+        let start = position;
+        match source[position] {
+            b' ' | b'\t' | b'\r' | b'\n' => position += 1,
+            b'(' => {
+                tokens.push(Token::Open);
+                position += 1;
+            }
+            b')' => {
+                tokens.push(Token::Close);
+                position += 1;
+            }
+            b',' => {
+                tokens.push(Token::Comma);
+                position += 1;
+            }
+            b'~' => {
+                tokens.push(Token::Tilde);
+                position += 1;
+            }
+            b'+' => {
+                tokens.push(Token::Plus);
+                position += 1;
+            }
+            b'-' => {
+                tokens.push(Token::Minus);
+                position += 1;
+            }
+            b'*' => {
+                tokens.push(Token::Star);
+                position += 1;
+            }
+            b'/' if source.get(position + 1) == Some(&b'/') => {
+                tokens.push(Token::DoubleSlash);
+                position += 2;
+            }
+            b'/' => {
+                tokens.push(Token::Slash);
+                position += 1;
+            }
+            b'%' => {
+                tokens.push(Token::Percent);
+                position += 1;
+            }
+            b'|' => {
+                tokens.push(Token::Pipe);
+                position += 1;
+            }
+            b'=' => {
+                tokens.push(Token::Assign);
+                position += 1;
+            }
+            b'.' => {
+                tokens.push(Token::Dot);
+                position += 1;
+            }
+            quote @ (b'"' | b'\'') => {
+                let length = match source[start + 1..].iter().position(|&byte| byte == quote) {
+                    Some(length) => length,
+                    None => return Err(JinjaError::SyntaxError("Unclosed string literal".into())),
+                };
+                position += length + 2;
+                tokens.push(Token::Literal(
+                    String::from_utf8_lossy(&source[start + 1..position - 1]).to_string(),
+                ));
+            }
+            b'0'..=b'9' => {
+                while position < source.len()
+                    && (source[position].is_ascii_digit() || source[position] == b'.')
+                {
+                    position += 1;
+                }
+                tokens.push(Token::Number(
+                    String::from_utf8_lossy(&source[start..position]).to_string(),
+                ));
+            }
+            byte if byte.is_ascii_alphabetic() || byte == b'_' => {
+                while position < source.len()
+                    && (source[position].is_ascii_alphanumeric() || source[position] == b'_')
+                {
+                    position += 1;
+                }
+                tokens.push(Token::Identifier(
+                    String::from_utf8_lossy(&source[start..position]).to_string(),
+                ));
+            }
+            other => {
+                return Err(JinjaError::SyntaxError(format!(
+                    "Unexpected character \"{}\" in expression",
+                    char::from(other)
+                )))
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens of an expression,
+/// or of a statement like `with a = 1`
+pub(crate) struct Parser {
+    tokens: Peekable<IntoIter<Token>>,
+}
+
+impl Parser {
+    /// Makes a parser for `source`
+    pub(crate) fn new(source: &str) -> Result<Parser, JinjaError> {
+        Ok(Parser {
+            tokens: tokenize(source.as_bytes())?.into_iter().peekable(),
+        })
+    }
+
+    /// Parses an expression
+    pub(crate) fn expression(&mut self) -> Result<Expr, JinjaError> {
+        self.sum()
+    }
+
+    /// Parses a name, like the keyword a statement starts with
+    pub(crate) fn identifier(&mut self) -> Result<String, JinjaError> {
+        match self.next() {
+            Some(Token::Identifier(name)) => Ok(name),
+            _ => Err(JinjaError::SyntaxError("Expected a name".into())),
+        }
+    }
+
+    /// Parses comma separated assignments, like `a = 1, b = c`
+    pub(crate) fn assignments(&mut self) -> Result<Vec<(String, Expr)>, JinjaError> {
+        let mut assignments = Vec::new();
+        loop {
+            let name = self.identifier()?;
+            if self.next() != Some(Token::Assign) {
+                return Err(JinjaError::SyntaxError(format!(
+                    "Expected = after \"{}\"",
+                    name
+                )));
+            }
+            assignments.push((name, self.expression()?));
+            if self.peek() != Some(&Token::Comma) {
+                return Ok(assignments);
+            }
+            self.next();
+        }
+    }
+
+    /// Whether everything has been parsed
+    pub(crate) fn is_at_end(&mut self) -> bool {
+        self.peek().is_none()
+    }
+
+    /// Fails if there's anything left to parse
+    pub(crate) fn end(&mut self) -> Result<(), JinjaError> {
+        if self.is_at_end() {
+            Ok(())
+        } else {
+            Err(JinjaError::SyntaxError(
+                "Unexpected characters after the end of the expression".into(),
+            ))
+        }
+    }
+
+    fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.tokens.next()
+    }
+
+    /// `sum := concat (('+' | '-') concat)*`
+    fn sum(&mut self) -> Result<Expr, JinjaError> {
+        let mut expression = self.concat()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Plus) => Operator::Add,
+                Some(Token::Minus) => Operator::Subtract,
+                _ => return Ok(expression),
+            };
+            self.next();
+            let right = self.concat()?;
+            expression = Expr::Binary(operator, Box::new(expression), Box::new(right));
+        }
+    }
+
+    /// `concat := product ('~' product)*`
+    fn concat(&mut self) -> Result<Expr, JinjaError> {
+        let mut expression = self.product()?;
+        while self.peek() == Some(&Token::Tilde) {
+            self.next();
+            let right = self.product()?;
+            expression = Expr::Binary(Operator::Concat, Box::new(expression), Box::new(right));
+        }
+        Ok(expression)
+    }
+
+    /// `product := unary (('*' | '/' | '//' | '%') unary)*`
+    fn product(&mut self) -> Result<Expr, JinjaError> {
+        let mut expression = self.unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Star) => Operator::Multiply,
+                Some(Token::Slash) => Operator::Divide,
+                Some(Token::DoubleSlash) => Operator::FloorDivide,
+                Some(Token::Percent) => Operator::Modulo,
+                _ => return Ok(expression),
+            };
+            self.next();
+            let right = self.unary()?;
+            expression = Expr::Binary(operator, Box::new(expression), Box::new(right));
+        }
+    }
+
+    /// `unary := '-' unary | filtered`
+    fn unary(&mut self) -> Result<Expr, JinjaError> {
+        if self.peek() == Some(&Token::Minus) {
+            self.next();
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        self.filtered()
+    }
+
+    /// `filtered := postfix ('|' name ['(' arguments ')'] | 'is' ['not'] name)*`
+    fn filtered(&mut self) -> Result<Expr, JinjaError> {
+        let mut expression = self.postfix()?;
+        loop {
+            match self.peek() {
+                Some(Token::Pipe) => {}
+                Some(Token::Identifier(name)) if name == "is" => {
+                    self.next();
+                    let negated =
+                        matches!(self.peek(), Some(Token::Identifier(name)) if name == "not");
+                    if negated {
+                        self.next();
+                    }
+                    let name = match self.next() {
+                        Some(Token::Identifier(name)) => name,
+                        _ => {
+                            return Err(JinjaError::SyntaxError(
+                                "Expected a test name after is".into(),
+                            ))
+                        }
+                    };
+                    expression = Expr::Test(Box::new(expression), name, negated);
+                    continue;
+                }
+                _ => return Ok(expression),
+            }
+            self.next();
+            let name = match self.next() {
+                Some(Token::Identifier(name)) => name,
+                _ => {
+                    return Err(JinjaError::SyntaxError(
+                        "Expected a filter name after |".into(),
+                    ))
+                }
+            };
+            let arguments = if self.peek() == Some(&Token::Open) {
+                self.next();
+                self.arguments()?
+            } else {
+                Vec::new()
+            };
+            expression = Expr::Filter(Box::new(expression), name, arguments);
+        }
+    }
+
+    /// `postfix := primary ('.' name ['(' arguments ')'])*`
+    fn postfix(&mut self) -> Result<Expr, JinjaError> {
+        let mut expression = self.primary()?;
+        while self.peek() == Some(&Token::Dot) {
+            self.next();
+            let name = match self.next() {
+                Some(Token::Identifier(name)) => name,
+                _ => return Err(JinjaError::SyntaxError("Expected a name after .".into())),
+            };
+            expression = if self.peek() == Some(&Token::Open) {
+                self.next();
+                Expr::Method(Box::new(expression), name, self.arguments()?)
+            } else {
+                Expr::Attribute(Box::new(expression), name)
+            };
+        }
+        Ok(expression)
+    }
+
+    /// `primary := literal | number | name | name '(' arguments ')' | '(' sum ')'`
+    fn primary(&mut self) -> Result<Expr, JinjaError> {
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(Expr::Literal(JinjaValue::Str(value))),
+            Some(Token::Number(number)) => match number.parse::<i64>() {
+                Ok(value) => Ok(Expr::Literal(JinjaValue::Int(value))),
+                Err(_) => match number.parse::<f64>() {
+                    Ok(value) => Ok(Expr::Literal(JinjaValue::Float(value))),
+                    Err(_) => Err(JinjaError::SyntaxError(format!(
+                        "\"{}\" isn't a valid number",
+                        number
+                    ))),
+                },
+            },
+            Some(Token::Identifier(name)) => {
+                // spelled both ways, like Jinja does
+                let constant = match &*name {
+                    "true" | "True" => Some(JinjaValue::Bool(true)),
+                    "false" | "False" => Some(JinjaValue::Bool(false)),
+                    "none" | "None" => Some(JinjaValue::None),
+                    _ => None,
+                };
+                if let Some(constant) = constant {
+                    Ok(Expr::Literal(constant))
+                } else if self.peek() == Some(&Token::Open) {
+                    self.next();
+                    Ok(Expr::Call(name, self.arguments()?))
+                } else {
+                    Ok(Expr::Variable(name))
+                }
+            }
+            Some(Token::Open) => {
+                let expression = self.sum()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expression),
+                    _ => Err(JinjaError::SyntaxError("Unclosed parentheses".into())),
+                }
+            }
+            Some(Token::Comma) => Err(JinjaError::SyntaxError(
+                "Expected a value, but a comma was found".into(),
+            )),
+            Some(other) => Err(JinjaError::SyntaxError(format!(
+                "Expected a value, but {:?} was found",
+                other
+            ))),
+            None => Err(JinjaError::SyntaxError(
+                "Expected a value, but the expression ended".into(),
+            )),
+        }
+    }
+
+    /// The arguments of a function call, after the opening parentheses,
+    /// up to and including the closing parentheses
+    fn arguments(&mut self) -> Result<Vec<Expr>, JinjaError> {
+        let mut arguments = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::Close) => {
+                    self.next();
+                    return Ok(arguments);
+                }
+                None => return Err(JinjaError::SyntaxError("Unclosed parentheses".into())),
+                _ => {}
+            }
+            arguments.push(self.sum()?);
+            match self.next() {
+                Some(Token::Comma) => {}
+                Some(Token::Close) => return Ok(arguments),
+                None => return Err(JinjaError::SyntaxError("Unclosed parentheses".into())),
+                Some(_) => {
+                    return Err(JinjaError::SyntaxError(
+                        "Expected a comma or closing parentheses after an argument".into(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Parses the contents of a `{{ }}`
+pub(crate) fn parse(source: &str) -> Result<Expr, JinjaError> {
+    let mut parser = Parser::new(source)?;
+    let expression = parser.expression()?;
+    parser.end()?;
+    Ok(expression)
+}
+
+/// Something an expression can call by name (see `Expr::for_each_call`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Callable {
+    /// A function passed to the template, like `price("apple")`
+    Function,
+    /// A filter, like `name | upper`
+    Filter,
+}
+
+impl Expr {
+    /// Calls `check` with every function and filter this expression calls,
+    /// stopping at the first error it returns
+    pub(crate) fn for_each_call(
+        &self,
+        check: &mut impl FnMut(Callable, &str) -> Result<(), JinjaError>,
+    ) -> Result<(), JinjaError> {
+        match self {
+            Expr::Literal(_) | Expr::Variable(_) => Ok(()),
+            Expr::Call(name, arguments) => {
+                check(Callable::Function, name)?;
+                arguments
+                    .iter()
+                    .try_for_each(|argument| argument.for_each_call(check))
+            }
+            Expr::Filter(value, name, arguments) => {
+                value.for_each_call(check)?;
+                check(Callable::Filter, name)?;
+                arguments
+                    .iter()
+                    .try_for_each(|argument| argument.for_each_call(check))
+            }
+            Expr::Method(value, _, arguments) => {
+                value.for_each_call(check)?;
+                arguments
+                    .iter()
+                    .try_for_each(|argument| argument.for_each_call(check))
+            }
+            Expr::Negate(value) | Expr::Attribute(value, _) | Expr::Test(value, _, _) => {
+                value.for_each_call(check)
+            }
+            Expr::Binary(_, left, right) => {
+                left.for_each_call(check)?;
+                right.for_each_call(check)
+            }
+        }
+    }
+
+    /// Works out the value of this expression
+    pub(crate) fn evaluate(
+        &self,
+        variables: &HashMap<&str, JinjaValue>,
+        functions: Option<&HashMap<&str, JinjaFunction>>,
+    ) -> Result<JinjaValue, JinjaError> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Variable(name) => match variables.get(&**name) {
+                None => Err(JinjaError::NoSuchVariable(name.clone())),
+                Some(value) => Ok(value.clone()),
+            },
+            Expr::Call(name, arguments) => {
+                let function = match functions.and_then(|functions| functions.get(&**name)) {
+                    None => return Err(JinjaError::NoSuchFunction(name.clone())),
+                    Some(function) => function,
+                };
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(variables, functions))
+                    .collect::<Result<Vec<JinjaValue>, JinjaError>>()?;
+                function(arguments)
+            }
+            Expr::Negate(value) => match value.evaluate(variables, functions)? {
+                JinjaValue::Int(value) => match value.checked_neg() {
+                    Some(value) => Ok(JinjaValue::Int(value)),
+                    None => Err(JinjaError::Other("Integer overflow".into())),
+                },
+                JinjaValue::Float(value) => Ok(JinjaValue::Float(-value)),
+                other => Err(JinjaError::TypeError(format!(
+                    "Can't negate {}",
+                    other.type_name()
+                ))),
+            },
+            Expr::Attribute(value, name) => match value.evaluate(variables, functions)? {
+                // a missing key is undefined, like a missing variable
+                JinjaValue::Map(mut values) => values.remove(name).ok_or_else(|| {
+                    JinjaError::NoSuchVariable(match &**value {
+                        Expr::Variable(map) => format!("{}.{}", map, name),
+                        _ => name.clone(),
+                    })
+                }),
+                other => Err(JinjaError::TypeError(format!(
+                    "{} has no attribute \"{}\"",
+                    other.type_name(),
+                    name
+                ))),
+            },
+            Expr::Method(value, name, arguments) => {
+                let value = value.evaluate(variables, functions)?;
+                if !arguments.is_empty() {
+                    return Err(JinjaError::TypeError(format!(
+                        "{}() takes no arguments",
+                        name
+                    )));
+                }
+                call_method(value, name)
+            }
+            Expr::Filter(value, name, arguments) => {
+                // evaluated before the arguments, so an undefined
+                // variable reaches the filter instead of failing here
+                let value = value.evaluate(variables, functions);
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(variables, functions))
+                    .collect::<Result<Vec<JinjaValue>, JinjaError>>()?;
+                filters::apply(name, value, arguments)
+            }
+            Expr::Test(value, name, negated) => {
+                let passed = is_tests::apply(name, value.evaluate(variables, functions))?;
+                Ok(JinjaValue::Bool(passed != *negated))
+            }
+            Expr::Binary(operator, left, right) => apply(
+                *operator,
+                left.evaluate(variables, functions)?,
+                right.evaluate(variables, functions)?,
+            ),
+        }
+    }
+}
+
+/// Calls the method `name` of `value`, like Python's `dict.items()`
+fn call_method(value: JinjaValue, name: &str) -> Result<JinjaValue, JinjaError> {
+    match (value, name) {
+        (JinjaValue::Map(values), "items") => Ok(JinjaValue::List(
+            values
+                .into_iter()
+                .map(|(key, value)| JinjaValue::List(vec![JinjaValue::Str(key), value]))
+                .collect(),
+        )),
+        (JinjaValue::Map(values), "keys") => Ok(JinjaValue::List(
+            values.into_keys().map(JinjaValue::Str).collect(),
+        )),
+        (JinjaValue::Map(values), "values") => Ok(JinjaValue::List(values.into_values().collect())),
+        (other, name) => Err(JinjaError::TypeError(format!(
+            "{} has no method \"{}\"",
+            other.type_name(),
+            name
+        ))),
+    }
+}
+
+/// Works out `left <operator> right`, following Python's rules for
+/// mixing integers and floats
+fn apply(
+    operator: Operator,
+    left: JinjaValue,
+    right: JinjaValue,
+) -> Result<JinjaValue, JinjaError> {
+    if let Operator::Concat = operator {
+        return Ok(JinjaValue::Str(left.to_string() + &right.to_string()));
+    }
+    match (left, right) {
+        (JinjaValue::Int(left), JinjaValue::Int(right)) => apply_int(operator, left, right),
+        (JinjaValue::Int(left), JinjaValue::Float(right)) => {
+            apply_float(operator, left as f64, right)
+        }
+        (JinjaValue::Float(left), JinjaValue::Int(right)) => {
+            apply_float(operator, left, right as f64)
+        }
+        (JinjaValue::Float(left), JinjaValue::Float(right)) => apply_float(operator, left, right),
+        (left, right) => Err(JinjaError::TypeError(format!(
+            "Unsupported operand types for {}: {} and {}",
+            operator.symbol(),
+            left.type_name(),
+            right.type_name()
+        ))),
+    }
+}
+
+fn apply_int(operator: Operator, left: i64, right: i64) -> Result<JinjaValue, JinjaError> {
+    if right == 0 {
+        if let Operator::Divide | Operator::FloorDivide | Operator::Modulo = operator {
+            return Err(JinjaError::Other("Division by zero".into()));
+        }
+    }
+    let result = match operator {
+        Operator::Add => left.checked_add(right),
+        Operator::Subtract => left.checked_sub(right),
+        Operator::Multiply => left.checked_mul(right),
+        Operator::Divide => return Ok(JinjaValue::Float(left as f64 / right as f64)),
+        Operator::FloorDivide => left.checked_div(right).map(|quotient| {
+            if left % right != 0 && (left < 0) != (right < 0) {
+                quotient - 1
+            } else {
+                quotient
+            }
+        }),
+        Operator::Modulo => left.checked_rem(right).map(|remainder| {
+            if remainder != 0 && (remainder < 0) != (right < 0) {
+                remainder + right
+            } else {
+                remainder
+            }
+        }),
+        Operator::Concat => unreachable!("concatenation isn't arithmetic"),
+    };
+    match result {
+        Some(result) => Ok(JinjaValue::Int(result)),
+        None => Err(JinjaError::Other("Integer overflow".into())),
+    }
+}
+
+fn apply_float(operator: Operator, left: f64, right: f64) -> Result<JinjaValue, JinjaError> {
+    if right == 0.0 {
+        if let Operator::Divide | Operator::FloorDivide | Operator::Modulo = operator {
+            return Err(JinjaError::Other("Division by zero".into()));
+        }
+    }
+    Ok(JinjaValue::Float(match operator {
+        Operator::Add => left + right,
+        Operator::Subtract => left - right,
+        Operator::Multiply => left * right,
+        Operator::Divide => left / right,
+        Operator::FloorDivide => (left / right).floor(),
+        Operator::Modulo => {
+            let remainder = left % right;
+            if remainder != 0.0 && (remainder < 0.0) != (right < 0.0) {
+                remainder + right
+            } else {
+                remainder
+            }
+        }
+        Operator::Concat => unreachable!("concatenation isn't arithmetic"),
+    }))
+}
@@ -0,0 +1,144 @@
+//! The built-in filters, like `{{ name | upper }}`
+
+use super::{JinjaError, JinjaValue};
+
+/// A built-in filter, which gets the value being filtered and
+/// the arguments it was called with
+type Filter = fn(JinjaValue, Vec<JinjaValue>) -> Result<JinjaValue, JinjaError>;
+
+/// Finds the built-in filter called `name`
+fn lookup(name: &str) -> Option<Filter> {
+    Some(match name {
+        "safe" => safe,
+        "upper" => upper,
+        "lower" => lower,
+        "capitalize" => capitalize,
+        "trim" => trim,
+        "length" | "count" => length,
+        "string" => string,
+        "abs" => abs,
+        _ => return None,
+    })
+}
+
+/// Runs the filter called `name` on `value`
+///
+/// `value` is the result of evaluating what's being filtered, so that
+/// `default` can replace an undefined variable. Every other filter passes
+/// the error on.
+pub(crate) fn apply(
+    name: &str,
+    value: Result<JinjaValue, JinjaError>,
+    arguments: Vec<JinjaValue>,
+) -> Result<JinjaValue, JinjaError> {
+    if let "default" | "d" = name {
+        return default(value, arguments);
+    }
+    let filter = match lookup(name) {
+        Some(filter) => filter,
+        None => return Err(JinjaError::NoSuchFilter(name.to_string())),
+    };
+    filter(value?, arguments)
+}
+
+/// Fails if a filter that takes no arguments was given some
+fn no_arguments(name: &str, arguments: &[JinjaValue]) -> Result<(), JinjaError> {
+    if arguments.is_empty() {
+        Ok(())
+    } else {
+        Err(JinjaError::TypeError(format!(
+            "The {} filter takes no arguments",
+            name
+        )))
+    }
+}
+
+/// `default(fallback="")`: `fallback` if the value is undefined, otherwise the value
+fn default(
+    value: Result<JinjaValue, JinjaError>,
+    arguments: Vec<JinjaValue>,
+) -> Result<JinjaValue, JinjaError> {
+    if arguments.len() > 1 {
+        return Err(JinjaError::TypeError(
+            "The default filter takes at most one argument".into(),
+        ));
+    }
+    match value {
+        Err(JinjaError::NoSuchVariable(_)) => Ok(arguments
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| JinjaValue::from(""))),
+        value => value,
+    }
+}
+
+/// Marks the value as safe to output as-is
+///
+/// Output isn't escaped yet, so this doesn't change anything.
+fn safe(value: JinjaValue, arguments: Vec<JinjaValue>) -> Result<JinjaValue, JinjaError> {
+    no_arguments("safe", &arguments)?;
+    Ok(value)
+}
+
+fn upper(value: JinjaValue, arguments: Vec<JinjaValue>) -> Result<JinjaValue, JinjaError> {
+    no_arguments("upper", &arguments)?;
+    Ok(JinjaValue::Str(value.to_string().to_uppercase()))
+}
+
+fn lower(value: JinjaValue, arguments: Vec<JinjaValue>) -> Result<JinjaValue, JinjaError> {
+    no_arguments("lower", &arguments)?;
+    Ok(JinjaValue::Str(value.to_string().to_lowercase()))
+}
+
+/// Uppercases the first character and lowercases the rest
+fn capitalize(value: JinjaValue, arguments: Vec<JinjaValue>) -> Result<JinjaValue, JinjaError> {
+    no_arguments("capitalize", &arguments)?;
+    let value = value.to_string();
+    let mut characters = value.chars();
+    Ok(JinjaValue::Str(match characters.next() {
+        Some(first) => first
+            .to_uppercase()
+            .chain(characters.flat_map(char::to_lowercase))
+            .collect(),
+        None => String::new(),
+    }))
+}
+
+fn trim(value: JinjaValue, arguments: Vec<JinjaValue>) -> Result<JinjaValue, JinjaError> {
+    no_arguments("trim", &arguments)?;
+    Ok(JinjaValue::Str(value.to_string().trim().to_string()))
+}
+
+/// The number of characters in a string, items in a list or keys in a map
+fn length(value: JinjaValue, arguments: Vec<JinjaValue>) -> Result<JinjaValue, JinjaError> {
+    no_arguments("length", &arguments)?;
+    match value {
+        JinjaValue::Str(value) => Ok(JinjaValue::Int(value.chars().count() as i64)),
+        JinjaValue::List(values) => Ok(JinjaValue::Int(values.len() as i64)),
+        JinjaValue::Map(values) => Ok(JinjaValue::Int(values.len() as i64)),
+        other => Err(JinjaError::TypeError(format!(
+            "Can't take the length of {}",
+            other.type_name()
+        ))),
+    }
+}
+
+fn string(value: JinjaValue, arguments: Vec<JinjaValue>) -> Result<JinjaValue, JinjaError> {
+    no_arguments("string", &arguments)?;
+    Ok(JinjaValue::Str(value.to_string()))
+}
+
+fn abs(value: JinjaValue, arguments: Vec<JinjaValue>) -> Result<JinjaValue, JinjaError> {
+    no_arguments("abs", &arguments)?;
+    match value {
+        JinjaValue::Int(value) => match value.checked_abs() {
+            Some(value) => Ok(JinjaValue::Int(value)),
+            None => Err(JinjaError::Other("Integer overflow".into())),
+        },
+        JinjaValue::Float(value) => Ok(JinjaValue::Float(value.abs())),
+        other => Err(JinjaError::TypeError(format!(
+            "Can't take the absolute value of {}",
+            other.type_name()
+        ))),
+    }
+}
@@ -0,0 +1,37 @@
+//! The built-in tests, like `{% if number is even %}`
+
+use super::{JinjaError, JinjaValue};
+
+/// A built-in test, which says whether the value passes it
+type Test = fn(&JinjaValue) -> bool;
+
+/// Finds the built-in test called `name`
+fn lookup(name: &str) -> Option<Test> {
+    Some(match name {
+        "none" => |value| *value == JinjaValue::None,
+        "even" => |value| matches!(value, JinjaValue::Int(value) if value % 2 == 0),
+        "odd" => |value| matches!(value, JinjaValue::Int(value) if value % 2 != 0),
+        "string" => |value| matches!(value, JinjaValue::Str(_)),
+        "number" => |value| matches!(value, JinjaValue::Int(_) | JinjaValue::Float(_)),
+        _ => return None,
+    })
+}
+
+/// Runs the test called `name` on `value`
+///
+/// Like with filters, `value` is the result of evaluating what's being
+/// tested, so that `defined` and `undefined` can check for an undefined
+/// variable. Every other test passes the error on.
+pub(crate) fn apply(name: &str, value: Result<JinjaValue, JinjaError>) -> Result<bool, JinjaError> {
+    match (name, value) {
+        ("defined", Err(JinjaError::NoSuchVariable(_))) => Ok(false),
+        ("undefined", Err(JinjaError::NoSuchVariable(_))) => Ok(true),
+        (_, Err(error)) => Err(error),
+        ("defined", Ok(_)) => Ok(true),
+        ("undefined", Ok(_)) => Ok(false),
+        (name, Ok(value)) => match lookup(name) {
+            Some(test) => Ok(test(&value)),
+            None => Err(JinjaError::Other(format!("No such test: {}", name))),
+        },
+    }
+}
@@ -1,12 +1,27 @@
 mod consts;
-
+mod expr;
+mod filters;
+mod is_tests;
+pub(crate) mod render_cache;
+mod sandbox;
+mod template;
+mod value;
+
+use regex::Captures;
 use std::{
-    collections::{HashMap, VecDeque},
-    fs::{read_to_string, File},
+    borrow::Cow,
+    collections::HashMap,
+    fs::{read_dir, read_to_string, File},
     io::Read,
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
+use render_cache::RenderCache;
+pub use sandbox::{render_template_string_sandboxed, Sandbox};
+pub use template::CompiledTemplate;
+pub use value::JinjaValue;
+
 #[macro_export]
 /// Wrap a function that requires a Jinja context,
 /// passing the context to it
@@ -31,19 +46,43 @@ macro_rules! wrap_context {
 ///     return args[0]
 /// ```
 ///
+/// Functions can be closures, so they can use state from the app, and
+/// an error they return stops the render and is returned from it.
+///
 /// # Examples
 /// ```
-/// fn func(arguments: Vec<String>) -> String {
-///     arguments[0_usize].clone()
-/// }
+/// # use rustedflask::jinja::{JinjaError, JinjaFunction, JinjaValue};
+/// # use std::{collections::HashMap, sync::Arc};
+/// let prices = HashMap::from([("apple", 2), ("pear", 3)]);
+/// let price: JinjaFunction = Arc::new(move |arguments: Vec<JinjaValue>| {
+///     let fruit = arguments.first().map(|fruit| fruit.to_string()).unwrap_or_default();
+///     match prices.get(&*fruit) {
+///         Some(price) => Ok(JinjaValue::Int(*price)),
+///         None => Err(JinjaError::Other(format!("No price for {}", fruit))),
+///     }
+/// });
 /// ```
-pub type JinjaFunction = fn(Vec<String>) -> String;
+pub type JinjaFunction =
+    Arc<dyn Fn(Vec<JinjaValue>) -> Result<JinjaValue, JinjaError> + Send + Sync>;
 
 /// An internal state for Jinja. Mostly stores cache related things
 pub struct JinjaState {
+    /// The templates that were read, as they are on disk
     file_cache: HashMap<String, String>,
+    /// The templates that were rendered, ready to be rendered again
+    compiled_cache: HashMap<String, CompiledTemplate>,
+    /// What templates rendered to, by their variables (see `cache_renders`)
+    render_cache: Option<RenderCache>,
+    /// What's done to every render's output (see `post_render`)
+    post_render: Option<PostRender>,
+    /// Where templates are read from (see `templates_folder`)
+    templates_folder: PathBuf,
 }
 
+/// Transforms the output of a template after it's rendered
+/// (see `JinjaState::post_render`)
+pub type PostRender = Arc<dyn Fn(String) -> String + Send + Sync>;
+
 /// An error from within Jinja.
 ///
 /// This should be raised as an issue
@@ -64,12 +103,22 @@ pub enum JinjaError {
     InternalJinjaError(InternalJinjaError),
     /// The template could not be found
     TemplateNotFound,
-    /// There was no such variable passed to Jinja
-    NoSuchVariable,
-    /// There was no such function passed to Jinja
-    NoSuchFunction,
+    /// There was no such variable passed to Jinja, which is named
+    ///
+    /// A missing key of a map is named with the map, like `user.email`.
+    NoSuchVariable(String),
+    /// There was no such function passed to Jinja, which is named
+    NoSuchFunction(String),
+    /// The template has no block with this name (see `render_block`)
+    NoSuchBlock(String),
+    /// The template used a filter that doesn't exist, which is named
+    NoSuchFilter(String),
+    /// The template did something its `Sandbox` doesn't allow, which is named
+    NotAllowed(String),
     /// Syntax was invalid
     SyntaxError(String),
+    /// A value of the wrong type was used, like a string in arithmetic
+    TypeError(String),
     /// The template could not be opened
     NoSuchTemplate,
     /// There were more than two parents in the template
@@ -78,16 +127,374 @@ pub enum JinjaError {
     Other(String),
 }
 
+/// Where templates are read from
+const TEMPLATES_FOLDER: &str = "./templates/";
+
+/// The environment variable the templates folder is read from, by
+/// `JinjaState::from_env` and the functions like `render_template` that
+/// don't take a `JinjaState`
+pub const TEMPLATES_FOLDER_VAR: &str = "RF_TEMPLATES";
+
+/// The folder in the `RF_TEMPLATES` environment variable, or `./templates/`
+/// if it isn't set
+///
+/// Fails if the variable isn't a folder that exists.
+fn templates_folder_from_env() -> Result<PathBuf, JinjaError> {
+    let folder = match std::env::var_os(TEMPLATES_FOLDER_VAR) {
+        Some(folder) => PathBuf::from(folder),
+        None => return Ok(PathBuf::from(TEMPLATES_FOLDER)),
+    };
+    if !folder.is_dir() {
+        return Err(JinjaError::Other(format!(
+            "{} is set to {}, which isn't a folder",
+            TEMPLATES_FOLDER_VAR,
+            folder.display()
+        )));
+    }
+    Ok(folder)
+}
+
+/// Whether `name` is a template inside of the `templates` folder, rather
+/// than a path that leads out of it
+fn is_safe_template_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('/')
+        && !name.contains(['\\', ':', '\0'])
+        && name.split('/').all(|segment| segment != "..")
+}
+
+/// Turns a `{% extends variable %}` in `template` into an extends of the
+/// template that `variable` names, so the parent can be picked when it's
+/// rendered (like for themes)
+///
+/// Fails if the variable isn't a string, or names a template outside of
+/// the `templates` folder.
+fn resolve_dynamic_extends<'t>(
+    template: &'t str,
+    variables: &HashMap<&str, JinjaValue>,
+) -> Result<Cow<'t, str>, JinjaError> {
+    let extends = match consts::EXTEND_VARIABLE.captures(template) {
+        Some(extends) => extends,
+        None => return Ok(Cow::Borrowed(template)),
+    };
+    let parent = match variables.get(&extends["variable"]) {
+        Some(JinjaValue::Str(parent)) => parent,
+        Some(other) => {
+            return Err(JinjaError::TypeError(format!(
+                "Can't extend {}, only a template name",
+                other.type_name()
+            )))
+        }
+        None => return Err(JinjaError::NoSuchVariable(extends["variable"].to_string())),
+    };
+    if !is_safe_template_name(parent) || parent.contains('"') {
+        return Err(JinjaError::Other(format!(
+            "Can't extend \"{}\", it's not in the templates folder",
+            parent
+        )));
+    }
+    let (start, end) = extends
+        .get(0)
+        .map_or((0, 0), |extends| (extends.start(), extends.end()));
+    Ok(Cow::Owned(format!(
+        "{}{{% extends \"{}\" %}}{}",
+        &template[..start],
+        parent,
+        &template[end..]
+    )))
+}
+
+/// Fills in the parent and the includes of `template`, reading the
+/// templates they name (from the `templates` folder) with `read`
+///
+/// This works on slices of `template` and the templates it reads, so each
+/// is only copied into the result once.
+fn expand(
+    template: &str,
+    mut read: impl FnMut(&str) -> Result<String, JinjaError>,
+) -> Result<String, JinjaError> {
+    let extended = match consts::EXTEND.captures(template) {
+        Some(parents) => {
+            let parent = read(&parents["filename"])?;
+            let child_blocks: HashMap<&str, &str> = consts::BLOCK
+                .captures_iter(template)
+                .filter_map(|block| {
+                    Some((
+                        block.name("blockname")?.as_str(),
+                        block.name("blockcontent")?.as_str(),
+                    ))
+                })
+                .collect();
+            // the tags are kept around the child's blocks, so they can
+            // still be found by name (see `render_block`)
+            let contents =
+                consts::BLOCK.replace_all(&parent, |block: &Captures| {
+                    match child_blocks.get(&block["blockname"]) {
+                        Some(child_block) => format!(
+                            "{{% block {} %}}{}{{% endblock %}}",
+                            &block["blockname"], child_block
+                        ),
+                        None => block[0].to_string(),
+                    }
+                });
+            let start = parents.get(0).map_or(0, |extends| extends.start());
+            // the text after `{% extends %}` only supplies blocks, so
+            // it's left out
+            let mut extended = String::with_capacity(start + contents.len());
+            extended.push_str(&template[..start]);
+            extended.push_str(&contents);
+            Cow::Owned(extended)
+        }
+        None => Cow::Borrowed(template),
+    };
+
+    let mut expanded = String::with_capacity(extended.len());
+    let mut copied_up_to = 0;
+    for entry in consts::INCLUDE.captures_iter(&extended) {
+        let include = match entry.get(0) {
+            Some(include) => include,
+            None => continue,
+        };
+        expanded.push_str(&extended[copied_up_to..include.start()]);
+        expanded.push_str(&read(&entry["filename"])?);
+        copied_up_to = include.end();
+    }
+    expanded.push_str(&extended[copied_up_to..]);
+    Ok(expanded)
+}
+
+impl Default for JinjaState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl JinjaState {
     /// Creates a new JinjaState
     pub fn new() -> Self {
         JinjaState {
             file_cache: HashMap::new(),
+            compiled_cache: HashMap::new(),
+            render_cache: None,
+            post_render: None,
+            templates_folder: PathBuf::from(TEMPLATES_FOLDER),
+        }
+    }
+
+    /// Creates a new JinjaState that reads templates from the folder in the
+    /// `RF_TEMPLATES` environment variable, or from `./templates/` if
+    /// it isn't set
+    ///
+    /// Fails if the variable isn't a folder that exists.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::jinja::JinjaState;
+    /// // RF_TEMPLATES=/srv/app/templates
+    /// let mut state = JinjaState::from_env().unwrap();
+    /// ```
+    ///
+    /// ```
+    /// # use rustedflask::jinja::{JinjaState, TEMPLATES_FOLDER_VAR};
+    /// std::env::set_var(TEMPLATES_FOLDER_VAR, "templates/missing");
+    /// assert!(JinjaState::from_env().is_err());
+    /// ```
+    pub fn from_env() -> Result<Self, JinjaError> {
+        let mut state = JinjaState::new();
+        state.templates_folder(templates_folder_from_env()?);
+        Ok(state)
+    }
+
+    /// Reads templates from `folder` instead of `./templates/`
+    pub fn templates_folder(&mut self, folder: impl Into<PathBuf>) {
+        self.templates_folder = folder.into();
+        self.file_cache.clear();
+        self.compiled_cache.clear();
+        if let Some(cache) = self.render_cache.as_mut() {
+            cache.clear();
+        }
+    }
+
+    /// The path of the template `template`, as it's stored in the cache
+    fn template_path(&self, template: &str) -> String {
+        self.templates_folder
+            .join(Path::new(template))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Passes the output of every template this renders through `hook`,
+    /// after everything in it has been filled in, e.g. to minify it or
+    /// add a nonce to its `<script>` tags
+    ///
+    /// Renders are cached (see `JinjaState::cache_renders`) before they go
+    /// through `hook`, so it's run on every render, even cached ones.
+    ///
+    /// # Examples
+    /// ```
+    /// # use rustedflask::jinja::{JinjaState, JinjaValue};
+    /// # use std::collections::HashMap;
+    /// let mut state = JinjaState::new();
+    /// state.post_render(|html| html.lines().map(str::trim).collect());
+    /// let rendered = state
+    ///     .render_template_string("<p>\n  Hi\n</p>".to_string(), &HashMap::new(), None)
+    ///     .unwrap();
+    /// assert_eq!(rendered, "<p>Hi</p>");
+    /// ```
+    pub fn post_render(&mut self, hook: impl Fn(String) -> String + Send + Sync + 'static) {
+        self.post_render = Some(Arc::new(hook));
+    }
+
+    /// Runs `rendered` through the `post_render` hook, if there is one
+    fn finish_render(&self, rendered: String) -> String {
+        match &self.post_render {
+            Some(hook) => hook(rendered),
+            None => rendered,
+        }
+    }
+
+    /// Keeps what the last `capacity` templates rendered to, so rendering
+    /// one again with the same variables returns it without rendering
+    ///
+    /// Only turn this on for templates whose output depends on nothing but
+    /// their variables. Renders with functions are never cached, as
+    /// there's no telling what they depend on. Once the cache is full, the
+    /// least recently used render is dropped, and a render is dropped as
+    /// soon as any of the files it came from change.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::jinja::{JinjaState, JinjaValue};
+    /// # use std::collections::HashMap;
+    /// let mut state = JinjaState::new();
+    /// state.cache_renders(100);
+    /// let variables = HashMap::from([("name", JinjaValue::from("Ferris"))]);
+    /// state
+    ///     .render_template_with_outcome("index.html.jinja2", variables.clone(), None)
+    ///     .unwrap();
+    /// let second = state
+    ///     .render_template_with_outcome("index.html.jinja2", variables, None)
+    ///     .unwrap();
+    /// assert!(second.render_cache_hit);
+    /// ```
+    pub fn cache_renders(&mut self, capacity: usize) {
+        self.render_cache = Some(RenderCache::new(capacity));
+    }
+
+    /// How many renders are cached (see `JinjaState::cache_renders`)
+    pub fn cached_renders(&self) -> usize {
+        self.render_cache.as_ref().map_or(0, RenderCache::len)
+    }
+
+    /// Drops the files at `paths` from the cache, and every compiled
+    /// template, as there's no telling which of them used the files
+    fn forget_files(&mut self, paths: &[String]) {
+        for path in paths {
+            self.file_cache.remove(path);
         }
+        self.compiled_cache.clear();
     }
+
+    /// The paths of the template at `path` and every template it extends
+    /// or includes, with the parent picked by `variables` if it's dynamic
+    fn template_files(
+        &mut self,
+        path: String,
+        variables: &HashMap<&str, JinjaValue>,
+    ) -> Result<Vec<String>, JinjaError> {
+        let mut files = Vec::new();
+        let mut pending = vec![path];
+        while let Some(path) = pending.pop() {
+            if files.contains(&path) {
+                continue;
+            }
+            let source = self.get_file(path.clone())?;
+            pending.extend(
+                consts::INCLUDE
+                    .captures_iter(&source)
+                    .chain(consts::EXTEND.captures_iter(&source))
+                    .map(|entry| self.template_path(&entry["filename"])),
+            );
+            if let Some(extends) = consts::EXTEND_VARIABLE.captures(&source) {
+                if let Some(JinjaValue::Str(parent)) = variables.get(&extends["variable"]) {
+                    pending.push(self.template_path(parent));
+                }
+            }
+            files.push(path);
+        }
+        Ok(files)
+    }
+
+    /// Reads the templates named `templates` (from the `templates` folder)
+    /// into the cache, along with any templates they include or extend,
+    /// so the first render of them doesn't have to wait for the disk
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::jinja::JinjaState;
+    /// let mut state = JinjaState::new();
+    /// state.warm_cache(&["index.html.jinja2", "about.html.jinja2"]).unwrap();
+    /// ```
+    pub fn warm_cache(&mut self, templates: &[&str]) -> Result<(), JinjaError> {
+        for template in templates {
+            let contents = self.get_file(self.template_path(template))?;
+            let dependencies: Vec<String> = consts::INCLUDE
+                .captures_iter(&contents)
+                .chain(consts::EXTEND.captures_iter(&contents))
+                .map(|entry| entry["filename"].to_string())
+                .filter(|dependency| !self.is_cached(dependency))
+                .collect();
+            let dependencies: Vec<&str> = dependencies.iter().map(|name| &**name).collect();
+            self.warm_cache(&dependencies)?;
+        }
+        Ok(())
+    }
+
+    /// Reads every file in the `templates` folder (and the folders in it)
+    /// into the cache, returning how many were read
+    pub fn warm_all_in_dir(&mut self) -> Result<usize, JinjaError> {
+        let mut folders = vec![self.templates_folder.clone()];
+        let mut read = 0;
+        while let Some(folder) = folders.pop() {
+            let entries = match read_dir(&folder) {
+                Ok(entries) => entries,
+                Err(why) => {
+                    return Err(JinjaError::Other(format!(
+                        "Can't read templates folder: {}",
+                        why
+                    )))
+                }
+            };
+            for entry in entries {
+                let path = match entry {
+                    Ok(entry) => entry.path(),
+                    Err(why) => {
+                        return Err(JinjaError::Other(format!(
+                            "Can't read templates folder: {}",
+                            why
+                        )))
+                    }
+                };
+                if path.is_dir() {
+                    folders.push(path);
+                } else {
+                    self.get_file(path.to_string_lossy().to_string())?;
+                    read += 1;
+                }
+            }
+        }
+        Ok(read)
+    }
+
+    /// Whether the template `template` (from the `templates` folder) is cached
+    pub fn is_cached(&self, template: &str) -> bool {
+        let path = self.template_path(template);
+        self.compiled_cache.contains_key(&path) || self.file_cache.contains_key(&path)
+    }
+
     fn get_file(&mut self, path: String) -> Result<String, JinjaError> {
-        match self.file_cache.clone().get(&path) {
-            Some(file) => Ok(file.to_string()),
+        match self.file_cache.get(&path) {
+            Some(file) => Ok(file.clone()),
             None => {
                 let result = read_to_string(&*path);
                 match result {
@@ -106,101 +513,13 @@ impl JinjaState {
     pub fn render_template_string<'a>(
         &mut self,
         template: String,
-        variables: &HashMap<&'a str, String>,
+        variables: &HashMap<&'a str, JinjaValue>,
         functions: Option<HashMap<&'a str, JinjaFunction>>,
     ) -> Result<String, JinjaError> {
-        let mut rendered = template.clone();
-        let simple_variable = &consts::REPLACE;
-
-        let inclusion = &consts::INCLUDE;
-
-        let extend = &consts::EXTEND;
-
-        let block = &consts::BLOCK;
-
-        let temp_render_clone = rendered.clone();
-        let extends = extend.captures(&temp_render_clone);
-
-        if let Some(parents) = extends {
-            let mut contents = match self.get_file(
-                Path::new("./templates/")
-                    .join(Path::new(&parents["filename"]))
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
-            ) {
-                Ok(contents) => contents,
-                Err(why) => return Err(why),
-            };
-            {
-                let temp_contents_clone = contents.clone();
-                let parent_blocks = block.captures_iter(&*temp_contents_clone);
-                let child_blocks = block.captures_iter(&*temp_render_clone);
-                let mut child_map = HashMap::new();
-                for block in child_blocks {
-                    child_map.insert(
-                        block["blockname"].to_string(),
-                        block["blockcontent"].to_string(),
-                    );
-                }
-                for block in parent_blocks {
-                    if let Some(child_block) = child_map.get(&block["blockname"].to_string()) {
-                        contents = temp_contents_clone.replace(&block[0], &*child_block)
-                    }
-                }
-            }
-            rendered = temp_render_clone
-                .replace(&parents[0], &*contents)
-                .replace(&parents["strip"], "");
-        }
-
-        for entry in inclusion.captures_iter(&rendered.clone()) {
-            let contents = match self.get_file(
-                Path::new("./templates/")
-                    .join(Path::new(&entry["filename"]))
-                    .to_str()
-                    .unwrap()
-                    .to_string(),
-            ) {
-                Ok(contents) => contents,
-                Err(why) => return Err(why),
-            };
-            rendered = rendered.replace(&entry[0], &*contents);
-        }
-
-        for entry in simple_variable.captures_iter(&rendered.clone()) {
-            let variable = &entry;
-            let varname = &variable["variable"];
-
-            let (is_function, function_name, function_args) =
-                match parse_replace(varname, &variables) {
-                    Err(why) => return Err(why),
-                    Ok(value) => value,
-                };
-            if is_function {
-                match functions {
-                    Some(ref functions) => {
-                        let functions = functions.clone();
-                        let function = match functions.get(&*function_name) {
-                            Some(function) => function,
-                            None => return Err(JinjaError::NoSuchFunction),
-                        };
-                        let value = function(function_args);
-                        rendered = rendered.replace(&variable[0], &*value);
-                    }
-                    None => return Err(JinjaError::NoSuchFunction),
-                }
-            } else {
-                let variable_value = match variables.get(&varname) {
-                    None => return Err(JinjaError::NoSuchVariable),
-                    Some(val) => val,
-                };
-                rendered = rendered.replace(&variable[0], variable_value);
-            };
-            return Ok(rendered);
-        }
-
-        Ok(rendered)
+        let template = resolve_dynamic_extends(&template, variables)?;
+        let rendered = expand(&template, |name| self.get_file(self.template_path(name)))?;
+        let rendered = CompiledTemplate::parse(&rendered)?.render(variables, functions.as_ref())?;
+        Ok(self.finish_render(rendered))
     }
 
     /// A version of `render_template` that takes advantage of
@@ -208,248 +527,176 @@ impl JinjaState {
     pub fn render_template<'a>(
         &mut self,
         file: &'a str,
-        variables: HashMap<&'a str, String>,
+        variables: HashMap<&'a str, JinjaValue>,
         functions: Option<HashMap<&'a str, JinjaFunction>>,
     ) -> Result<String, JinjaError> {
-        // Variables are <&str, String> because the key is more likely to be
-        // a string const, and the value is more likely to be dynamically generated
-        let contents = match self.get_file(
-            Path::new("./templates/")
-                .join(Path::new(file))
-                .to_str()
-                .unwrap()
-                .to_string(),
-        ) {
-            Ok(contents) => contents,
-            Err(why) => return Err(why),
-        };
-
-        return render_template_string(contents, variables, functions);
+        self.render_template_with_outcome(file, variables, functions)
+            .map(|outcome| outcome.body)
     }
-}
 
-fn parse_replace<'a>(
-    varname: &str,
-    variables: &HashMap<&'a str, String>,
-) -> Result<(bool, String, Vec<String>), JinjaError> {
-    let mut is_function = false;
-    let mut function_name = String::new();
-    let mut function_args = Vec::<String>::new();
-    let mut varname_chars = VecDeque::from(varname.to_string().into_bytes());
-    loop {
-        let curchar = match varname_chars.pop_front() {
-            None => break,
-            Some(val) => val,
+    /// A version of `render_template` that also says whether the template
+    /// came from the cache
+    ///
+    /// The template is compiled the first time it's rendered (see
+    /// `CompiledTemplate`), and later renders reuse that instead of
+    /// parsing it again.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # use rustedflask::jinja::JinjaState;
+    /// # use std::collections::HashMap;
+    /// let mut state = JinjaState::new();
+    /// let outcome = state
+    ///     .render_template_with_outcome("index.html.jinja2", HashMap::new(), None)
+    ///     .unwrap();
+    /// assert!(!outcome.cache_hit);
+    /// ```
+    pub fn render_template_with_outcome<'a>(
+        &mut self,
+        file: &'a str,
+        variables: HashMap<&'a str, JinjaValue>,
+        functions: Option<HashMap<&'a str, JinjaFunction>>,
+    ) -> Result<RenderOutcome, JinjaError> {
+        let render_key = match self.render_cache {
+            Some(_) if functions.is_none() => Some(RenderCache::key(file, &variables)),
+            _ => None,
         };
-        if curchar == b'(' {
-            is_function = true;
-            if function_name == "".to_string() {
-                return Err(JinjaError::SyntaxError("Function call with no name".into()));
-            } else {
-                // Start parsing arguments
-                loop {
-                    let curchar = match varname_chars.pop_front() {
-                        None => return Err(JinjaError::SyntaxError("Unclosed parentheses".into())),
-                        Some(val) => val,
-                    };
-                    if curchar == b'"' {
-                        let mut string_lit = String::new();
-                        // Start parsing a string literal
-                        loop {
-                            let curchar = match varname_chars.pop_front() {
-                                None => {
-                                    return Err(JinjaError::SyntaxError(
-                                        "Unclosed string literal".into(),
-                                    ))
-                                }
-                                Some(val) => val,
-                            };
-                            if curchar == b'"' {
-                                function_args.push(string_lit.clone());
-                                let curchar = match varname_chars.pop_front() {
-                                    None => {
-                                        return Err(JinjaError::SyntaxError(
-                                            "Unclosed parentheses".into(),
-                                        ))
-                                    }
-                                    Some(val) => val,
-                                };
-                                match curchar {
-                                    b',' => {
-                                        varname_chars.push_back(b',');
-                                        break;
-                                    }
-                                    b')' => return Ok((is_function, function_name, function_args)),
-                                    somethingelse => {
-                                        return Err(JinjaError::SyntaxError(format!(
-                                            "Expected comma or closing parentheses, got \"{}\"",
-                                            char::from(somethingelse)
-                                        ))
-                                        .into())
-                                    }
-                                }
-                            }
-                            string_lit.push(curchar.into());
-                        }
-                    } else if curchar == b')' {
-                        return Ok((is_function, function_name, function_args));
-                    } else if curchar == b',' || curchar == b' ' {
-                        continue;
-                    } else {
-                        // it's a variable, start parsing
-                        let mut varname = String::new();
-                        varname.push(curchar.into());
-                        let mut curchar: u8;
-                        loop {
-                            curchar = match varname_chars.pop_front() {
-                                None => {
-                                    return Err(JinjaError::SyntaxError(
-                                        "Unclosed parentheses".into(),
-                                    ))
-                                }
-                                Some(val) => val,
-                            };
-                            if curchar == b',' || curchar == b')' {
-                                break;
-                            }
-                            if curchar == b' ' {
-                                return Err(JinjaError::SyntaxError(
-                                    "Expected a variable name, but a space was found".into(),
-                                ));
-                            } else {
-                                varname.push(curchar.into());
-                            }
-                        }
-                        let varval = match variables.get(&*varname) {
-                            None => return Err(JinjaError::NoSuchVariable),
-                            Some(val) => val,
-                        };
-                        function_args.push(varval.clone());
-                        if curchar == b')' {
-                            return Ok((is_function, function_name, function_args));
-                        }
-                    }
+        if let (Some(key), Some(cache)) = (render_key, self.render_cache.as_mut()) {
+            match cache.get(key, file, &variables) {
+                Ok(Some(body)) => {
+                    return Ok(RenderOutcome {
+                        body: self.finish_render(body),
+                        cache_hit: true,
+                        render_cache_hit: true,
+                    })
                 }
+                Ok(None) => {}
+                Err(changed) => self.forget_files(&changed),
+            }
+        }
+        let outcome = self.render_compiled(file, &variables, functions)?;
+        if let Some(key) = render_key {
+            let files = self.template_files(self.template_path(file), &variables)?;
+            if let Some(cache) = self.render_cache.as_mut() {
+                cache.store(key, file, &variables, outcome.body.clone(), files);
             }
         }
-        function_name.push(curchar.into());
+        Ok(RenderOutcome {
+            body: self.finish_render(outcome.body),
+            ..outcome
+        })
     }
-    if !is_function {
-        return Ok((is_function, String::new(), vec![]));
-    };
-    unreachable!()
+
+    /// Renders the template `file`, compiling it first if it isn't yet
+    fn render_compiled<'a>(
+        &mut self,
+        file: &'a str,
+        variables: &HashMap<&'a str, JinjaValue>,
+        functions: Option<HashMap<&'a str, JinjaFunction>>,
+    ) -> Result<RenderOutcome, JinjaError> {
+        // Variables are keyed by &str because the key is more likely to be
+        // a string const, while the value is more likely to be dynamically generated
+        let cache_hit = self.is_cached(file);
+        let path = self.template_path(file);
+        if !self.compiled_cache.contains_key(&path) {
+            let source = self.get_file(path.clone())?;
+            if consts::EXTEND_VARIABLE.is_match(&source) {
+                // the parent can change between renders, so it can't be compiled once
+                let source = resolve_dynamic_extends(&source, variables)?;
+                let expanded = expand(&source, |name| self.get_file(self.template_path(name)))?;
+                return Ok(RenderOutcome {
+                    body: CompiledTemplate::parse(&expanded)?
+                        .render(variables, functions.as_ref())?,
+                    cache_hit,
+                    render_cache_hit: false,
+                });
+            }
+            let expanded = expand(&source, |name| self.get_file(self.template_path(name)))?;
+            self.compiled_cache
+                .insert(path.clone(), CompiledTemplate::parse(&expanded)?);
+        }
+
+        Ok(RenderOutcome {
+            body: self.compiled_cache[&path].render(variables, functions.as_ref())?,
+            cache_hit,
+            render_cache_hit: false,
+        })
+    }
+}
+
+/// A rendered template, along with how it was rendered
+#[derive(Clone, Debug)]
+pub struct RenderOutcome {
+    /// The rendered template
+    pub body: String,
+    /// Whether the template was read from the cache, instead of from disk
+    pub cache_hit: bool,
+    /// Whether the whole output came from the render cache (see
+    /// `JinjaState::cache_renders`), without rendering the template
+    pub render_cache_hit: bool,
 }
 
 /// Renders a template from a given string
+///
+/// The parent template can come from a variable, like
+/// `{% extends theme %}`, as long as it's in the `templates` folder.
 pub fn render_template_string<'a>(
     template: String,
-    variables: HashMap<&'a str, String>,
+    variables: HashMap<&'a str, JinjaValue>,
     functions: Option<HashMap<&'a str, JinjaFunction>>,
 ) -> Result<String, JinjaError> {
-    let mut rendered = template.clone();
-    let simple_variable = &consts::REPLACE;
-
-    let inclusion = &consts::INCLUDE;
-
-    let extend = &consts::EXTEND;
-
-    let block = &consts::BLOCK;
-
-    let temp_render_clone = rendered.clone();
-    let extends = extend.captures(&temp_render_clone);
-
-    if let Some(parents) = extends {
-        let filename = Path::new("./templates/").join(Path::new(&parents["filename"]));
-        let mut file = match File::open(filename) {
-            Err(_) => return Err(JinjaError::NoSuchTemplate),
-            Ok(file) => file,
-        };
-
-        let mut contents = String::new();
-        match file.read_to_string(&mut contents) {
-            Err(_) => return Err(JinjaError::Other("Could not read template file".into())),
-            Ok(_) => {}
-        };
-        {
-            let temp_contents_clone = contents.clone();
-            let parent_blocks = block.captures_iter(&*temp_contents_clone);
-            let child_blocks = block.captures_iter(&*temp_render_clone);
-            let mut child_map = HashMap::new();
-            for block in child_blocks {
-                child_map.insert(
-                    block["blockname"].to_string(),
-                    block["blockcontent"].to_string(),
-                );
-            }
-            for block in parent_blocks {
-                if let Some(child_block) = child_map.get(&block["blockname"].to_string()) {
-                    contents = temp_contents_clone.replace(&block[0], &*child_block)
-                }
-            }
-        }
-        rendered = temp_render_clone
-            .replace(&parents[0], &*contents)
-            .replace(&parents["strip"], "");
-    }
-
-    for entry in inclusion.captures_iter(&rendered.clone()) {
-        let filename = Path::new("./templates/").join(Path::new(&entry["filename"]));
-        let mut file = match File::open(filename) {
-            Err(_) => return Err(JinjaError::NoSuchTemplate),
-            Ok(file) => file,
-        };
+    compile(&resolve_dynamic_extends(&template, &variables)?)?
+        .render(&variables, functions.as_ref())
+}
 
-        let mut contents = String::new();
-        match file.read_to_string(&mut contents) {
-            Err(_) => return Err(JinjaError::Other("Could not read template file".into())),
-            Ok(_) => {}
-        };
-        rendered = rendered.replace(&entry[0], &*contents);
+/// Fills in the parent and includes of a template (from the templates folder,
+/// see `render_template`) and parses it, so it can be rendered as many times as needed without
+/// being parsed again
+///
+/// # Examples
+/// ```
+/// # use rustedflask::jinja::{compile, JinjaValue};
+/// # use std::collections::HashMap;
+/// let template = compile("Hello, {{ name }}!").unwrap();
+/// for name in ["Ferris", "Corro"] {
+///     let variables = HashMap::from([("name", JinjaValue::from(name))]);
+///     println!("{}", template.render(&variables, None).unwrap());
+/// }
+/// ```
+pub fn compile(source: &str) -> Result<CompiledTemplate, JinjaError> {
+    if consts::EXTEND_VARIABLE.is_match(source) {
+        return Err(JinjaError::SyntaxError(
+            "The parent of a compiled template can't come from a variable".into(),
+        ));
     }
+    let folder = templates_folder_from_env()?;
+    let expanded = expand(source, |name| read_included(&folder.join(name)))?;
+    CompiledTemplate::parse(&expanded)
+}
 
-    for entry in simple_variable.captures_iter(&rendered.clone()) {
-        let variable = &entry;
-        let varname = &variable["variable"];
-
-        let (is_function, function_name, function_args) = match parse_replace(varname, &variables) {
-            Err(why) => return Err(why),
-            Ok(value) => value,
-        };
-        if is_function {
-            match functions {
-                Some(ref functions) => {
-                    let functions = functions.clone();
-                    let function = match functions.get(&*function_name) {
-                        Some(function) => function,
-                        None => return Err(JinjaError::NoSuchFunction),
-                    };
-                    let value = function(function_args);
-                    rendered = rendered.replace(&variable[0], &*value);
-                }
-                None => return Err(JinjaError::NoSuchFunction),
-            }
-        } else {
-            let variable_value = match variables.get(&varname) {
-                None => return Err(JinjaError::NoSuchVariable),
-                Some(val) => val,
-            };
-            rendered = rendered.replace(&variable[0], variable_value);
-        };
-        return Ok(rendered);
+/// Reads a template that another one includes or extends
+fn read_included(path: &Path) -> Result<String, JinjaError> {
+    let mut file = match File::open(path) {
+        Err(_) => return Err(JinjaError::NoSuchTemplate),
+        Ok(file) => file,
+    };
+    let mut contents = String::new();
+    match file.read_to_string(&mut contents) {
+        Err(_) => Err(JinjaError::Other("Could not read template file".into())),
+        Ok(_) => Ok(contents),
     }
-
-    Ok(rendered)
 }
 
-/// Renders a template from a given file
+/// Renders a template from a given file in the templates folder
+/// (`./templates/`, or the one in `RF_TEMPLATES`)
 pub fn render_template<'a>(
     file: &'a str,
-    variables: HashMap<&'a str, String>,
+    variables: HashMap<&'a str, JinjaValue>,
     functions: Option<HashMap<&'a str, JinjaFunction>>,
 ) -> Result<String, JinjaError> {
-    // Variables are <&str, String> because the key is more likely to be
-    // a string const, and the value is more likely to be dynamically generated
-    let fpath = Path::new("./templates/").join(file);
+    // Variables are keyed by &str because the key is more likely to be
+    // a string const, while the value is more likely to be dynamically generated
+    let fpath = templates_folder_from_env()?.join(file);
     let mut opened_file = match File::open(fpath) {
         Err(why) => {
             return Err(JinjaError::Other(format!(
@@ -463,12 +710,88 @@ pub fn render_template<'a>(
     let mut contents = String::new();
 
     match opened_file.read_to_string(&mut contents) {
+        Err(why) => Err(JinjaError::Other(format!(
+            "couldn't read file, error: {}",
+            why
+        ))),
+        Ok(_) => render_template_string(contents, variables, functions),
+    }
+}
+
+/// Renders only the block called `block` of a template from a given file,
+/// like for updating part of a page
+///
+/// The block can come from the template or from the one it extends, and
+/// fails with `NoSuchBlock` if neither has it.
+///
+/// # Examples
+/// ```no_run
+/// # use rustedflask::jinja::render_block;
+/// # use std::collections::HashMap;
+/// let sidebar = render_block("index.html.jinja2", "sidebar", HashMap::new(), None).unwrap();
+/// ```
+pub fn render_block<'a>(
+    file: &'a str,
+    block: &str,
+    variables: HashMap<&'a str, JinjaValue>,
+    functions: Option<HashMap<&'a str, JinjaFunction>>,
+) -> Result<String, JinjaError> {
+    let folder = templates_folder_from_env()?;
+    let contents = match read_to_string(folder.join(file)) {
         Err(why) => {
             return Err(JinjaError::Other(format!(
-                "couldn't read file, error: {}",
+                "can't read file, error: {}",
                 why
             )))
         }
-        Ok(_) => return render_template_string(contents, variables, functions),
-    }
+        Ok(contents) => contents,
+    };
+    let contents = resolve_dynamic_extends(&contents, &variables)?;
+    let expanded = expand(&contents, |name| read_included(&folder.join(name)))?;
+    CompiledTemplate::parse(&expanded)?.render_block(block, &variables, functions.as_ref())
+}
+
+/// A version of `render_template` that also returns the path of every
+/// template file it read, in the order they were read
+///
+/// The first path is always `file`'s, followed by the template it
+/// extends (if any) and then the ones it includes, which is useful for
+/// knowing which files to watch for changes.
+///
+/// # Examples
+/// ```no_run
+/// # use rustedflask::jinja::render_template_with_files;
+/// # use std::collections::HashMap;
+/// let (body, files) =
+///     render_template_with_files("index.html.jinja2", HashMap::new(), None).unwrap();
+/// for file in files {
+///     println!("read {}", file.display());
+/// }
+/// ```
+pub fn render_template_with_files<'a>(
+    file: &'a str,
+    variables: HashMap<&'a str, JinjaValue>,
+    functions: Option<HashMap<&'a str, JinjaFunction>>,
+) -> Result<(String, Vec<PathBuf>), JinjaError> {
+    let folder = templates_folder_from_env()?;
+    let path = folder.join(file);
+    let contents = match read_to_string(&path) {
+        Err(why) => {
+            return Err(JinjaError::Other(format!(
+                "can't read file, error: {}",
+                why
+            )))
+        }
+        Ok(contents) => contents,
+    };
+    let contents = resolve_dynamic_extends(&contents, &variables)?;
+    let mut files = vec![path];
+    let expanded = expand(&contents, |name| {
+        let path = folder.join(name);
+        let contents = read_included(&path)?;
+        files.push(path);
+        Ok(contents)
+    })?;
+    let body = CompiledTemplate::parse(&expanded)?.render(&variables, functions.as_ref())?;
+    Ok((body, files))
 }
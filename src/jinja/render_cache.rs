@@ -0,0 +1,185 @@
+//! Keeping the output of templates rendered with the same variables, so
+//! they don't have to be rendered again (see `JinjaState::cache_renders`)
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs,
+    hash::{Hash, Hasher},
+    time::SystemTime,
+};
+
+use super::JinjaValue;
+
+/// Feeds `value` into `hasher`, floats by their bits
+fn hash_value(value: &JinjaValue, hasher: &mut impl Hasher) {
+    match value {
+        JinjaValue::Str(string) => (0_u8, string).hash(hasher),
+        JinjaValue::Int(int) => (1_u8, int).hash(hasher),
+        JinjaValue::Float(float) => (2_u8, float.to_bits()).hash(hasher),
+        JinjaValue::List(values) => {
+            (3_u8, values.len()).hash(hasher);
+            for value in values {
+                hash_value(value, hasher);
+            }
+        }
+        JinjaValue::Map(values) => {
+            (4_u8, values.len()).hash(hasher);
+            for (key, value) in values {
+                key.hash(hasher);
+                hash_value(value, hasher);
+            }
+        }
+        JinjaValue::Bool(bool) => (5_u8, bool).hash(hasher),
+        JinjaValue::None => 6_u8.hash(hasher),
+    }
+}
+
+/// When `path` was last changed, if that can be found out
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// A template's output, along with what it was rendered from
+struct CachedRender {
+    /// The template and variables, as two renders can end up with the same
+    /// key without them being the same
+    template: String,
+    variables: Vec<(String, JinjaValue)>,
+    body: String,
+    files: Vec<(String, Option<SystemTime>)>,
+    last_used: u64,
+}
+
+impl CachedRender {
+    /// Whether this is the output of `template` rendered with `variables`
+    fn rendered_from(&self, template: &str, variables: &HashMap<&str, JinjaValue>) -> bool {
+        self.template == template
+            && self.variables.len() == variables.len()
+            && self
+                .variables
+                .iter()
+                .all(|(name, value)| variables.get(name.as_str()) == Some(value))
+    }
+}
+
+/// The output of the last `capacity` templates rendered, by template and
+/// variables, with the least recently used ones dropped first
+pub(crate) struct RenderCache {
+    capacity: usize,
+    uses: u64,
+    entries: HashMap<u64, CachedRender>,
+}
+
+impl RenderCache {
+    pub(crate) fn new(capacity: usize) -> RenderCache {
+        RenderCache {
+            capacity,
+            uses: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// What the output of `template` rendered with `variables` is cached under
+    pub(crate) fn key(template: &str, variables: &HashMap<&str, JinjaValue>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        template.hash(&mut hasher);
+        // the map's order changes from one map to the next
+        let mut variables: Vec<_> = variables.iter().collect();
+        variables.sort_unstable_by_key(|(name, _)| **name);
+        for (name, value) in variables {
+            name.hash(&mut hasher);
+            hash_value(value, &mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// The output of `template` rendered with `variables`, cached under
+    /// `key`, if none of the files it was rendered from have changed since
+    ///
+    /// If some have, the entry is dropped and they're returned instead.
+    pub(crate) fn get(
+        &mut self,
+        key: u64,
+        template: &str,
+        variables: &HashMap<&str, JinjaValue>,
+    ) -> Result<Option<String>, Vec<String>> {
+        let entry = match self.entries.get_mut(&key) {
+            Some(entry) if entry.rendered_from(template, variables) => entry,
+            _ => return Ok(None),
+        };
+        let changed: Vec<String> = entry
+            .files
+            .iter()
+            .filter(|(path, when)| modified(path) != *when)
+            .map(|(path, _)| path.clone())
+            .collect();
+        if !changed.is_empty() {
+            self.entries.remove(&key);
+            return Err(changed);
+        }
+        self.uses += 1;
+        entry.last_used = self.uses;
+        Ok(Some(entry.body.clone()))
+    }
+
+    /// Caches `body` under `key`, as the output of `template` rendered with
+    /// `variables` from `files`, dropping the least recently used entry if
+    /// the cache is full
+    pub(crate) fn store(
+        &mut self,
+        key: u64,
+        template: &str,
+        variables: &HashMap<&str, JinjaValue>,
+        body: String,
+        files: Vec<String>,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.uses += 1;
+        let files = files
+            .into_iter()
+            .map(|path| {
+                let when = modified(&path);
+                (path, when)
+            })
+            .collect();
+        let mut variables: Vec<_> = variables
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+        variables.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        self.entries.insert(
+            key,
+            CachedRender {
+                template: template.to_string(),
+                variables,
+                body,
+                files,
+                last_used: self.uses,
+            },
+        );
+    }
+
+    /// How many renders are cached
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Drops every cached render
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
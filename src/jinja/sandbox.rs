@@ -0,0 +1,119 @@
+//! Limiting what templates from untrusted sources can do
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    compile, consts, expr::Callable, resolve_dynamic_extends, CompiledTemplate, JinjaError,
+    JinjaFunction, JinjaValue,
+};
+
+/// What a template rendered with `render_template_string_sandboxed` is
+/// allowed to do
+///
+/// A new sandbox doesn't let templates call any functions, or include or
+/// extend other templates, but does let them use any of the built in
+/// filters.
+///
+/// # Examples
+/// ```
+/// # use rustedflask::jinja::{render_template_string_sandboxed, JinjaError, Sandbox};
+/// # use std::collections::HashMap;
+/// let sandbox = Sandbox::new().allow_filters(&["upper"]);
+/// let variables = HashMap::from([("name", "ferris".into())]);
+/// let rendered = render_template_string_sandboxed(
+///     "{{ name | upper }}".to_string(),
+///     variables.clone(),
+///     None,
+///     &sandbox,
+/// );
+/// assert_eq!(rendered.unwrap(), "FERRIS");
+///
+/// let rendered = render_template_string_sandboxed(
+///     "{{ name | lower }}".to_string(),
+///     variables,
+///     None,
+///     &sandbox,
+/// );
+/// assert!(matches!(rendered, Err(JinjaError::NotAllowed(_))));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Sandbox {
+    functions: HashSet<String>,
+    /// `None` if every filter is allowed
+    filters: Option<HashSet<String>>,
+    includes: bool,
+}
+
+impl Sandbox {
+    /// Makes a sandbox that only allows the built in filters
+    pub fn new() -> Sandbox {
+        Sandbox::default()
+    }
+
+    /// Lets templates call the functions named `functions`
+    /// (if they're passed to the render)
+    pub fn allow_functions(mut self, functions: &[&str]) -> Sandbox {
+        self.functions
+            .extend(functions.iter().map(|function| function.to_string()));
+        self
+    }
+
+    /// Only lets templates use the filters named `filters`, instead of all of them
+    pub fn allow_filters(mut self, filters: &[&str]) -> Sandbox {
+        self.filters
+            .get_or_insert_with(HashSet::new)
+            .extend(filters.iter().map(|filter| filter.to_string()));
+        self
+    }
+
+    /// Whether to let templates include and extend templates from the
+    /// `templates` folder
+    pub fn allow_includes(mut self, allow: bool) -> Sandbox {
+        self.includes = allow;
+        self
+    }
+
+    /// Fails with `NotAllowed` if `template` calls anything this sandbox doesn't allow
+    fn check(&self, template: &CompiledTemplate) -> Result<(), JinjaError> {
+        template.for_each_call(&mut |callable, name| {
+            let allowed = match callable {
+                Callable::Function => self.functions.contains(name),
+                Callable::Filter => self
+                    .filters
+                    .as_ref()
+                    .is_none_or(|filters| filters.contains(name)),
+            };
+            match (allowed, callable) {
+                (true, _) => Ok(()),
+                (false, Callable::Function) => {
+                    Err(JinjaError::NotAllowed(format!("function \"{}\"", name)))
+                }
+                (false, Callable::Filter) => {
+                    Err(JinjaError::NotAllowed(format!("filter \"{}\"", name)))
+                }
+            }
+        })
+    }
+}
+
+/// Renders a template from a given string, like `render_template_string`,
+/// failing with `NotAllowed` before anything is rendered if it does
+/// something `sandbox` doesn't allow
+pub fn render_template_string_sandboxed<'a>(
+    template: String,
+    variables: HashMap<&'a str, JinjaValue>,
+    functions: Option<HashMap<&'a str, JinjaFunction>>,
+    sandbox: &Sandbox,
+) -> Result<String, JinjaError> {
+    if !sandbox.includes {
+        if consts::INCLUDE.is_match(&template) {
+            return Err(JinjaError::NotAllowed("include".into()));
+        }
+        if consts::EXTEND.is_match(&template) || consts::EXTEND_VARIABLE.is_match(&template) {
+            return Err(JinjaError::NotAllowed("extends".into()));
+        }
+    }
+    let compiled = compile(&resolve_dynamic_extends(&template, &variables)?)?;
+    sandbox.check(&compiled)?;
+    compiled.render(&variables, functions.as_ref())
+}
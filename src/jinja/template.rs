@@ -0,0 +1,434 @@
+//! Parsing and rendering the `{{ }}` and `{% %}` tags of a template,
+//! once its includes and parents have been filled in
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    vec::IntoIter,
+};
+
+use super::{
+    consts,
+    expr::{self, Callable, Expr},
+    JinjaError, JinjaFunction, JinjaValue,
+};
+
+/// A piece of a template, before it's parsed
+enum Piece<'s> {
+    /// Text that's output as-is
+    Text(&'s str),
+    /// The inside of a `{{ }}`
+    Output(&'s str),
+    /// The inside of a `{% %}`
+    Statement(&'s str),
+}
+
+/// A parsed piece of a template
+#[derive(Debug)]
+pub(crate) enum Node {
+    /// Text that's output as-is
+    Text(String),
+    /// `{{ expression }}`
+    Output(Expr),
+    /// `{% with name = value, ... %}body{% endwith %}`
+    With(Vec<(String, Expr)>, Vec<Node>),
+    /// `{% if condition %}body{% elif condition %}body{% else %}otherwise{% endif %}`,
+    /// with each condition and its body, and what's output if none are true
+    If(Vec<(Expr, Vec<Node>)>, Vec<Node>),
+    /// `{% for name in list %}body{% else %}empty{% endfor %}`, where
+    /// `empty` is output instead of the body if the list is empty
+    ///
+    /// With more than one name (`for key, value in map.items()`), each
+    /// item is unpacked into them.
+    For {
+        variables: Vec<String>,
+        iterable: Expr,
+        body: Vec<Node>,
+        empty: Vec<Node>,
+    },
+    /// A `{% block name %}`, with what a child template filled it with
+    /// if it did, which is output as if the tags weren't there
+    Block(String, Vec<Node>),
+}
+
+/// Finds where `close` is in `source`, skipping over string literals
+fn find_close(source: &[u8], close: &[u8]) -> Option<usize> {
+    let mut quote = None;
+    for position in 0..source.len() {
+        match quote {
+            Some(quote_character) if source[position] == quote_character => quote = None,
+            Some(_) => {}
+            None if source[position] == b'"' || source[position] == b'\'' => {
+                quote = Some(source[position])
+            }
+            None if source[position..].starts_with(close) => return Some(position),
+            None => {}
+        }
+    }
+    None
+}
+
+/// Splits `template` into text, `{{ }}`s and `{% %}`s
+fn split(template: &str) -> Result<Vec<Piece<'_>>, JinjaError> {
+    let bytes = template.as_bytes();
+    let mut pieces = Vec::new();
+    let mut text_start = 0;
+    let mut position = 0;
+    while position + 1 < bytes.len() {
+        let close: &[u8] = match &bytes[position..position + 2] {
+            b"{{" => b"}}",
+            b"{%" => b"%}",
+            _ => {
+                position += 1;
+                continue;
+            }
+        };
+        if text_start < position {
+            pieces.push(Piece::Text(&template[text_start..position]));
+        }
+        let inside_start = position + 2;
+        let inside_end = match find_close(&bytes[inside_start..], close) {
+            Some(length) => inside_start + length,
+            None => {
+                return Err(JinjaError::SyntaxError(format!(
+                    "Unclosed {}",
+                    &template[position..inside_start]
+                )))
+            }
+        };
+        let inside = &template[inside_start..inside_end];
+        pieces.push(match close {
+            b"}}" => Piece::Output(inside),
+            _ => Piece::Statement(inside),
+        });
+        position = inside_end + 2;
+        text_start = position;
+    }
+    if text_start < bytes.len() {
+        pieces.push(Piece::Text(&template[text_start..]));
+    }
+    Ok(pieces)
+}
+
+/// Parses pieces into nodes, keeping track of which blocks are open
+struct TemplateParser<'s> {
+    pieces: IntoIter<Piece<'s>>,
+    /// The condition of the `{% elif %}` that `body` last stopped at
+    elif_condition: Option<Expr>,
+}
+
+impl TemplateParser<'_> {
+    /// Parses nodes until a tag in `ends` (like the `{% endwith %}` that
+    /// closes the current block), returning them along with that tag, or
+    /// until the end of the template if `ends` is empty
+    fn body(&mut self, ends: &[&str]) -> Result<(Vec<Node>, String), JinjaError> {
+        let mut nodes = Vec::new();
+        while let Some(piece) = self.pieces.next() {
+            let source = match piece {
+                Piece::Text(text) => {
+                    nodes.push(Node::Text(text.to_string()));
+                    continue;
+                }
+                Piece::Output(source) => {
+                    nodes.push(Node::Output(expr::parse(source)?));
+                    continue;
+                }
+                Piece::Statement(source) => source,
+            };
+            let mut statement = expr::Parser::new(source)?;
+            let keyword = statement.identifier()?;
+            match &*keyword {
+                "with" => {
+                    let assignments = statement.assignments()?;
+                    statement.end()?;
+                    let (body, _) = self.body(&["endwith"])?;
+                    nodes.push(Node::With(assignments, body));
+                }
+                "if" => {
+                    let mut branches = Vec::new();
+                    let mut otherwise = Vec::new();
+                    let mut condition = statement.expression()?;
+                    statement.end()?;
+                    loop {
+                        let (body, end) = self.body(&["elif", "else", "endif"])?;
+                        branches.push((condition, body));
+                        match &*end {
+                            "elif" => match self.elif_condition.take() {
+                                Some(elif_condition) => condition = elif_condition,
+                                None => unreachable!("body parses the condition of an elif"),
+                            },
+                            "else" => {
+                                otherwise = self.body(&["endif"])?.0;
+                                break;
+                            }
+                            _ => break,
+                        }
+                    }
+                    nodes.push(Node::If(branches, otherwise));
+                }
+                "for" => {
+                    let parts = consts::FOR.captures(source).ok_or_else(|| {
+                        JinjaError::SyntaxError("Expected {% for name in iterable %}".into())
+                    })?;
+                    let variables = parts["variables"]
+                        .split(',')
+                        .map(|name| name.trim().to_string())
+                        .collect();
+                    let iterable = expr::parse(&parts["iterable"])?;
+                    let (body, end) = self.body(&["else", "endfor"])?;
+                    let empty = match &*end {
+                        "else" => self.body(&["endfor"])?.0,
+                        _ => Vec::new(),
+                    };
+                    nodes.push(Node::For {
+                        variables,
+                        iterable,
+                        body,
+                        empty,
+                    });
+                }
+                "block" => {
+                    let name = statement.identifier()?;
+                    statement.end()?;
+                    nodes.push(Node::Block(name, self.body(&["endblock"])?.0));
+                }
+                keyword if ends.contains(&keyword) => {
+                    // `{% endblock name %}` can say which block it ends
+                    if keyword == "endblock" && !statement.is_at_end() {
+                        statement.identifier()?;
+                    }
+                    if keyword == "elif" {
+                        self.elif_condition = Some(statement.expression()?);
+                    }
+                    statement.end()?;
+                    return Ok((nodes, keyword.to_string()));
+                }
+                keyword => {
+                    return Err(JinjaError::SyntaxError(format!(
+                        "Unknown tag \"{}\"",
+                        keyword
+                    )))
+                }
+            }
+        }
+        match ends.last() {
+            Some(end) => Err(JinjaError::SyntaxError(format!("Missing {{% {} %}}", end))),
+            None => Ok((nodes, String::new())),
+        }
+    }
+}
+
+/// A template that's been split into text and tags and parsed, so it can be
+/// rendered again and again without being scanned each time (see `compile`)
+#[derive(Debug)]
+pub struct CompiledTemplate {
+    nodes: Vec<Node>,
+}
+
+impl CompiledTemplate {
+    /// Parses a template, once its includes and parents have been filled in
+    pub(crate) fn parse(template: &str) -> Result<CompiledTemplate, JinjaError> {
+        let nodes = TemplateParser {
+            pieces: split(template)?.into_iter(),
+            elif_condition: None,
+        }
+        .body(&[])?
+        .0;
+        Ok(CompiledTemplate { nodes })
+    }
+
+    /// Calls `check` with every function and filter this template calls,
+    /// stopping at the first error it returns
+    pub(crate) fn for_each_call(
+        &self,
+        check: &mut impl FnMut(Callable, &str) -> Result<(), JinjaError>,
+    ) -> Result<(), JinjaError> {
+        nodes_for_each_call(&self.nodes, check)
+    }
+
+    /// Renders this template with `variables` and `functions`
+    pub fn render(
+        &self,
+        variables: &HashMap<&str, JinjaValue>,
+        functions: Option<&HashMap<&str, JinjaFunction>>,
+    ) -> Result<String, JinjaError> {
+        let mut out = String::new();
+        render_nodes(&self.nodes, variables, functions, &mut out)?;
+        Ok(out)
+    }
+
+    /// Renders only the block called `name`, failing with `NoSuchBlock`
+    /// if there isn't one
+    pub fn render_block(
+        &self,
+        name: &str,
+        variables: &HashMap<&str, JinjaValue>,
+        functions: Option<&HashMap<&str, JinjaFunction>>,
+    ) -> Result<String, JinjaError> {
+        let body = match find_block(&self.nodes, name) {
+            Some(body) => body,
+            None => return Err(JinjaError::NoSuchBlock(name.to_string())),
+        };
+        let mut out = String::new();
+        render_nodes(body, variables, functions, &mut out)?;
+        Ok(out)
+    }
+}
+
+/// The body of the block called `name` in `nodes`, including blocks
+/// inside of other tags
+fn find_block<'n>(nodes: &'n [Node], name: &str) -> Option<&'n [Node]> {
+    nodes.iter().find_map(|node| match node {
+        Node::Block(block_name, body) if block_name == name => Some(&body[..]),
+        Node::Block(_, body) | Node::With(_, body) => find_block(body, name),
+        Node::If(branches, otherwise) => branches
+            .iter()
+            .find_map(|(_, branch)| find_block(branch, name))
+            .or_else(|| find_block(otherwise, name)),
+        Node::For { body, empty, .. } => find_block(body, name).or_else(|| find_block(empty, name)),
+        Node::Text(_) | Node::Output(_) => None,
+    })
+}
+
+/// Calls `check` with every function and filter `nodes` call
+fn nodes_for_each_call(
+    nodes: &[Node],
+    check: &mut impl FnMut(Callable, &str) -> Result<(), JinjaError>,
+) -> Result<(), JinjaError> {
+    for node in nodes {
+        match node {
+            Node::Text(_) => {}
+            Node::Output(expression) => expression.for_each_call(check)?,
+            Node::With(assignments, body) => {
+                for (_, value) in assignments {
+                    value.for_each_call(check)?;
+                }
+                nodes_for_each_call(body, check)?;
+            }
+            Node::If(branches, otherwise) => {
+                for (condition, branch) in branches {
+                    condition.for_each_call(check)?;
+                    nodes_for_each_call(branch, check)?;
+                }
+                nodes_for_each_call(otherwise, check)?;
+            }
+            Node::For {
+                iterable,
+                body,
+                empty,
+                ..
+            } => {
+                iterable.for_each_call(check)?;
+                nodes_for_each_call(body, check)?;
+                nodes_for_each_call(empty, check)?;
+            }
+            Node::Block(_, body) => nodes_for_each_call(body, check)?,
+        }
+    }
+    Ok(())
+}
+
+/// The `loop` variable inside of a `{% for %}`, for the item at `index`
+/// out of `length`, with the `loop` of the loop it's in, if there is one
+fn loop_variable(index: usize, length: usize, parent: Option<&JinjaValue>) -> JinjaValue {
+    let mut attributes = BTreeMap::from([
+        ("index".to_string(), JinjaValue::Int(index as i64 + 1)),
+        ("index0".to_string(), JinjaValue::Int(index as i64)),
+        (
+            "revindex".to_string(),
+            JinjaValue::Int((length - index) as i64),
+        ),
+        (
+            "revindex0".to_string(),
+            JinjaValue::Int((length - index - 1) as i64),
+        ),
+        ("first".to_string(), JinjaValue::Bool(index == 0)),
+        ("last".to_string(), JinjaValue::Bool(index + 1 == length)),
+        ("length".to_string(), JinjaValue::Int(length as i64)),
+    ]);
+    if let Some(parent) = parent {
+        attributes.insert("parentloop".to_string(), parent.clone());
+    }
+    JinjaValue::Map(attributes)
+}
+
+/// Renders `nodes` onto the end of `out`
+fn render_nodes(
+    nodes: &[Node],
+    variables: &HashMap<&str, JinjaValue>,
+    functions: Option<&HashMap<&str, JinjaFunction>>,
+    out: &mut String,
+) -> Result<(), JinjaError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Output(expression) => {
+                out.push_str(&expression.evaluate(variables, functions)?.to_string())
+            }
+            Node::With(assignments, body) => {
+                // the assignments only exist inside the block, and
+                // can't see each other
+                let mut scope: HashMap<&str, JinjaValue> = variables.clone();
+                for (name, value) in assignments {
+                    scope.insert(name, value.evaluate(variables, functions)?);
+                }
+                render_nodes(body, &scope, functions, out)?;
+            }
+            Node::If(branches, otherwise) => {
+                let mut body = otherwise;
+                for (condition, branch) in branches {
+                    if condition.evaluate(variables, functions)?.is_truthy() {
+                        body = branch;
+                        break;
+                    }
+                }
+                render_nodes(body, variables, functions, out)?;
+            }
+            Node::For {
+                variables: names,
+                iterable,
+                body,
+                empty,
+            } => {
+                let items = match iterable.evaluate(variables, functions)? {
+                    JinjaValue::List(items) => items,
+                    // like Python, looping over a map goes over its keys
+                    JinjaValue::Map(items) => items.into_keys().map(JinjaValue::Str).collect(),
+                    other => {
+                        return Err(JinjaError::TypeError(format!(
+                            "Can't loop over {}",
+                            other.type_name()
+                        )))
+                    }
+                };
+                if items.is_empty() {
+                    render_nodes(empty, variables, functions, out)?;
+                }
+                let mut scope: HashMap<&str, JinjaValue> = variables.clone();
+                let length = items.len();
+                for (index, item) in items.into_iter().enumerate() {
+                    scope.insert("loop", loop_variable(index, length, variables.get("loop")));
+                    match (&names[..], item) {
+                        ([name], item) => {
+                            scope.insert(name, item);
+                        }
+                        (names, JinjaValue::List(values)) if values.len() == names.len() => {
+                            for (name, value) in names.iter().zip(values) {
+                                scope.insert(name, value);
+                            }
+                        }
+                        (names, item) => {
+                            return Err(JinjaError::TypeError(format!(
+                                "Can't unpack {} into {} variables",
+                                item.type_name(),
+                                names.len()
+                            )))
+                        }
+                    }
+                    render_nodes(body, &scope, functions, out)?;
+                }
+            }
+            Node::Block(_, body) => render_nodes(body, variables, functions, out)?,
+        }
+    }
+    Ok(())
+}
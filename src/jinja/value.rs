@@ -0,0 +1,178 @@
+//! The values templates work with
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+/// A value passed to a template, or worked out while rendering one
+///
+/// # Examples
+/// ```
+/// # use rustedflask::jinja::JinjaValue;
+/// # use std::collections::HashMap;
+/// let mut variables = HashMap::new();
+/// variables.insert("name", JinjaValue::from("Ferris"));
+/// variables.insert("price", JinjaValue::Float(2.5));
+/// variables.insert("quantity", JinjaValue::Int(4));
+/// variables.insert("tags", JinjaValue::from(vec!["new", "sale"]));
+/// variables.insert("stock", JinjaValue::from(HashMap::from([("red", 2), ("blue", 0)])));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum JinjaValue {
+    /// A string
+    Str(String),
+    /// A whole number
+    Int(i64),
+    /// A floating point number
+    Float(f64),
+    /// A list of values, which `{% for %}` can loop over
+    List(Vec<JinjaValue>),
+    /// Values by their keys, which `{% for %}` loops over in key order
+    Map(BTreeMap<String, JinjaValue>),
+    /// `true` or `false`, like the result of an `is` test
+    Bool(bool),
+    /// No value, written `none` in templates
+    None,
+}
+
+impl JinjaValue {
+    /// The name of this value's type, like "a string", for error messages
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            JinjaValue::Str(_) => "a string",
+            JinjaValue::Int(_) => "an integer",
+            JinjaValue::Float(_) => "a float",
+            JinjaValue::List(_) => "a list",
+            JinjaValue::Map(_) => "a map",
+            JinjaValue::Bool(_) => "a boolean",
+            JinjaValue::None => "none",
+        }
+    }
+
+    /// Whether this value counts as true in an `{% if %}`, which
+    /// (like in Python) is anything but zero, empty, `false` and `none`
+    pub(crate) fn is_truthy(&self) -> bool {
+        match self {
+            JinjaValue::Str(value) => !value.is_empty(),
+            JinjaValue::Int(value) => *value != 0,
+            JinjaValue::Float(value) => *value != 0.0,
+            JinjaValue::List(values) => !values.is_empty(),
+            JinjaValue::Map(values) => !values.is_empty(),
+            JinjaValue::Bool(value) => *value,
+            JinjaValue::None => false,
+        }
+    }
+
+    /// Writes this value like it'd be written in Python source, which is
+    /// how values inside of a list are shown
+    fn fmt_repr(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JinjaValue::Str(value) => {
+                write!(f, "'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+            }
+            other => write!(f, "{}", other),
+        }
+    }
+}
+
+impl Display for JinjaValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JinjaValue::Str(value) => write!(f, "{}", value),
+            JinjaValue::Int(value) => write!(f, "{}", value),
+            // like Python, always show a decimal point (`2.0`, not `2`)
+            JinjaValue::Float(value) => write!(f, "{:?}", value),
+            JinjaValue::List(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    value.fmt_repr(f)?;
+                }
+                write!(f, "]")
+            }
+            JinjaValue::Map(values) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    JinjaValue::Str(key.clone()).fmt_repr(f)?;
+                    write!(f, ": ")?;
+                    value.fmt_repr(f)?;
+                }
+                write!(f, "}}")
+            }
+            // Python's spelling, as that's what Jinja outputs
+            JinjaValue::Bool(true) => write!(f, "True"),
+            JinjaValue::Bool(false) => write!(f, "False"),
+            JinjaValue::None => write!(f, "None"),
+        }
+    }
+}
+
+impl From<String> for JinjaValue {
+    fn from(value: String) -> Self {
+        JinjaValue::Str(value)
+    }
+}
+
+impl From<&str> for JinjaValue {
+    fn from(value: &str) -> Self {
+        JinjaValue::Str(value.to_string())
+    }
+}
+
+impl From<i64> for JinjaValue {
+    fn from(value: i64) -> Self {
+        JinjaValue::Int(value)
+    }
+}
+
+impl From<i32> for JinjaValue {
+    fn from(value: i32) -> Self {
+        JinjaValue::Int(value.into())
+    }
+}
+
+impl From<bool> for JinjaValue {
+    fn from(value: bool) -> Self {
+        JinjaValue::Bool(value)
+    }
+}
+
+impl From<f64> for JinjaValue {
+    fn from(value: f64) -> Self {
+        JinjaValue::Float(value)
+    }
+}
+
+impl<T: Into<JinjaValue>> From<Vec<T>> for JinjaValue {
+    fn from(values: Vec<T>) -> Self {
+        JinjaValue::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<K: Into<String>, V: Into<JinjaValue>> From<BTreeMap<K, V>> for JinjaValue {
+    fn from(values: BTreeMap<K, V>) -> Self {
+        JinjaValue::Map(
+            values
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+impl<K: Into<String>, V: Into<JinjaValue>> From<HashMap<K, V>> for JinjaValue {
+    fn from(values: HashMap<K, V>) -> Self {
+        JinjaValue::Map(
+            values
+                .into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
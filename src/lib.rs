@@ -11,11 +11,26 @@ pub mod flask;
 /// A Jinja parser and renderer for Rust
 pub mod jinja;
 
+mod error;
+pub use error::{Error, RustedFlaskError};
+
+// not `pub`, see the module's docs
+mod internals;
+
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, io::Read};
+    use std::{
+        collections::HashMap,
+        io::{Cursor, Read, Write},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
 
-    use crate::core::misc::ReadableVec;
+    use crate::core::misc::{ReadableVec, RecordingStream};
 
     #[cfg(feature = "jinja")]
     use crate::jinja::render_template_string;
@@ -29,13 +44,19 @@ mod tests {
         let mut example_request = core::http::HTTPRequest {
             method: b"GET".to_vec(),
             path: b"/".to_vec(),
+            raw_path: b"/".to_vec(),
             httptag: Box::new(b"HTTP".to_owned()),
             httpversion: (1, 1),
-            headers: headers,
+            headers,
             content: b"".into(),
+            request_id: None,
+            secure: false,
+            parsed_body: None,
+            lazy_body: None,
+            path_params: HashMap::new(),
         };
         example_request.send_to("example.com:80".to_string())?;
-        return Ok(());
+        Ok(())
     }
 
     #[test]
@@ -45,11 +66,11 @@ mod tests {
         let mut readablevec = ReadableVec {
             vector: &mut vec.clone(),
         };
-        readablevec.read(buf)?;
+        assert_eq!(readablevec.read(buf)?, 3);
         assert_eq!(vec[0], buf[0]);
         assert_eq!(vec[1], buf[1]);
         assert_eq!(vec[2], buf[2]);
-        return Ok(());
+        Ok(())
     }
 
     #[test]
@@ -62,16 +83,66 @@ mod tests {
             httpversion: (1, 1),
             reason: Box::new(b"OK".to_owned()),
             statuscode: core::http::HttpStatusCodes::NoContent,
-            headers: headers,
+            headers,
             content: b"".into(),
+            stream: None,
+            keep_version: false,
         };
         let mut resp_bytes: Vec<u8> = example_response.into();
-        let resp_parsed = core::http::HTTPResponse::read_http_response(&mut ReadableVec {
+        core::http::HTTPResponse::read_http_response(&mut ReadableVec {
             vector: &mut resp_bytes,
+        })?;
+        Ok(())
+    }
+
+    fn test_request(method: &str, path: &str) -> core::http::HTTPRequest {
+        core::http::HTTPRequest::new(method, path)
+    }
+
+    #[test]
+    fn test_request_id() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |request| {
+            assert!(request.request_id.is_some());
+            "works".into()
         });
-        if resp_parsed.is_err() {
-            return Err(resp_parsed.unwrap_err());
-        }
+        let response = app.respond(test_request("GET", "/"));
+        let request_id = &response.headers[flask::REQUEST_ID_HEADER];
+        assert!(!request_id.is_empty());
+        let log_line = flask::access_log_line("GET", "/", &response, request_id);
+        assert!(log_line.contains(request_id.as_str()));
+
+        // IDs sent by the client are only used when trusted
+        let mut request = test_request("GET", "/");
+        request
+            .headers
+            .insert(flask::REQUEST_ID_HEADER.to_string(), "abc-123".to_string());
+        let response = app.respond(request.clone());
+        assert_ne!(response.headers[flask::REQUEST_ID_HEADER], "abc-123");
+        app.trust_request_id(true);
+        let response = app.respond(request);
+        assert_eq!(response.headers[flask::REQUEST_ID_HEADER], "abc-123");
+    }
+
+    #[test]
+    fn test_body_reader() -> Result<(), core::http::Error> {
+        let body: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let expected = body.clone();
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", move |_| {
+            core::http::HTTPResponse::new()
+                .with_body_reader(Cursor::new(body.clone()), body.len() as u64)
+        });
+        let mut sent = Vec::new();
+        app.respond(test_request("GET", "/"))
+            .write_to(&mut sent)
+            .unwrap();
+        let response = core::http::HTTPResponse::read_http_response(&mut Cursor::new(sent))?;
+        assert_eq!(
+            response.headers["Content-Length"],
+            expected.len().to_string()
+        );
+        assert!(response.content == expected);
         Ok(())
     }
 
@@ -79,28 +150,24 @@ mod tests {
     #[cfg(feature = "jinja")]
     fn test_jinja_variable() -> Result<(), jinja::JinjaError> {
         let template = "{{ variable }}".to_string();
-        let mut variables = HashMap::<&str, String>::new();
-        variables.insert("variable", "works".to_string());
-        let rendered = match render_template_string(template, variables, None) {
-            Err(why) => return Err(why),
-            Ok(response) => response,
-        };
+        let mut variables = HashMap::<&str, jinja::JinjaValue>::new();
+        variables.insert("variable", "works".into());
+        let rendered = render_template_string(template, variables, None)?;
         assert_eq!(rendered, "works".to_string());
         Ok(())
     }
     #[test]
     #[cfg(feature = "jinja")]
     fn test_jinja_function_no_args() -> Result<(), jinja::JinjaError> {
-        fn test_function(args: Vec<String>) -> String {
-            "works".to_string()
+        fn test_function(
+            _: Vec<jinja::JinjaValue>,
+        ) -> Result<jinja::JinjaValue, jinja::JinjaError> {
+            Ok("works".into())
         }
         let template = "{{ function() }}".to_string();
         let mut functions: HashMap<&str, jinja::JinjaFunction> = HashMap::new();
-        functions.insert("function", test_function);
-        let rendered = match render_template_string(template, HashMap::new(), Some(functions)) {
-            Err(why) => return Err(why),
-            Ok(response) => response,
-        };
+        functions.insert("function", Arc::new(test_function));
+        let rendered = render_template_string(template, HashMap::new(), Some(functions))?;
         assert_eq!(rendered, "works".to_string());
         Ok(())
     }
@@ -108,24 +175,3495 @@ mod tests {
     #[test]
     #[cfg(feature = "jinja")]
     fn test_jinja_function_args() -> Result<(), jinja::JinjaError> {
-        fn test_function(args: Vec<String>) -> String {
-            let mut return_val = args[0].clone();
-            return_val.extend(args[1].chars());
-            return_val.extend(args[2].chars());
-            return_val.extend(args[3].chars());
-            return_val
-        };
+        fn test_function(
+            args: Vec<jinja::JinjaValue>,
+        ) -> Result<jinja::JinjaValue, jinja::JinjaError> {
+            let mut return_val = args[0].to_string();
+            return_val.push_str(&args[1].to_string());
+            return_val.push_str(&args[2].to_string());
+            return_val.push_str(&args[3].to_string());
+            Ok(return_val.into())
+        }
         let template = r#"{{ function("works", "blah","hah", variable) }}"#.to_string();
         let mut functions: HashMap<&str, jinja::JinjaFunction> = HashMap::new();
-        functions.insert("function", test_function);
+        functions.insert("function", Arc::new(test_function));
 
         let mut variables = HashMap::new();
-        variables.insert("variable", "gah".to_string());
-        let rendered = match render_template_string(template, variables, Some(functions)) {
-            Err(why) => return Err(why),
-            Ok(response) => response,
-        };
+        variables.insert("variable", "gah".into());
+        let rendered = render_template_string(template, variables, Some(functions))?;
         assert_eq!(rendered, "worksblahhahgah".to_string());
         Ok(())
     }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_function_spaced_args() -> Result<(), jinja::JinjaError> {
+        fn test_function(
+            args: Vec<jinja::JinjaValue>,
+        ) -> Result<jinja::JinjaValue, jinja::JinjaError> {
+            let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+            Ok(args.join("+").into())
+        }
+        let mut functions: HashMap<&str, jinja::JinjaFunction> = HashMap::new();
+        functions.insert("f", Arc::new(test_function));
+        let mut variables = HashMap::new();
+        variables.insert("a", "1".into());
+        variables.insert("b", "2".into());
+
+        let rendered = render_template_string(
+            "{{ f( a , b ) }}".to_string(),
+            variables.clone(),
+            Some(functions.clone()),
+        )?;
+        assert_eq!(rendered, "1+2");
+        let rendered = render_template_string(
+            r#"{{ f( "x" , a,"y" ,b ) }}"#.to_string(),
+            variables.clone(),
+            Some(functions.clone()),
+        )?;
+        assert_eq!(rendered, "x+1+y+2");
+        // names still can't have spaces in them
+        assert!(matches!(
+            render_template_string("{{ f(a b) }}".to_string(), variables, Some(functions)),
+            Err(jinja::JinjaError::SyntaxError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_body_iter() -> Result<(), core::http::Error> {
+        let path = std::env::temp_dir().join("rustedflask-test-body-iter");
+        let expected: Vec<u8> = (0..10_000).map(|i| (i % 256) as u8).collect();
+        std::fs::write(&path, &expected).unwrap();
+
+        let mut app = flask::App::new("test".to_string());
+        let file_path = path.clone();
+        app.route("/", move |_| {
+            let mut file = std::fs::File::open(&file_path).unwrap();
+            let length = file.metadata().unwrap().len();
+            let blocks = std::iter::from_fn(move || {
+                let mut block = vec![0_u8; 4096];
+                match file.read(&mut block) {
+                    Ok(0) | Err(_) => None,
+                    Ok(read) => {
+                        block.truncate(read);
+                        Some(block)
+                    }
+                }
+            });
+            core::http::HTTPResponse::new().with_body_iter(blocks, Some(length))
+        });
+        let mut sent = Vec::new();
+        app.respond(test_request("GET", "/"))
+            .write_to(&mut sent)
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        let response = core::http::HTTPResponse::read_http_response(&mut Cursor::new(sent))?;
+        assert_eq!(response.headers["Content-Length"], "10000");
+        assert!(response.content == expected);
+
+        // without a length, each segment becomes a chunk
+        let segments = vec![b"foo".to_vec(), b"barbaz".to_vec()].into_iter();
+        let mut sent = Vec::new();
+        core::http::HTTPResponse::new()
+            .with_body_iter(segments, None)
+            .write_to(&mut sent)
+            .unwrap();
+        assert!(sent.ends_with(b"\r\n\r\n3\r\nfoo\r\n6\r\nbarbaz\r\n0\r\n\r\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_request_line() -> Result<(), core::http::Error> {
+        let (method, path, version) = core::http::parse_request_line(b"POST /a/b HTTP/1.0\r\n")?;
+        assert_eq!(method, b"POST");
+        assert_eq!(path, b"/a/b");
+        assert_eq!(version, (1, 0));
+        assert!(matches!(
+            core::http::parse_request_line(b"GET /"),
+            Err(core::http::Error::UnreadableMessageError)
+        ));
+        assert!(matches!(
+            core::http::parse_request_line(b"GET / FTP/1.1"),
+            Err(core::http::Error::NotHTTP)
+        ));
+        assert!(matches!(
+            core::http::parse_request_line(b"GET / HTTP/x.1"),
+            Err(core::http::Error::InvalidVersionError)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_status_line() -> Result<(), core::http::Error> {
+        let (version, status, reason) =
+            core::http::parse_status_line(b"HTTP/1.1 505 HTTP Version Not Supported")?;
+        assert_eq!(version, (1, 1));
+        assert!(matches!(
+            status,
+            core::http::HttpStatusCodes::HTTPVersionNotSupported
+        ));
+        assert_eq!(reason, b"HTTP Version Not Supported");
+        assert!(matches!(
+            core::http::parse_status_line(b"HTTP/1.1 abc OK"),
+            Err(core::http::Error::UnreadableStatusCode)
+        ));
+        assert!(matches!(
+            core::http::parse_status_line(b"garbage"),
+            Err(core::http::Error::UnreadableMessageError)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_function_argument_edge_cases() -> Result<(), jinja::JinjaError> {
+        fn count_args(
+            args: Vec<jinja::JinjaValue>,
+        ) -> Result<jinja::JinjaValue, jinja::JinjaError> {
+            let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+            Ok(format!("{}:{}", args.len(), args.join(",")).into())
+        }
+        let mut functions: HashMap<&str, jinja::JinjaFunction> = HashMap::new();
+        functions.insert("f", Arc::new(count_args));
+        let mut variables = HashMap::new();
+        variables.insert("x", "1".into());
+        variables.insert("a", "2".into());
+
+        let render = |template: &str| {
+            render_template_string(
+                template.to_string(),
+                variables.clone(),
+                Some(functions.clone()),
+            )
+        };
+        assert_eq!(render("{{ f() }}")?, "0:");
+        assert_eq!(render("{{ f(x) }}")?, "1:1");
+        assert_eq!(render("{{ f(a,) }}")?, "1:2");
+        assert_eq!(render("{{ f( ) }}")?, "0:");
+        assert_eq!(render("{{ f(x, 42, 'y') }}")?, "3:1,42,y");
+        assert!(matches!(
+            render("{{ f(,) }}"),
+            Err(jinja::JinjaError::SyntaxError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_with_body() -> Result<(), core::http::Error> {
+        let mut stream = Cursor::new(
+            b"GET /first HTTP/1.1\r\ncontent-length: 5\r\n\r\nhello\
+            GET /second HTTP/1.1\r\nContent-Length: 0\r\n\r\n\
+            GET /third HTTP/1.1\r\nHost: example.com\r\n\r\n"
+                .to_vec(),
+        );
+        let first = core::http::HTTPRequest::read_http_request(&mut stream)?;
+        assert_eq!(first.path, b"/first");
+        assert!(first.has_body());
+        assert_eq!(first.body_bytes(), b"hello");
+
+        // the body was drained, so the pipelined requests still parse
+        let second = core::http::HTTPRequest::read_http_request(&mut stream)?;
+        assert_eq!(second.path, b"/second");
+        assert!(second.has_body());
+        assert!(second.body_bytes().is_empty());
+
+        let third = core::http::HTTPRequest::read_http_request(&mut stream)?;
+        assert_eq!(third.path, b"/third");
+        assert!(!third.has_body());
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_normalization() -> Result<(), core::http::Error> {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "root".into());
+        app.route("/a", |_| "a".into());
+        app.route("/a/b", |request| {
+            assert_eq!(request.path, b"/a/b");
+            "a/b".into()
+        });
+        assert_eq!(app.respond(test_request("GET", "//a")).content, b"a");
+        assert_eq!(app.respond(test_request("GET", "/a/./b")).content, b"a/b");
+        assert_eq!(app.respond(test_request("GET", "/a/../../a")).content, b"a");
+
+        // an empty target is the root
+        let (method, path, _) = core::http::parse_request_line(b"GET  HTTP/1.1")?;
+        assert!(path.is_empty());
+        let request = test_request(&String::from_utf8_lossy(&method), "");
+        assert_eq!(app.respond(request).content, b"root");
+        Ok(())
+    }
+
+    #[derive(Clone, Default)]
+    struct MemoryLogger {
+        records: Arc<Mutex<Vec<(&'static str, String)>>>,
+    }
+
+    impl flask::Logger for MemoryLogger {
+        fn info(&self, message: &str) {
+            self.records
+                .lock()
+                .unwrap()
+                .push(("info", message.to_string()));
+        }
+        fn warn(&self, message: &str) {
+            self.records
+                .lock()
+                .unwrap()
+                .push(("warn", message.to_string()));
+        }
+        fn error(&self, message: &str) {
+            self.records
+                .lock()
+                .unwrap()
+                .push(("error", message.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_custom_logger() {
+        let logger = MemoryLogger::default();
+        let mut app = flask::App::new("test".to_string());
+        app.set_logger(logger.clone());
+        app.route("/", |_| "works".into());
+        let response = app.respond(test_request("GET", "/missing"));
+        let request_id = &response.headers[flask::REQUEST_ID_HEADER];
+
+        let records = logger.records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].0, "info");
+        assert!(records[0].1.starts_with("GET /missing 404"));
+        assert!(records[0].1.contains(request_id.as_str()));
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_error_template() {
+        let mut app = flask::App::new("test".to_string());
+        app.error_template(
+            core::http::HttpStatusCodes::InternalServerError,
+            "errors/500.html.jinja2",
+        );
+        app.error_template(
+            core::http::HttpStatusCodes::NotFound,
+            "errors/missing.jinja2",
+        );
+        app.route("/", |_| panic!("secret debug string"));
+
+        let response = app.respond(test_request("GET", "/"));
+        assert!(matches!(
+            response.statuscode,
+            core::http::HttpStatusCodes::InternalServerError
+        ));
+        let body = String::from_utf8(response.content).unwrap();
+        assert!(body.contains("Something went wrong (500)"));
+        assert!(!body.contains("secret debug string"));
+
+        // missing templates fall back to plain text
+        let response = app.respond(test_request("GET", "/missing"));
+        assert_eq!(response.content, b"404 Not Found");
+    }
+
+    #[test]
+    fn test_debug_mode() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| panic!("secret debug string"));
+        let production = app.respond(test_request("GET", "/"));
+        assert_eq!(production.content, b"500 Internal Server Error");
+
+        let debug = app
+            .with_debug(true)
+            .respond(test_request("GET", "/"))
+            .content;
+        let debug = String::from_utf8(debug).unwrap();
+        assert!(debug.starts_with("500 Internal Server Error"));
+        assert!(debug.contains("secret debug string"));
+    }
+
+    /// An in-memory connection, which reads from `input` and writes to `output`
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl MockStream {
+        fn new(input: &[u8]) -> MockStream {
+            MockStream {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_recording_stream() -> Result<(), core::http::Error> {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "works".into());
+
+        let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let mut server_side = RecordingStream::new(MockStream::new(request));
+        app.handle_connection(&mut server_side);
+        assert_eq!(server_side.read_bytes(), request);
+        let written = server_side.written_bytes().to_vec();
+        assert!(written.starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+        // the client sends exactly what the server read
+        let mut client_request = test_request("GET", "/");
+        client_request
+            .headers
+            .insert("Host".to_string(), "localhost".to_string());
+        let mut client_side =
+            core::http::BufferedStream::new(RecordingStream::new(MockStream::new(&written)));
+        let response = client_request.send_over(&mut client_side)?;
+        assert_eq!(client_side.get_mut().written_bytes(), request);
+        assert_eq!(response.content, b"works");
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_status_code() -> Result<(), core::http::Error> {
+        let response = core::http::HTTPResponse::new()
+            .with_statuscode(
+                core::http::HttpStatusCodes::Custom(599),
+                Box::new(b"Network Connect Timeout".to_owned()),
+            )
+            .with_content(b"timeout".to_vec());
+        let bytes: Vec<u8> = response.into();
+        assert!(bytes.starts_with(b"HTTP/1.1 599 Network Connect Timeout\r\n"));
+
+        let parsed = core::http::HTTPResponse::read_http_response(&mut Cursor::new(bytes))?;
+        assert!(matches!(
+            parsed.statuscode,
+            core::http::HttpStatusCodes::Custom(599)
+        ));
+        assert_eq!(parsed.statuscode.status_code_number(), 599);
+        assert_eq!(&*parsed.reason, b"Network Connect Timeout");
+
+        // known codes never become custom ones
+        assert!(matches!(
+            core::http::HttpStatusCodes::from_code(404),
+            Some(core::http::HttpStatusCodes::NotFound)
+        ));
+        assert!(core::http::HttpStatusCodes::from_code(600).is_none());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_concatenation() -> Result<(), jinja::JinjaError> {
+        fn shout(args: Vec<jinja::JinjaValue>) -> Result<jinja::JinjaValue, jinja::JinjaError> {
+            let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+            Ok(args.concat().to_uppercase().into())
+        }
+        let mut functions: HashMap<&str, jinja::JinjaFunction> = HashMap::new();
+        functions.insert("shout", Arc::new(shout));
+        let mut variables = HashMap::new();
+        variables.insert("first", "Rusted".into());
+        variables.insert("last", "Flask".into());
+        variables.insert("count", "3".into());
+
+        let render = |template: &str| {
+            render_template_string(
+                template.to_string(),
+                variables.clone(),
+                Some(functions.clone()),
+            )
+        };
+        assert_eq!(render("{{ 'Hello, ' ~ first }}")?, "Hello, Rusted");
+        assert_eq!(render("{{ first ~ last }}")?, "RustedFlask");
+        assert_eq!(render("{{ first ~ ' ' ~ last }}")?, "Rusted Flask");
+        assert_eq!(render("{{ count ~ 1 }}")?, "31");
+        assert_eq!(render("{{ shout(first ~ '!') ~ last }}")?, "RUSTED!Flask");
+        assert!(matches!(
+            render("{{ first ~ }}"),
+            Err(jinja::JinjaError::SyntaxError(_))
+        ));
+        assert!(matches!(
+            render("{{ first ~ missing }}"),
+            Err(jinja::JinjaError::NoSuchVariable(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_http_version() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "works".into());
+
+        let mut stream = MockStream::new(b"GET / HTTP/1.0\r\n\r\n");
+        app.handle_connection(&mut stream);
+        assert!(stream.output.starts_with(b"HTTP/1.0 200 OK\r\n"));
+
+        let mut stream = MockStream::new(b"GET / HTTP/1.1\r\n\r\n");
+        app.handle_connection(&mut stream);
+        assert!(stream.output.starts_with(b"HTTP/1.1 200 OK\r\n"));
+
+        let app = app.with_http_version((1, 1));
+        let mut stream = MockStream::new(b"GET / HTTP/1.0\r\n\r\n");
+        app.handle_connection(&mut stream);
+        assert!(stream.output.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_arithmetic() -> Result<(), jinja::JinjaError> {
+        use jinja::JinjaValue;
+        let mut variables = HashMap::new();
+        variables.insert("price", JinjaValue::Float(2.5));
+        variables.insert("quantity", JinjaValue::Int(4));
+        variables.insert("name", JinjaValue::from("widget"));
+
+        let render =
+            |template: &str| render_template_string(template.to_string(), variables.clone(), None);
+        assert_eq!(render("{{ 2 + 3 * 4 }}")?, "14");
+        assert_eq!(render("{{ (2 + 3) * 4 }}")?, "20");
+        assert_eq!(render("{{ 10 - 2 - 3 }}")?, "5");
+        assert_eq!(render("{{ price * quantity }}")?, "10.0");
+        assert_eq!(render("{{ 7 / 2 }}")?, "3.5");
+        assert_eq!(render("{{ 4 / 2 }}")?, "2.0");
+        assert_eq!(render("{{ 7 // 2 }}")?, "3");
+        assert_eq!(render("{{ -7 // 2 }}")?, "-4");
+        assert_eq!(render("{{ 7.5 // 2 }}")?, "3.0");
+        assert_eq!(render("{{ -7 % 3 }}")?, "2");
+        assert_eq!(render("{{ quantity ~ 'x' ~ (1 + 1) }}")?, "4x2");
+        assert!(matches!(
+            render("{{ name * 2 }}"),
+            Err(jinja::JinjaError::TypeError(_))
+        ));
+        assert!(matches!(
+            render("{{ 1 / 0 }}"),
+            Err(jinja::JinjaError::Other(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_with_retry() -> Result<(), core::http::Error> {
+        use std::{net::TcpListener, thread, time::Duration};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let mut app = flask::App::new("test".to_string());
+            app.route_with_allowed_methods(
+                "/",
+                |_| "works".into(),
+                vec!["GET".to_string(), "POST".to_string()],
+            );
+            let mut connections = listener.incoming();
+            // hang up on the first two connections without answering
+            for _ in 0..2 {
+                drop(connections.next());
+            }
+            // and then answer properly
+            let mut stream = connections.next().unwrap().unwrap();
+            app.handle_connection(&mut stream);
+            // the POST is only sent once
+            drop(connections.next());
+        });
+
+        let policy = core::http::RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let response = test_request("GET", "/").send_to_with_retry(address.clone(), &policy)?;
+        assert_eq!(response.content, b"works");
+
+        let result = test_request("POST", "/").send_to_with_retry(address, &policy);
+        assert!(matches!(
+            result,
+            Err(core::http::Error::StreamReadError) | Err(core::http::Error::CouldntSend)
+        ));
+        server.join().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_warm_cache() -> Result<(), jinja::JinjaError> {
+        let mut state = jinja::JinjaState::new();
+        assert!(!state.is_cached("warm/page.html.jinja2"));
+        state.warm_cache(&["warm/page.html.jinja2"])?;
+        assert!(state.is_cached("warm/page.html.jinja2"));
+        // included by the page
+        assert!(state.is_cached("warm/part.html.jinja2"));
+        assert!(!state.is_cached("errors/500.html.jinja2"));
+
+        assert!(state.warm_all_in_dir()? >= 3);
+        assert!(state.is_cached("errors/500.html.jinja2"));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_render_cache_hit() -> Result<(), jinja::JinjaError> {
+        let mut state = jinja::JinjaState::new();
+        let first =
+            state.render_template_with_outcome("warm/part.html.jinja2", HashMap::new(), None)?;
+        assert!(!first.cache_hit);
+        let second =
+            state.render_template_with_outcome("warm/part.html.jinja2", HashMap::new(), None)?;
+        assert!(second.cache_hit);
+        assert_eq!(first.body, second.body);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_filters() -> Result<(), jinja::JinjaError> {
+        let mut variables = HashMap::new();
+        variables.insert("name", jinja::JinjaValue::from("  ferris "));
+        variables.insert("count", jinja::JinjaValue::Int(-3));
+
+        let render =
+            |template: &str| render_template_string(template.to_string(), variables.clone(), None);
+        assert_eq!(render("{{ missing | default(\"x\") }}")?, "x");
+        assert_eq!(render("{{ missing | default }}")?, "");
+        assert_eq!(render("{{ name | default('x') | trim }}")?, "ferris");
+        assert_eq!(render("{{ name | trim | upper }}")?, "FERRIS");
+        assert_eq!(render("{{ name | trim | capitalize | safe }}")?, "Ferris");
+        assert_eq!(render("{{ count | abs * 2 }}")?, "6");
+        assert_eq!(render("{{ -count|abs }}")?, "-3");
+        // undefined variables only get past `default`
+        assert!(matches!(
+            render("{{ missing | upper }}"),
+            Err(jinja::JinjaError::NoSuchVariable(_))
+        ));
+        assert!(matches!(
+            render("{{ missing | upper | default('x') }}"),
+            Ok(ref rendered) if rendered == "x"
+        ));
+        assert!(matches!(
+            render("{{ name | bogus }}"),
+            Err(jinja::JinjaError::NoSuchFilter(ref filter)) if filter == "bogus"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_with() -> Result<(), jinja::JinjaError> {
+        let mut variables = HashMap::new();
+        variables.insert("x", jinja::JinjaValue::from("outer"));
+        variables.insert("var", jinja::JinjaValue::Int(2));
+
+        let render =
+            |template: &str| render_template_string(template.to_string(), variables.clone(), None);
+        assert_eq!(
+            render("{% with a = \"1\", b = var %}{{ a }}+{{ b }}{% endwith %}")?,
+            "1+2"
+        );
+        // shadows outer variables, which come back after the block
+        assert_eq!(
+            render("{% with x = 'inner' %}{{ x }}{% endwith %} {{ x }}")?,
+            "inner outer"
+        );
+        assert_eq!(
+            render("{% with a = 1 %}{% with a = a + 1 %}{{ a }}{% endwith %}{{ a }}{% endwith %}")?,
+            "21"
+        );
+        // and don't exist outside of it
+        assert_eq!(
+            render("{% with a = 1 %}{% endwith %}{{ a | default('gone') }}")?,
+            "gone"
+        );
+        assert!(matches!(
+            render("{% with a = 1 %}{% endwith %}{{ a }}"),
+            Err(jinja::JinjaError::NoSuchVariable(_))
+        ));
+        assert!(matches!(
+            render("{% with a = 1 %}{{ a }}"),
+            Err(jinja::JinjaError::SyntaxError(_))
+        ));
+        assert!(matches!(
+            render("{% with a %}{% endwith %}"),
+            Err(jinja::JinjaError::SyntaxError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_static_gzip() -> Result<(), core::http::Error> {
+        let folder =
+            std::env::temp_dir().join(format!("rustedflask-static-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("style.css"), b"body {}").unwrap();
+        std::fs::write(folder.join("style.css.gz"), b"pretend this is gzip").unwrap();
+        std::fs::write(folder.join("app.js"), b"main()").unwrap();
+
+        let mut app = flask::App::new("test".to_string());
+        app.static_folder("/static", &folder);
+        let get = |request: &[u8]| {
+            let mut stream = MockStream::new(request);
+            app.handle_connection(&mut stream);
+            core::http::HTTPResponse::read_http_response(&mut Cursor::new(stream.output))
+        };
+
+        let response =
+            get(b"GET /static/style.css HTTP/1.1\r\nAccept-Encoding: deflate, gzip\r\n\r\n")?;
+        assert_eq!(response.content, b"pretend this is gzip");
+        assert_eq!(response.headers["Content-Encoding"], "gzip");
+        assert_eq!(response.headers["Content-Type"], "text/css");
+        assert_eq!(response.headers["Vary"], "Accept-Encoding");
+
+        // falls back to the uncompressed file
+        let response = get(b"GET /static/style.css HTTP/1.1\r\n\r\n")?;
+        assert_eq!(response.content, b"body {}");
+        assert!(!response.headers.contains_key("Content-Encoding"));
+        let response = get(b"GET /static/style.css HTTP/1.1\r\nAccept-Encoding: gzip;q=0\r\n\r\n")?;
+        assert_eq!(response.content, b"body {}");
+        let response = get(b"GET /static/app.js HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n")?;
+        assert_eq!(response.content, b"main()");
+        assert!(!response.headers.contains_key("Content-Encoding"));
+
+        let response = get(b"GET /static/missing.css HTTP/1.1\r\n\r\n")?;
+        assert_eq!(response.statuscode.status_code_number(), 404);
+        std::fs::remove_dir_all(&folder).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_from_string() {
+        let page = "<p>rendered</p>".to_string();
+        let buffer = page.as_ptr();
+        let response = core::http::HTTPResponse::from(page);
+        // the body is the string's own buffer, not a copy of it
+        assert_eq!(response.content.as_ptr(), buffer);
+        assert_eq!(response.content, b"<p>rendered</p>");
+        assert_eq!(response.headers["Content-Length"], "15");
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_render_into_response() -> Result<(), jinja::JinjaError> {
+        let response = flask::render("warm/page.html.jinja2", HashMap::new(), None)?;
+        assert_eq!(response.content, b"<main><p>part</p></main>\n");
+        assert_eq!(
+            response.content,
+            jinja::render_template("warm/page.html.jinja2", HashMap::new(), None)?.into_bytes()
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_render_content_type() -> Result<(), jinja::JinjaError> {
+        use crate::core::http::headers;
+
+        let response = flask::render("warm/page.html.jinja2", HashMap::new(), None)?;
+        assert_eq!(response.header(headers::CONTENT_TYPE), Some("text/html"));
+
+        let mut variables = HashMap::new();
+        variables.insert("name", jinja::JinjaValue::from("Ferris"));
+        let response = flask::render("api/user.json.jinja2", variables, None)?;
+        assert_eq!(
+            response.header(headers::CONTENT_TYPE),
+            Some("application/json")
+        );
+        assert_eq!(response.content, b"{\"name\": \"Ferris\"}\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_alive_connection_close() -> Result<(), core::http::Error> {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "works".into());
+
+        let mut stream = MockStream::new(
+            b"GET / HTTP/1.1\r\n\r\n\
+              GET / HTTP/1.1\r\nConnection: close\r\n\r\n\
+              GET / HTTP/1.1\r\n\r\n",
+        );
+        app.handle_connection(&mut stream);
+
+        let mut output = Cursor::new(stream.output);
+        let first = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(first.content, b"works");
+        assert_eq!(first.headers["Connection"], "keep-alive");
+        let second = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(second.content, b"works");
+        assert_eq!(second.headers["Connection"], "close");
+        // the third request is never answered
+        assert_eq!(output.position() as usize, output.get_ref().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_mapper() {
+        #[derive(Debug)]
+        struct Invalid(&'static str);
+        impl std::fmt::Display for Invalid {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "invalid {}", self.0)
+            }
+        }
+        impl std::error::Error for Invalid {}
+
+        let mut app = flask::App::new("test".to_string());
+        app.try_route("/invalid", |_| Err(Invalid("name")));
+        app.try_route("/io", |_| Err(std::io::Error::other("disk on fire")));
+        app.try_route("/ok", |_| Ok::<_, Invalid>("works".into()));
+
+        // without a mapper, errors are 500s that don't say what went wrong
+        let response = app.respond(test_request("GET", "/invalid"));
+        assert_eq!(response.statuscode.status_code_number(), 500);
+        assert!(!String::from_utf8_lossy(&response.content).contains("invalid name"));
+
+        app.error_mapper(|error| match error.downcast_ref::<Invalid>() {
+            Some(invalid) => {
+                core::http::HTTPResponse::from(format!("{{\"error\": \"{}\"}}", invalid))
+                    .with_statuscode(
+                        core::http::HttpStatusCodes::UnprocessableContent,
+                        Box::new(b"Unprocessable Content".to_owned()),
+                    )
+                    .with_header("Content-Type".to_string(), "application/json".to_string())
+            }
+            None => core::http::HTTPResponse::from("oops").with_statuscode(
+                core::http::HttpStatusCodes::InternalServerError,
+                Box::new(b"Internal Server Error".to_owned()),
+            ),
+        });
+        let response = app.respond(test_request("GET", "/invalid"));
+        assert_eq!(response.statuscode.status_code_number(), 422);
+        assert_eq!(response.headers["Content-Type"], "application/json");
+        assert_eq!(response.content, br#"{"error": "invalid name"}"#);
+
+        let response = app.respond(test_request("GET", "/io"));
+        assert_eq!(response.statuscode.status_code_number(), 500);
+        assert_eq!(response.content, b"oops");
+
+        let response = app.respond(test_request("GET", "/ok"));
+        assert_eq!(response.content, b"works");
+    }
+
+    #[test]
+    fn test_max_headers() -> Result<(), core::http::Error> {
+        let limits = core::http::RequestLimits {
+            max_headers: 3,
+            ..Default::default()
+        };
+        let request = |headers: usize| {
+            let mut request = b"GET / HTTP/1.1\r\n".to_vec();
+            for header in 0..headers {
+                request.extend(format!("X-Header-{}:{}\r\n", header, header).as_bytes());
+            }
+            request.extend(b"\r\n");
+            request
+        };
+
+        let parsed = core::http::HTTPRequest::read_http_request_with_limits(
+            &mut Cursor::new(request(3)),
+            &limits,
+        )?;
+        assert_eq!(parsed.headers.len(), 3);
+        assert_eq!(parsed.headers["X-Header-0"], "0");
+        assert!(matches!(
+            core::http::HTTPRequest::read_http_request_with_limits(
+                &mut Cursor::new(request(4)),
+                &limits
+            ),
+            Err(core::http::Error::TooManyHeaders)
+        ));
+
+        let mut app = flask::App::new("test".to_string()).with_request_limits(limits);
+        app.route("/", |_| "works".into());
+        let mut stream = MockStream::new(&request(4));
+        app.handle_connection(&mut stream);
+        let response =
+            core::http::HTTPResponse::read_http_response(&mut Cursor::new(stream.output))?;
+        assert_eq!(response.statuscode.status_code_number(), 431);
+        assert_eq!(response.headers["Connection"], "close");
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_secure() -> Result<(), core::http::Error> {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |request| {
+            request.is_secure().to_string().as_str().into()
+        });
+        let serve = |app: &flask::App, request: &[u8], tls: bool| {
+            let mut stream = MockStream::new(request);
+            if tls {
+                app.handle_tls_connection(&mut stream);
+            } else {
+                app.handle_connection(&mut stream);
+            }
+            core::http::HTTPResponse::read_http_response(&mut Cursor::new(stream.output))
+        };
+        let plain = b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n";
+        let forwarded =
+            b"GET / HTTP/1.1\r\nX-Forwarded-Proto: https, http\r\nConnection: close\r\n\r\n";
+
+        assert_eq!(serve(&app, plain, false)?.content, b"false");
+        assert_eq!(serve(&app, plain, true)?.content, b"true");
+        // the header is ignored unless the proxy is trusted
+        assert_eq!(serve(&app, forwarded, false)?.content, b"false");
+        app.trust_forwarded_proto(true);
+        assert_eq!(serve(&app, forwarded, false)?.content, b"true");
+        assert_eq!(serve(&app, plain, false)?.content, b"false");
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_path() -> Result<(), core::http::Error> {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/files/a/b", |request| {
+            format!(
+                "{} {}",
+                String::from_utf8_lossy(&request.path),
+                String::from_utf8_lossy(&request.raw_path)
+            )
+            .as_str()
+            .into()
+        });
+        let mut stream = MockStream::new(b"GET /files/a%2Fb HTTP/1.1\r\nConnection: close\r\n\r\n");
+        app.handle_connection(&mut stream);
+        let response =
+            core::http::HTTPResponse::read_http_response(&mut Cursor::new(stream.output))?;
+        assert_eq!(response.content, b"/files/a/b /files/a%2Fb");
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut app = flask::App::new("test".to_string());
+        app.cache_responses(Duration::from_millis(200));
+        let counter = Arc::clone(&calls);
+        app.route_with_allowed_methods(
+            "/",
+            move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                "cached".into()
+            },
+            vec!["GET".to_string(), "POST".to_string()],
+        );
+        let counter = Arc::clone(&calls);
+        app.route("/cookie", move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            core::http::HTTPResponse::from("not cached")
+                .with_header("Set-Cookie".to_string(), "a=b".to_string())
+        });
+
+        assert_eq!(app.respond(test_request("GET", "/")).content, b"cached");
+        assert_eq!(app.respond(test_request("GET", "/")).content, b"cached");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // POSTs always go to the handler
+        app.respond(test_request("POST", "/"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        thread::sleep(Duration::from_millis(300));
+        app.respond(test_request("GET", "/"));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        app.respond(test_request("GET", "/cookie"));
+        app.respond(test_request("GET", "/cookie"));
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_http_1_0_keep_alive() -> Result<(), core::http::Error> {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "works".into());
+
+        let mut stream = MockStream::new(
+            b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+              GET / HTTP/1.0\r\n\r\n\
+              GET / HTTP/1.0\r\n\r\n",
+        );
+        app.handle_connection(&mut stream);
+
+        let mut output = Cursor::new(stream.output);
+        let first = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(first.headers["Connection"], "keep-alive");
+        let second = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(second.headers["Connection"], "close");
+        // the third request is never answered
+        assert_eq!(output.position() as usize, output.get_ref().len());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_function_with_state() {
+        let settings: HashMap<String, String> =
+            HashMap::from([("site_name".to_string(), "Rusted".to_string())]);
+        let lookup: jinja::JinjaFunction = Arc::new(move |args: Vec<jinja::JinjaValue>| {
+            let key = args.first().map(ToString::to_string).unwrap_or_default();
+            match settings.get(&key) {
+                Some(value) => Ok(value.as_str().into()),
+                None => Err(jinja::JinjaError::Other(format!("No setting {}", key))),
+            }
+        });
+        let mut functions: HashMap<&str, jinja::JinjaFunction> = HashMap::new();
+        functions.insert("setting", lookup);
+
+        let render = |template: &str| {
+            render_template_string(
+                template.to_string(),
+                HashMap::new(),
+                Some(functions.clone()),
+            )
+        };
+        assert_eq!(render(r#"{{ setting("site_name") }}!"#).unwrap(), "Rusted!");
+        assert!(matches!(
+            render(r#"{{ setting("missing") }}"#),
+            Err(jinja::JinjaError::Other(message)) if message == "No setting missing"
+        ));
+    }
+
+    #[test]
+    fn test_status_line_round_trip() -> Result<(), core::http::Error> {
+        let upstream = core::http::HTTPResponse::read_http_response(&mut Cursor::new(
+            b"HTTP/1.0 203 Mirrored\r\nContent-Length: 5\r\n\r\nproxy".to_vec(),
+        ))?;
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", move |_| {
+            core::http::HTTPResponse::new()
+                .with_status_line(
+                    upstream.httpversion,
+                    upstream.statuscode.clone(),
+                    upstream.reason.clone(),
+                )
+                .with_content(upstream.content.clone())
+                .with_header("Content-Length".to_string(), "5".to_string())
+        });
+
+        let mut stream = MockStream::new(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        app.handle_connection(&mut stream);
+        assert!(stream.output.starts_with(b"HTTP/1.0 203 Mirrored\r\n"));
+        let response =
+            core::http::HTTPResponse::read_http_response(&mut Cursor::new(stream.output))?;
+        assert_eq!(response.content, b"proxy");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_compiled_template() -> Result<(), jinja::JinjaError> {
+        // parsed once here, both renders below only walk the parsed nodes
+        let template = jinja::compile(
+            "{% with greeting = \"Hi\" %}{{ greeting }}, {{ name | upper }}!{% endwith %}",
+        )?;
+        let render = |name: &str| {
+            let variables = HashMap::from([("name", jinja::JinjaValue::from(name))]);
+            template.render(&variables, None)
+        };
+        assert_eq!(render("ferris")?, "Hi, FERRIS!");
+        assert_eq!(render("corro")?, "Hi, CORRO!");
+        Ok(())
+    }
+
+    #[test]
+    fn test_test_client() {
+        let mut app = flask::App::new("test".to_string());
+        app.route_with_allowed_methods(
+            "/echo",
+            |request| {
+                let mut body = request.headers["X-Prefix"].clone();
+                body.push_str(&String::from_utf8_lossy(&request.content));
+                body.as_str().into()
+            },
+            vec!["POST".to_string()],
+        );
+
+        let response = app
+            .test()
+            .method("POST")
+            .path("/echo")
+            .header("X-Prefix", "got: ")
+            .body("hello")
+            .send();
+        assert_eq!(response.statuscode.status_code_number(), 200);
+        assert_eq!(response.content, b"got: hello");
+
+        // method checks still apply
+        let response = app.test().path("/echo").send();
+        assert_eq!(response.statuscode.status_code_number(), 405);
+    }
+
+    #[test]
+    fn test_header_timeout() -> Result<(), core::http::Error> {
+        /// Sends a byte at a time, waiting between each one
+        struct SlowStream {
+            input: Cursor<Vec<u8>>,
+            output: Vec<u8>,
+        }
+        impl Read for SlowStream {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                thread::sleep(Duration::from_millis(5));
+                let length = buf.len().min(1);
+                self.input.read(&mut buf[..length])
+            }
+        }
+        impl Write for SlowStream {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.output.write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let limits = core::http::RequestLimits {
+            header_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let mut request = b"GET / HTTP/1.1\r\n".to_vec();
+        request.extend(b"X-Slow: ".iter().chain([b'z'; 100].iter()));
+        request.extend(b"\r\n\r\n");
+        let mut app = flask::App::new("test".to_string()).with_request_limits(limits);
+        app.route("/", |_| "works".into());
+        let mut stream = SlowStream {
+            input: Cursor::new(request),
+            output: Vec::new(),
+        };
+        app.handle_connection(&mut stream);
+        let response =
+            core::http::HTTPResponse::read_http_response(&mut Cursor::new(stream.output))?;
+        assert_eq!(response.statuscode.status_code_number(), 408);
+        assert_eq!(response.headers["Connection"], "close");
+        Ok(())
+    }
+
+    #[test]
+    fn test_preferred_language() {
+        let with_languages = |accept_language: &str| {
+            let mut request = test_request("GET", "/");
+            request
+                .headers
+                .insert("Accept-Language".to_string(), accept_language.to_string());
+            request
+        };
+        let available = ["en-US", "fr", "de-DE"];
+
+        let request = with_languages("fr-CH, fr;q=0.9, en;q=0.8");
+        assert_eq!(request.preferred_language(&["fr-CH", "fr"]), Some("fr-CH"));
+        // `fr-CH` falls back to the more general `fr`
+        assert_eq!(request.preferred_language(&available), Some("fr"));
+
+        let request = with_languages("en;q=0.7, de;q=0.9");
+        assert_eq!(request.preferred_language(&available), Some("de-DE"));
+
+        let request = with_languages("ja, zh;q=0.5, fr;q=0");
+        assert_eq!(request.preferred_language(&available), None);
+        assert_eq!(
+            test_request("GET", "/").preferred_language(&available),
+            None
+        );
+    }
+
+    #[test]
+    fn test_chunked_request_body() -> Result<(), core::http::Error> {
+        let mut app = flask::App::new("test".to_string());
+        app.route_with_allowed_methods(
+            "/upload",
+            |request| String::from_utf8_lossy(&request.content).as_ref().into(),
+            vec!["POST".to_string()],
+        );
+        let mut stream = MockStream::new(
+            b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n7;name=value\r\n, world\r\n0\r\n\r\n\
+              GET /upload HTTP/1.1\r\nConnection: close\r\n\r\n",
+        );
+        app.handle_connection(&mut stream);
+        let mut output = Cursor::new(stream.output);
+        let response = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(response.content, b"hello, world");
+        // the next request is read from the right place
+        let response = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(response.statuscode.status_code_number(), 405);
+
+        // the limit is on the decoded body
+        let limits = core::http::RequestLimits {
+            max_body_size: 8,
+            ..Default::default()
+        };
+        let mut request = Cursor::new(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"
+                .to_vec(),
+        );
+        assert!(matches!(
+            core::http::HTTPRequest::read_http_request_with_limits(&mut request, &limits),
+            Err(core::http::Error::BodyTooLarge)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_route_suggestions() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/about", |_| "about".into());
+        app.route("/contact", |_| "contact".into());
+
+        let response = app.test().path("/abou").send();
+        assert_eq!(response.statuscode.status_code_number(), 404);
+        assert!(!String::from_utf8_lossy(&response.content).contains("/about"));
+
+        let app = app.with_debug(true);
+        let response = app.test().path("/abou").send();
+        let page = String::from_utf8_lossy(&response.content);
+        assert!(page.contains("Did you mean:\n/about"));
+        assert!(!page.contains("/contact"));
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_for_else() -> Result<(), jinja::JinjaError> {
+        let template =
+            "{% for result in results %}<li>{{ result }}</li>{% else %}No results{% endfor %}";
+        let render = |results: Vec<&str>| {
+            let variables = HashMap::from([("results", jinja::JinjaValue::from(results))]);
+            render_template_string(template.to_string(), variables, None)
+        };
+        assert_eq!(render(vec!["a", "b"])?, "<li>a</li><li>b</li>");
+        assert_eq!(render(Vec::new())?, "No results");
+        let variables = HashMap::from([("results", jinja::JinjaValue::from(vec!["a", "it's"]))]);
+        assert_eq!(
+            render_template_string("{{ results }}".to_string(), variables, None)?,
+            r"['a', 'it\'s']"
+        );
+
+        // `else` is optional
+        let variables = HashMap::from([("results", jinja::JinjaValue::from(Vec::<i64>::new()))]);
+        assert_eq!(
+            render_template_string(
+                "[{% for result in results %}{{ result }}{% endfor %}]".to_string(),
+                variables,
+                None
+            )?,
+            "[]"
+        );
+        assert!(matches!(
+            render_template_string(
+                "{% for result in results %}{% else %}".to_string(),
+                HashMap::new(),
+                None
+            ),
+            Err(jinja::JinjaError::SyntaxError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_equality() {
+        let expected = core::http::HTTPResponse::from("works")
+            .with_header("X-First".to_string(), "1".to_string())
+            .with_header("X-Second".to_string(), "2".to_string());
+        let actual = core::http::HTTPResponse::from("works")
+            .with_header("X-Second".to_string(), "2".to_string())
+            .with_header("X-First".to_string(), "1".to_string());
+        assert_eq!(actual, expected);
+        assert_ne!(actual.clone().with_content(b"other".to_vec()), expected);
+        assert_eq!(test_request("GET", "/"), test_request("GET", "/"));
+        assert_ne!(test_request("GET", "/"), test_request("POST", "/"));
+    }
+
+    #[test]
+    fn test_multipart_save_to() -> Result<(), Box<dyn std::error::Error>> {
+        let folder =
+            std::env::temp_dir().join(format!("rustedflask-upload-{}", std::process::id()));
+        std::fs::create_dir_all(&folder)?;
+
+        let mut app = flask::App::new("test".to_string());
+        let upload_folder = folder.clone();
+        app.route_with_allowed_methods(
+            "/upload",
+            move |request| {
+                let parts = request.multipart().unwrap();
+                assert_eq!(parts[0].name.as_deref(), Some("title"));
+                assert_eq!(parts[0].content, b"Notes");
+                let saved = parts[1].save_to(&upload_folder).unwrap();
+                saved.to_string_lossy().as_ref().into()
+            },
+            vec!["POST".to_string()],
+        );
+        let body = "--XyZ\r\n\
+                    Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+                    Notes\r\n\
+                    --XyZ\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"../../notes.txt\"\r\n\
+                    Content-Type: text/plain\r\n\r\n\
+                    line one\r\nline two\r\n\
+                    --XyZ--\r\n";
+        let response = app
+            .test()
+            .method("POST")
+            .path("/upload")
+            .header("Content-Type", "multipart/form-data; boundary=XyZ")
+            .body(body)
+            .send();
+
+        // the folders in the filename are dropped
+        let saved = folder.join("notes.txt");
+        assert_eq!(
+            String::from_utf8_lossy(&response.content),
+            saved.to_string_lossy()
+        );
+        assert_eq!(std::fs::read(&saved)?, b"line one\r\nline two");
+        std::fs::remove_dir_all(&folder)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_line_breaks_dropped() {
+        let mut response = core::http::HTTPResponse::new().with_statuscode(
+            core::http::HttpStatusCodes::Ok,
+            b"OK\r\nSet-Cookie: reason=injected".to_vec().into(),
+        );
+        response.headers.insert(
+            "X-Name".to_string(),
+            "value\r\n\r\n<h1>injected</h1>".to_string(),
+        );
+        let bytes = Vec::<u8>::from(response);
+        let text = String::from_utf8(bytes).unwrap();
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+        let lines: Vec<&str> = head.split("\r\n").collect();
+        assert_eq!(lines[0], "HTTP/1.1 200 OKSet-Cookie: reason=injected");
+        assert_eq!(lines[1..], ["X-Name: value<h1>injected</h1>"]);
+        assert_eq!(body, "");
+    }
+
+    #[test]
+    fn test_after_request() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "Hello".into());
+        let seen_request_ids = Arc::new(AtomicUsize::new(0));
+        let seen = seen_request_ids.clone();
+        app.after_request(move |response| {
+            if response
+                .iter_headers()
+                .any(|(header, _)| header == flask::REQUEST_ID_HEADER)
+            {
+                seen.fetch_add(1, Ordering::SeqCst);
+            }
+            response
+                .headers
+                .insert("X-Frame-Options".to_string(), "DENY".to_string());
+        });
+
+        let response = app.test().send();
+        assert_eq!(seen_request_ids.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            core::http::get_header(&response.headers, "X-Frame-Options"),
+            Some(&"DENY".to_string())
+        );
+        // serializing borrows, so the response can still be used after
+        let bytes = response.to_bytes();
+        assert!(bytes.ends_with(b"\r\n\r\nHello"));
+        assert_eq!(response.content, b"Hello");
+        assert_eq!(Vec::<u8>::from(response), bytes);
+    }
+
+    #[test]
+    fn test_header_injection_rejected() {
+        use crate::core::http::{Error, HTTPResponse};
+
+        let response = HTTPResponse::redirect("/next").unwrap();
+        assert_eq!(
+            core::http::get_header(&response.headers, "Location"),
+            Some(&"/next".to_string())
+        );
+        assert!(matches!(
+            HTTPResponse::redirect("/next\r\nSet-Cookie: session=stolen"),
+            Err(Error::InvalidHeader)
+        ));
+        assert!(matches!(
+            HTTPResponse::redirect("/next\0"),
+            Err(Error::InvalidHeader)
+        ));
+
+        let mut response = HTTPResponse::new();
+        assert!(matches!(
+            response.set_cookie("theme", "dark\r\nX-Injected: yes"),
+            Err(Error::InvalidHeader)
+        ));
+        assert!(matches!(
+            response.set_cookie("theme", "dark; Max-Age=99999999"),
+            Err(Error::InvalidHeader)
+        ));
+        assert!(response.headers.is_empty());
+        response.set_cookie("theme", "dark").unwrap();
+        assert_eq!(
+            core::http::get_header(&response.headers, "Set-Cookie"),
+            Some(&"theme=dark".to_string())
+        );
+
+        assert!(HTTPResponse::new()
+            .try_with_header("X-Bad\nName".to_string(), "value".to_string())
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_is_tests() -> Result<(), jinja::JinjaError> {
+        let render = |template: &str, variables: HashMap<&str, jinja::JinjaValue>| {
+            render_template_string(template.to_string(), variables, None)
+        };
+        let template = "{% if user is defined %}Hi, {{ user }}{% else %}Log in{% endif %}";
+        let variables = HashMap::from([("user", jinja::JinjaValue::from("Ferris"))]);
+        assert_eq!(render(template, variables)?, "Hi, Ferris");
+        assert_eq!(render(template, HashMap::new())?, "Log in");
+
+        let template = "{% if n is even %}even{% elif n is odd %}odd{% endif %}";
+        let variables = HashMap::from([("n", jinja::JinjaValue::Int(4))]);
+        assert_eq!(render(template, variables)?, "even");
+        let variables = HashMap::from([("n", jinja::JinjaValue::Int(-3))]);
+        assert_eq!(render(template, variables)?, "odd");
+
+        let variables = HashMap::from([("n", jinja::JinjaValue::Int(3))]);
+        assert_eq!(
+            render(
+                "{{ n is not even }} {{ n is string }} {{ n is number }} {{ none is none }} {{ m is undefined }}",
+                variables
+            )?,
+            "True False True True True"
+        );
+        // only an undefined variable can be tested for, other errors still fail
+        assert!(matches!(
+            render("{{ f() is defined }}", HashMap::new()),
+            Err(jinja::JinjaError::NoSuchFunction(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_format() {
+        let mut app = flask::App::new("test".to_string());
+        let not_found = |app: &flask::App| {
+            let response = app.test().path("/missing").send();
+            assert_eq!(response.statuscode.status_code_number(), 404);
+            let content_type = core::http::get_header(&response.headers, "Content-Type").cloned();
+            (String::from_utf8(response.content).unwrap(), content_type)
+        };
+
+        assert_eq!(not_found(&app), ("404 Not Found".to_string(), None));
+
+        app.error_format(flask::ErrorFormat::Json);
+        assert_eq!(
+            not_found(&app),
+            (
+                r#"{"status":404,"error":"Not Found"}"#.to_string(),
+                Some("application/json".to_string())
+            )
+        );
+
+        app.error_format(flask::ErrorFormat::Html);
+        let (content, content_type) = not_found(&app);
+        assert!(content.contains("<title>404 Not Found</title>"));
+        assert!(content.contains("<p>The requested URL was not found on the server.</p>"));
+        assert_eq!(content_type.as_deref(), Some("text/html; charset=utf-8"));
+
+        // details only show up in debug mode, escaped for the format
+        let mut app = app.with_debug(true);
+        app.route("/fail", |_| panic!("<\"oops\">"));
+        app.error_format(flask::ErrorFormat::Json);
+        let response = app.test().path("/fail").send();
+        assert!(String::from_utf8(response.content)
+            .unwrap()
+            .starts_with(r#"{"status":500,"error":"Internal Server Error","details":""#));
+    }
+
+    #[test]
+    fn test_shutdown_drain() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/slow", |_| {
+            thread::sleep(Duration::from_millis(500));
+            "done".into()
+        });
+        app.route("/fast", |_| "done".into());
+        let shutdown = app.shutdown_handle();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || app.serve(listener));
+
+        let request = |path: &str| {
+            let mut client = std::net::TcpStream::connect(address).unwrap();
+            write!(client, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            client
+        };
+        let read_response = |mut client: std::net::TcpStream| {
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).unwrap();
+            response
+        };
+
+        // a request that's done before the drain timeout isn't counted
+        let client = request("/fast");
+        let slow_client = request("/slow");
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(shutdown.in_flight(), 1);
+        assert_eq!(shutdown.shutdown(Duration::from_millis(50)), 1);
+        server.join().unwrap();
+        assert!(std::net::TcpStream::connect(address).is_err());
+
+        // the slow request still finishes, and then the connection is closed
+        assert!(read_response(slow_client).ends_with(b"\r\n\r\ndone"));
+        assert_eq!(shutdown.shutdown(Duration::from_secs(1)), 0);
+        drop(client);
+    }
+
+    #[test]
+    fn test_with_body_from_reader() -> std::io::Result<()> {
+        let response = core::http::HTTPResponse::new()
+            .with_body_iter(std::iter::empty(), None)
+            .with_body_from_reader(Cursor::new(b"Hello, reader!".to_vec()))?;
+        assert_eq!(response.content, b"Hello, reader!");
+        assert!(response.stream.is_none());
+        assert_eq!(
+            core::http::get_header(&response.headers, "Content-Length"),
+            Some(&"14".to_string())
+        );
+        assert_eq!(
+            core::http::get_header(&response.headers, "Transfer-Encoding"),
+            None
+        );
+
+        struct FailingReader;
+        impl Read for FailingReader {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disk on fire"))
+            }
+        }
+        assert!(core::http::HTTPResponse::new()
+            .with_body_from_reader(FailingReader)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_deadline() {
+        /// Gives out one byte at a time, slowly
+        struct TricklingReader {
+            data: Cursor<Vec<u8>>,
+        }
+        impl Read for TricklingReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                thread::sleep(Duration::from_millis(5));
+                let end = buf.len().min(1);
+                self.data.read(&mut buf[..end])
+            }
+        }
+        let trickle = |data: &[u8]| {
+            std::io::BufReader::new(TricklingReader {
+                data: Cursor::new(data.to_vec()),
+            })
+        };
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 20\r\n\r\n01234567890123456789";
+        let request = b"POST / HTTP/1.1\r\nContent-Length: 20\r\n\r\n01234567890123456789";
+
+        let soon = || std::time::Instant::now() + Duration::from_millis(50);
+        assert!(matches!(
+            core::http::HTTPResponse::read_http_response_deadline(&mut trickle(response), soon()),
+            Err(core::http::Error::DeadlineExceeded)
+        ));
+        assert!(matches!(
+            core::http::HTTPRequest::read_http_request_deadline(&mut trickle(request), soon()),
+            Err(core::http::Error::DeadlineExceeded)
+        ));
+        // the deadline also covers the body, after the head's arrived
+        let mut slow_body = trickle(&request[39..]);
+        let mut stream = Cursor::new(request[..39].to_vec()).chain(&mut slow_body);
+        assert!(matches!(
+            core::http::HTTPRequest::read_http_request_deadline(&mut stream, soon()),
+            Err(core::http::Error::DeadlineExceeded)
+        ));
+
+        let later = std::time::Instant::now() + Duration::from_secs(60);
+        let response =
+            core::http::HTTPResponse::read_http_response_deadline(&mut trickle(response), later)
+                .unwrap();
+        assert_eq!(response.content, b"01234567890123456789");
+    }
+
+    #[test]
+    fn test_post_json() -> Result<(), core::http::Error> {
+        let mut request = core::http::HTTPRequest::post_json("/api/users", r#"{"name":"Ferris"}"#);
+        request
+            .headers
+            .insert("Host".to_string(), "example.com".to_string());
+        assert_eq!(request.content, br#"{"name":"Ferris"}"#);
+
+        // it reads back as the same request
+        let bytes = request.to_bytes();
+        assert!(bytes.starts_with(b"POST /api/users HTTP/1.1\r\n"));
+        assert!(bytes.ends_with(b"\r\n\r\n{\"name\":\"Ferris\"}"));
+        let parsed = core::http::HTTPRequest::read_http_request(&mut Cursor::new(bytes))?;
+        assert_eq!(parsed.headers["Content-Type"], "application/json");
+        assert_eq!(parsed.headers["Content-Length"], "17");
+        assert_eq!(parsed.content, request.content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeated_headers_combined() -> Result<(), core::http::Error> {
+        let mut stream = Cursor::new(
+            b"GET / HTTP/1.1\r\n\
+              Accept: text/html\r\n\
+              Cache-Control: no-cache\r\n\
+              accept: application/json;q=0.9\r\n\r\n"
+                .to_vec(),
+        );
+        let request = core::http::HTTPRequest::read_http_request(&mut stream)?;
+        assert_eq!(
+            request.get_combined("ACCEPT").as_deref(),
+            Some("text/html, application/json;q=0.9")
+        );
+        assert_eq!(
+            request.get_combined("Cache-Control").as_deref(),
+            Some("no-cache")
+        );
+        assert_eq!(request.get_combined("Cookie"), None);
+
+        // cookies can have commas in them, so they're kept apart
+        let mut stream = Cursor::new(
+            b"HTTP/1.1 200 OK\r\n\
+              Set-Cookie: a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT\r\n\
+              Set-Cookie: b=2\r\n\
+              Content-Length: 0\r\n\r\n"
+                .to_vec(),
+        );
+        let response = core::http::HTTPResponse::read_http_response(&mut stream)?;
+        assert_eq!(response.get_combined("Set-Cookie").as_deref(), Some("b=2"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_single_threaded() {
+        let mut app = flask::App::new("test".to_string());
+        let handler_threads = Arc::new(Mutex::new(Vec::new()));
+        let threads = handler_threads.clone();
+        app.route("/", move |_| {
+            let mut threads = threads.lock().unwrap();
+            threads.push(thread::current().id());
+            threads.len().to_string().into()
+        });
+        let shutdown = app.shutdown_handle();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            app.serve_single_threaded(listener);
+            thread::current().id()
+        });
+
+        for expected in 1..=3 {
+            let mut client = std::net::TcpStream::connect(address).unwrap();
+            write!(client, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).unwrap();
+            assert!(response.ends_with(format!("\r\n\r\n{}", expected).as_bytes()));
+        }
+        assert_eq!(shutdown.shutdown(Duration::from_secs(1)), 0);
+        let server_thread = server.join().unwrap();
+        // every request was handled on the thread serving
+        assert_eq!(*handler_threads.lock().unwrap(), vec![server_thread; 3]);
+    }
+
+    #[test]
+    fn test_cors_origins() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "Hello".into());
+        app.cors(
+            flask::Cors::new()
+                .allow_origin("https://example.com")
+                .allow_origin("https://*.example.com"),
+        );
+        let from = |app: &flask::App, origin: &str| app.test().header("Origin", origin).send();
+        let allow_origin = |response: &core::http::HTTPResponse| {
+            core::http::get_header(&response.headers, "Access-Control-Allow-Origin").cloned()
+        };
+
+        let response = from(&app, "https://api.example.com");
+        assert_eq!(response.content, b"Hello");
+        assert_eq!(
+            allow_origin(&response).as_deref(),
+            Some("https://api.example.com")
+        );
+        assert_eq!(response.headers["Vary"], "Origin");
+        assert_eq!(
+            allow_origin(&from(&app, "https://example.com")).as_deref(),
+            Some("https://example.com")
+        );
+        // the wildcard only stands in for subdomains
+        for origin in [
+            "https://evil.com",
+            "https://evilexample.com",
+            "https://evil.com/.example.com",
+            "http://api.example.com",
+        ] {
+            let response = from(&app, origin);
+            assert_eq!(allow_origin(&response), None, "{}", origin);
+            assert!(!response
+                .iter_headers()
+                .any(|(header, _)| header.starts_with("Access-Control-")));
+        }
+
+        // with credentials, the origin is echoed even when any is allowed
+        app.cors(flask::Cors::new().allow_origin("*").allow_credentials(true));
+        let response = from(&app, "https://other.org");
+        assert_eq!(
+            allow_origin(&response).as_deref(),
+            Some("https://other.org")
+        );
+        assert_eq!(response.headers["Access-Control-Allow-Credentials"], "true");
+        app.cors(flask::Cors::new().allow_origin("*"));
+        assert_eq!(
+            allow_origin(&from(&app, "https://other.org")).as_deref(),
+            Some("*")
+        );
+
+        // preflights are answered without reaching the route
+        let response = app
+            .test()
+            .method("OPTIONS")
+            .header("Origin", "https://other.org")
+            .header("Access-Control-Request-Method", "POST")
+            .header("Access-Control-Request-Headers", "Content-Type")
+            .send();
+        assert_eq!(response.statuscode.status_code_number(), 204);
+        assert_eq!(
+            response.headers["Access-Control-Allow-Methods"],
+            "GET, HEAD, POST"
+        );
+        assert_eq!(
+            response.headers["Access-Control-Allow-Headers"],
+            "Content-Type"
+        );
+    }
+
+    #[test]
+    fn test_body_parser() {
+        /// What a "key=value" body is parsed into
+        struct Pair(String, String);
+
+        let mut app = flask::App::new("test".to_string());
+        app.body_parser("application/x-pair", |request| {
+            let body = String::from_utf8(request.content.clone())?;
+            match body.split_once('=') {
+                Some((key, value)) => Ok(core::http::ParsedBody::new(
+                    "application/x-pair",
+                    Pair(key.to_string(), value.to_string()),
+                )),
+                None => Err("missing =".into()),
+            }
+        });
+        app.route_with_allowed_methods(
+            "/",
+            |request| match request.parsed_body::<Pair>() {
+                Some(Pair(key, value)) => format!("{} is {}", key, value).into(),
+                None => "nothing parsed".into(),
+            },
+            vec!["POST".to_string()],
+        );
+        let post = |content_type: &str, body: &str| {
+            app.test()
+                .method("POST")
+                .header("Content-Type", content_type)
+                .body(body)
+                .send()
+        };
+
+        assert_eq!(
+            post("application/x-pair; charset=utf-8", "colour=blue").content,
+            b"colour is blue"
+        );
+        assert_eq!(post("text/plain", "colour=blue").content, b"nothing parsed");
+        assert_eq!(
+            post("application/x-pair", "colour").statuscode,
+            core::http::HttpStatusCodes::BadRequest
+        );
+        // multipart bodies are parsed out of the box
+        let response = app
+            .test()
+            .method("POST")
+            .header("Content-Type", "multipart/form-data; boundary=b")
+            .body("not multipart")
+            .send();
+        assert_eq!(response.statuscode.status_code_number(), 400);
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_map_loops() -> Result<(), jinja::JinjaError> {
+        let stock = HashMap::from([("red", 2), ("blue", 0)]);
+        let render = |template: &str| {
+            let variables = HashMap::from([("stock", jinja::JinjaValue::from(stock.clone()))]);
+            render_template_string(template.to_string(), variables, None)
+        };
+        // in key order, whatever order the map was in
+        assert_eq!(
+            render(
+                "{% for colour, count in stock.items() %}{{ colour }}={{ count }};{% endfor %}"
+            )?,
+            "blue=0;red=2;"
+        );
+        assert_eq!(
+            render("{% for colour in stock %}{{ colour }} {% endfor %}")?,
+            "blue red "
+        );
+        assert_eq!(
+            render("{{ stock.red }} {{ stock }}")?,
+            "2 {'blue': 0, 'red': 2}"
+        );
+        assert_eq!(render("{{ stock.green | default('none') }}")?, "none");
+        assert!(matches!(
+            render("{% for a, b, c in stock.items() %}{% endfor %}"),
+            Err(jinja::JinjaError::TypeError(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_loop_variable() -> Result<(), jinja::JinjaError> {
+        let variables = HashMap::from([
+            ("items", jinja::JinjaValue::from(vec!["a", "b", "c"])),
+            ("rows", jinja::JinjaValue::from(vec![vec![1, 2], vec![3]])),
+        ]);
+        let render =
+            |template: &str| render_template_string(template.to_string(), variables.clone(), None);
+        assert_eq!(
+            render(
+                "{% for item in items %}{% if loop.first %}[{% endif %}{{ item }}\
+                 {% if loop.last %}]{% else %}, {% endif %}{% endfor %}"
+            )?,
+            "[a, b, c]"
+        );
+        assert_eq!(
+            render("{% for item in items %}{{ loop.index }}/{{ loop.length }} {% endfor %}")?,
+            "1/3 2/3 3/3 "
+        );
+        // each loop has its own, and can see the one around it
+        assert_eq!(
+            render(
+                "{% for row in rows %}{% for cell in row %}\
+                 {{ loop.parentloop.index }}.{{ loop.index }}{{ loop.last }} \
+                 {% endfor %}{{ loop.last }};{% endfor %}"
+            )?,
+            "1.1False 1.2True False;2.1True True;"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_header_injection() {
+        let location = "/next\r\nSet-Cookie: session=stolen";
+        let response = core::http::HTTPResponse::new()
+            .with_header("Location".to_string(), location.to_string());
+        assert_eq!(
+            response.headers["Location"],
+            "/nextSet-Cookie: session=stolen"
+        );
+        let bytes = response.to_bytes();
+        let head = String::from_utf8_lossy(&bytes);
+        assert!(!head.contains("\r\nSet-Cookie"));
+
+        assert!(matches!(
+            core::http::HTTPResponse::new().with_cookie("session", "x\r\nLocation: /evil"),
+            Err(core::http::Error::InvalidHeader)
+        ));
+        let response = core::http::HTTPResponse::new()
+            .with_cookie("session", "abc123")
+            .unwrap();
+        assert_eq!(response.headers["Set-Cookie"], "session=abc123");
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_render_template_with_files() -> Result<(), jinja::JinjaError> {
+        let (body, files) =
+            jinja::render_template_with_files("trace/child.html.jinja2", HashMap::new(), None)?;
+        assert_eq!(body, "<main>child</main>\n<footer>footer</footer>\n");
+        // the templates folder can be somewhere else while
+        // `test_config_from_env` runs, with the same templates in it
+        let read: Vec<_> = files
+            .iter()
+            .map(|file| file.strip_prefix(file.ancestors().nth(2).unwrap()).unwrap())
+            .collect();
+        assert_eq!(
+            read,
+            vec![
+                std::path::Path::new("trace/child.html.jinja2"),
+                std::path::Path::new("trace/base.html.jinja2"),
+                std::path::Path::new("trace/footer.html.jinja2"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_redirect_from() -> Result<(), core::http::Error> {
+        let request = core::http::HTTPRequest {
+            method: b"GET".to_vec(),
+            path: b"/account/settings?tab=1".to_vec(),
+            raw_path: b"/account/settings?tab=1".to_vec(),
+            httptag: Box::new(b"HTTP".to_owned()),
+            httpversion: (1, 1),
+            headers: HashMap::from([("Host".to_string(), "example.com:8080".to_string())]),
+            content: Vec::new(),
+            request_id: None,
+            secure: true,
+            parsed_body: None,
+            lazy_body: None,
+            path_params: HashMap::new(),
+        };
+
+        let response =
+            core::http::HTTPResponse::redirect_from(&request, "http://other.example/home")?;
+        assert_eq!(response.headers["Location"], "http://other.example/home");
+
+        let response = core::http::HTTPResponse::redirect_from(&request, "/login")?;
+        assert_eq!(response.statuscode.status_code_number(), 302);
+        assert_eq!(
+            response.headers["Location"],
+            "https://example.com:8080/login"
+        );
+
+        let response = core::http::HTTPResponse::redirect_from(&request, "profile")?;
+        assert_eq!(
+            response.headers["Location"],
+            "https://example.com:8080/account/profile"
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_no_such_filter() {
+        let mut variables = HashMap::new();
+        variables.insert("x", jinja::JinjaValue::from("value"));
+        // a missing filter is told apart from a missing variable
+        assert!(matches!(
+            render_template_string("{{ x | upper | bogus }}".to_string(), variables, None),
+            Err(jinja::JinjaError::NoSuchFilter(ref filter)) if filter == "bogus"
+        ));
+        assert!(matches!(
+            render_template_string("{{ y | upper }}".to_string(), HashMap::new(), None),
+            Err(jinja::JinjaError::NoSuchVariable(_))
+        ));
+    }
+
+    #[test]
+    fn test_strict_methods() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "works".into());
+
+        // without strict mode, any method a route doesn't allow is a 405
+        let response = app.test().method("FROB").send();
+        assert_eq!(response.statuscode.status_code_number(), 405);
+
+        app.strict_methods(true);
+        let response = app.test().method("DELETE").send();
+        assert_eq!(response.statuscode.status_code_number(), 405);
+        let response = app.test().method("FROB").send();
+        assert_eq!(response.statuscode.status_code_number(), 501);
+        let response = app.test().method("get").send();
+        assert_eq!(response.statuscode.status_code_number(), 501);
+        let response = app.test().send();
+        assert_eq!(response.content, b"works");
+    }
+
+    #[test]
+    fn test_append_to_body() -> std::io::Result<()> {
+        use std::io::Write;
+
+        let mut response = core::http::HTTPResponse::new();
+        response.append_to_body(b"<ul>");
+        write!(response.body_writer(), "<li>{}</li>", 1)?;
+        response.append_to_body(b"</ul>");
+        assert_eq!(response.content, b"<ul><li>1</li></ul>");
+        assert_eq!(response.headers["Content-Length"], "19");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_sandbox() {
+        use jinja::{render_template_string_sandboxed, JinjaError, JinjaFunction, Sandbox};
+
+        let functions = || {
+            let mut functions: HashMap<&str, JinjaFunction> = HashMap::new();
+            functions.insert("greet", Arc::new(|_| Ok("hello".into())));
+            functions.insert("secret", Arc::new(|_| Ok("hunter2".into())));
+            Some(functions)
+        };
+        let sandbox = Sandbox::new().allow_functions(&["greet"]);
+        let render = |template: &str| {
+            render_template_string_sandboxed(
+                template.to_string(),
+                HashMap::new(),
+                functions(),
+                &sandbox,
+            )
+        };
+
+        assert_eq!(render("{{ greet() | upper }}").unwrap(), "HELLO");
+        // calls are checked before anything renders, even in branches that aren't taken
+        assert!(matches!(
+            render("{% if false %}{{ secret() }}{% endif %}"),
+            Err(JinjaError::NotAllowed(ref call)) if call == "function \"secret\""
+        ));
+        assert!(matches!(
+            render("{{ greet(secret()) }}"),
+            Err(JinjaError::NotAllowed(_))
+        ));
+        assert!(matches!(
+            render("{% include \"warm/part.html.jinja2\" %}"),
+            Err(JinjaError::NotAllowed(ref call)) if call == "include"
+        ));
+
+        let sandbox = Sandbox::new().allow_filters(&["upper"]);
+        let rendered = render_template_string_sandboxed(
+            "{{ 'a' | upper }}{{ 'b' | lower }}".to_string(),
+            HashMap::new(),
+            None,
+            &sandbox,
+        );
+        assert!(matches!(
+            rendered,
+            Err(JinjaError::NotAllowed(ref call)) if call == "filter \"lower\""
+        ));
+    }
+
+    #[test]
+    fn test_accept_backoff() {
+        let logger = MemoryLogger::default();
+        let mut app = flask::App::new("test".to_string());
+        app.set_logger(logger.clone());
+        let mut backoff = flask::AcceptBackoff::default();
+
+        // too many open files
+        let exhausted = std::io::Error::from_raw_os_error(24);
+        let aborted = std::io::Error::from(std::io::ErrorKind::ConnectionAborted);
+        assert_eq!(backoff.failed(&exhausted), Duration::from_millis(10));
+        assert_eq!(backoff.failed(&exhausted), Duration::from_millis(20));
+        assert_eq!(backoff.failed(&aborted), Duration::ZERO);
+        assert_eq!(backoff.failed(&exhausted), Duration::from_millis(40));
+        for _ in 0..20 {
+            backoff.failed(&exhausted);
+        }
+        assert_eq!(backoff.failed(&exhausted), Duration::from_secs(1));
+        backoff.succeeded();
+
+        app.accept_failed(&aborted, &mut backoff);
+        app.accept_failed(&exhausted, &mut backoff);
+        let records = logger.records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0, "warn");
+        assert_eq!(records[1].0, "error");
+        assert!(records[1].1.contains("trying again in 10ms"));
+    }
+
+    #[test]
+    fn test_resolve_location() {
+        let mut request = test_request("GET", "/docs/guide/intro?lang=en");
+        request
+            .headers
+            .insert("Host".to_string(), "example.com".to_string());
+        let resolve = |location: &str| request.resolve_location(location);
+
+        assert_eq!(
+            resolve("https://other.example/a?b").as_deref(),
+            Some("https://other.example/a?b")
+        );
+        assert_eq!(
+            resolve("//cdn.example/lib.js").as_deref(),
+            Some("http://cdn.example/lib.js")
+        );
+        assert_eq!(
+            resolve("/next?page=2").as_deref(),
+            Some("http://example.com/next?page=2")
+        );
+        assert_eq!(
+            resolve("setup").as_deref(),
+            Some("http://example.com/docs/guide/setup")
+        );
+        assert_eq!(
+            resolve("../api/./index#top").as_deref(),
+            Some("http://example.com/docs/api/index#top")
+        );
+        assert_eq!(resolve("..").as_deref(), Some("http://example.com/docs/"));
+        assert_eq!(
+            resolve("?lang=fr").as_deref(),
+            Some("http://example.com/docs/guide/intro?lang=fr")
+        );
+        assert_eq!(
+            resolve("#usage").as_deref(),
+            Some("http://example.com/docs/guide/intro?lang=en#usage")
+        );
+
+        request.secure = true;
+        assert_eq!(
+            request.resolve_location("//cdn.example/").as_deref(),
+            Some("https://cdn.example/")
+        );
+        request.headers.clear();
+        assert_eq!(request.resolve_location("/next"), None);
+        assert_eq!(
+            request.resolve_location("http://example.com/").as_deref(),
+            Some("http://example.com/")
+        );
+    }
+
+    #[test]
+    fn test_lazy_bodies() -> Result<(), core::http::Error> {
+        /// Remembers how much had been read when the first response was written
+        struct UploadStream {
+            input: Cursor<Vec<u8>>,
+            output: Vec<u8>,
+            read_before_response: Option<u64>,
+        }
+        impl Read for UploadStream {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.input.read(buf)
+            }
+        }
+        impl Write for UploadStream {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.read_before_response
+                    .get_or_insert(self.input.position());
+                self.output.write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut app = flask::App::new("test".to_string());
+        app.lazy_bodies(true);
+        app.route_with_allowed_methods(
+            "/upload",
+            |_| {
+                core::http::HTTPResponse::from("Log in first").with_statuscode(
+                    core::http::HttpStatusCodes::Unauthorized,
+                    Box::new(b"Unauthorized".to_owned()),
+                )
+            },
+            vec!["POST".to_string()],
+        );
+        app.route_with_allowed_methods(
+            "/echo",
+            |mut request| match request.read_body() {
+                Ok(body) => core::http::HTTPResponse::new().with_content(body.to_vec()),
+                Err(_) => "unreadable".into(),
+            },
+            vec!["POST".to_string()],
+        );
+
+        let head = b"POST /upload HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n";
+        let mut input = head.to_vec();
+        input.extend(vec![b'x'; 1_000_000]);
+        input.extend(b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello");
+        let mut stream = UploadStream {
+            input: Cursor::new(input),
+            output: Vec::new(),
+            read_before_response: None,
+        };
+        app.handle_connection(&mut stream);
+
+        // the upload was turned away before any more of it was read than
+        // fit in the connection's buffer along with the head
+        assert!(stream
+            .read_before_response
+            .is_some_and(|read| read >= head.len() as u64 && read <= 8 * 1024));
+        let mut output = Cursor::new(stream.output);
+        let first = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(first.statuscode.status_code_number(), 401);
+        assert_eq!(first.headers["Connection"], "keep-alive");
+        // and skipped, so the next request on the connection still works
+        let second = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(second.content, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_internals() {
+        use crate::internals::{header_parameter, media_type};
+
+        let content_type = "Multipart/Form-Data; charset=utf-8; Boundary=\"abc\"";
+        assert_eq!(media_type(content_type), "multipart/form-data");
+        assert_eq!(
+            header_parameter(content_type, "boundary").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            header_parameter(content_type, "charset").as_deref(),
+            Some("utf-8")
+        );
+        assert_eq!(header_parameter(content_type, "name"), None);
+    }
+
+    #[test]
+    fn test_header_constants() {
+        use crate::core::http::headers;
+
+        assert_eq!(headers::CONTENT_TYPE, "Content-Type");
+        assert_eq!(headers::CONTENT_LENGTH, "Content-Length");
+        assert_eq!(headers::HOST, "Host");
+        assert_eq!(headers::SET_COOKIE, "Set-Cookie");
+        assert_eq!(
+            headers::ACCESS_CONTROL_ALLOW_ORIGIN,
+            "Access-Control-Allow-Origin"
+        );
+
+        let mut request = test_request("POST", "/");
+        request
+            .headers
+            .insert("content-type".to_string(), "text/plain".to_string());
+        assert_eq!(request.header(headers::CONTENT_TYPE), Some("text/plain"));
+        assert_eq!(request.header(headers::HOST), None);
+
+        let response = core::http::HTTPResponse::from("works");
+        assert_eq!(response.header(headers::CONTENT_LENGTH), Some("5"));
+    }
+
+    #[test]
+    fn test_pipelined_responses_in_order() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/slow", |_| {
+            thread::sleep(Duration::from_millis(500));
+            "slow".into()
+        });
+        app.route("/fast", |_| "fast".into());
+        let shutdown = app.shutdown_handle();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || app.serve(listener));
+
+        // both requests are sent before either response arrives
+        let mut client = std::net::TcpStream::connect(address).unwrap();
+        client
+            .write_all(
+                b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  GET /fast HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+
+        // another connection doesn't have to wait for the slow request
+        let mut other = std::net::TcpStream::connect(address).unwrap();
+        other
+            .write_all(b"GET /fast HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let started = std::time::Instant::now();
+        let mut other = core::http::BufferedStream::new(other);
+        let other_response = core::http::HTTPResponse::read_http_response(&mut other).unwrap();
+        assert_eq!(other_response.content, b"fast");
+        assert!(started.elapsed() < Duration::from_millis(400));
+
+        let mut client = core::http::BufferedStream::new(client);
+        let first = core::http::HTTPResponse::read_http_response(&mut client).unwrap();
+        let second = core::http::HTTPResponse::read_http_response(&mut client).unwrap();
+        assert_eq!(first.content, b"slow");
+        assert_eq!(second.content, b"fast");
+
+        shutdown.shutdown(Duration::from_secs(1));
+        server.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_dynamic_extends() -> Result<(), jinja::JinjaError> {
+        let template = "{% extends theme %}{% block content %}Hello{% endblock %}";
+        let render = |theme: &str| {
+            let mut variables = HashMap::new();
+            variables.insert("theme", jinja::JinjaValue::from(theme));
+            render_template_string(template.to_string(), variables, None)
+        };
+
+        assert_eq!(
+            render("themes/dark.html.jinja2")?,
+            "<body class=\"dark\">Hello</body>\n"
+        );
+        assert_eq!(
+            render("themes/light.html.jinja2")?,
+            "<body class=\"light\">Hello</body>\n"
+        );
+        // the parent has to be in the templates folder
+        assert!(matches!(
+            render("../Cargo.toml"),
+            Err(jinja::JinjaError::Other(_))
+        ));
+        assert!(matches!(
+            render("/etc/passwd"),
+            Err(jinja::JinjaError::Other(_))
+        ));
+        assert!(matches!(
+            render_template_string(template.to_string(), HashMap::new(), None),
+            Err(jinja::JinjaError::NoSuchVariable(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_missing_names() {
+        fn identity(args: Vec<jinja::JinjaValue>) -> Result<jinja::JinjaValue, jinja::JinjaError> {
+            Ok(args.into_iter().next().unwrap_or_else(|| "".into()))
+        }
+        let render = |template: &str| {
+            let mut functions: HashMap<&str, jinja::JinjaFunction> = HashMap::new();
+            functions.insert("identity", Arc::new(identity));
+            let mut variables = HashMap::new();
+            variables.insert(
+                "user",
+                jinja::JinjaValue::Map(
+                    [("name".to_string(), "Ferris".into())]
+                        .into_iter()
+                        .collect(),
+                ),
+            );
+            render_template_string(template.to_string(), variables, Some(functions))
+        };
+
+        assert_eq!(render("{{ user.name }}").unwrap(), "Ferris");
+        assert!(matches!(
+            render("{{ missing }}"),
+            Err(jinja::JinjaError::NoSuchVariable(ref name)) if name == "missing"
+        ));
+        assert!(matches!(
+            render("{{ identity(missing) }}"),
+            Err(jinja::JinjaError::NoSuchVariable(ref name)) if name == "missing"
+        ));
+        assert!(matches!(
+            render("{{ user.email }}"),
+            Err(jinja::JinjaError::NoSuchVariable(ref name)) if name == "user.email"
+        ));
+        assert!(matches!(
+            render("{{ shout(user.name) }}"),
+            Err(jinja::JinjaError::NoSuchFunction(ref name)) if name == "shout"
+        ));
+    }
+
+    #[test]
+    fn test_well_known_files() {
+        use crate::core::http::headers;
+
+        let mut app = flask::App::new("test".to_string());
+        // nothing is served until they're configured
+        let response = app.respond(test_request("GET", "/robots.txt"));
+        assert_eq!(response.statuscode, core::http::HttpStatusCodes::NotFound);
+
+        app.default_favicon(b"\x00\x00\x01\x00icon".to_vec());
+        app.robots_txt("User-agent: *\nDisallow:\n");
+
+        let favicon = app.respond(test_request("GET", "/favicon.ico"));
+        assert_eq!(favicon.statuscode, core::http::HttpStatusCodes::Ok);
+        assert_eq!(favicon.content, b"\x00\x00\x01\x00icon");
+        assert_eq!(favicon.header(headers::CONTENT_TYPE), Some("image/x-icon"));
+        assert_eq!(
+            favicon.header(headers::CACHE_CONTROL),
+            Some("public, max-age=86400")
+        );
+
+        let robots = app.respond(test_request("GET", "/robots.txt"));
+        assert_eq!(robots.content, b"User-agent: *\nDisallow:\n");
+        assert_eq!(robots.header(headers::CONTENT_TYPE), Some("text/plain"));
+        assert!(robots.header(headers::CACHE_CONTROL).is_some());
+
+        // an explicit route wins
+        app.route("/robots.txt", |_| "User-agent: *\nDisallow: /\n".into());
+        let robots = app.respond(test_request("GET", "/robots.txt"));
+        assert_eq!(robots.content, b"User-agent: *\nDisallow: /\n");
+    }
+
+    #[test]
+    fn test_transfer_encoding_over_content_length() -> Result<(), core::http::Error> {
+        let mut app = flask::App::new("test".to_string());
+        app.route_with_allowed_methods(
+            "/upload",
+            |request| String::from_utf8_lossy(&request.content).as_ref().into(),
+            vec!["POST".to_string()],
+        );
+        // the Content-Length would stop the body after "5\r\nhe"
+        let mut stream = MockStream::new(
+            b"POST /upload HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n0\r\n\r\n\
+              POST /upload HTTP/1.1\r\nContent-Length: 3\r\nConnection: close\r\n\r\nbye",
+        );
+        app.handle_connection(&mut stream);
+        let mut output = Cursor::new(stream.output);
+        let response = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(response.content, b"hello");
+        let response = core::http::HTTPResponse::read_http_response(&mut output)?;
+        assert_eq!(response.content, b"bye");
+
+        // responses are read the same way
+        let response = core::http::HTTPResponse::read_http_response(&mut Cursor::new(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Length: 2\r\n\r\n\
+              5\r\nhello\r\n0\r\n\r\n"
+                .to_vec(),
+        ))?;
+        assert_eq!(response.content, b"hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_content_length() -> Result<(), core::http::Error> {
+        let mut request = Cursor::new(
+            b"POST / HTTP/1.1\r\nContent-Length: 3\r\nContent-Length: 3\r\n\r\nabc".to_vec(),
+        );
+        assert!(matches!(
+            core::http::HTTPRequest::read_http_request(&mut request),
+            Err(core::http::Error::ConflictingFraming)
+        ));
+        let mut response = Cursor::new(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\nContent-Length: 5\r\n\r\nabcde".to_vec(),
+        );
+        assert!(matches!(
+            core::http::HTTPResponse::read_http_response(&mut response),
+            Err(core::http::Error::ConflictingFraming)
+        ));
+        // the server refuses it instead of guessing
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "works".into());
+        let mut stream = MockStream::new(
+            b"GET / HTTP/1.1\r\nContent-Length: 0\r\nContent-Length: 4\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+        );
+        app.handle_connection(&mut stream);
+        let response =
+            core::http::HTTPResponse::read_http_response(&mut Cursor::new(stream.output))?;
+        assert_eq!(response.statuscode, core::http::HttpStatusCodes::BadRequest);
+        Ok(())
+    }
+
+    #[test]
+    fn test_route_matcher() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "version 1".into());
+        app.route_matcher(
+            |request| request.header("X-Api-Version") == Some("2"),
+            |_| "version 2".into(),
+        );
+        app.route_matcher(
+            |request| request.header("X-Api-Version").is_some(),
+            |_| "some other version".into(),
+        );
+
+        let response = app.test().path("/").send();
+        assert_eq!(response.content, b"version 1");
+        let response = app.test().path("/").header("X-Api-Version", "2").send();
+        assert_eq!(response.content, b"version 2");
+        // matchers ignore the path and method, and the first to match wins
+        let response = app
+            .test()
+            .method("POST")
+            .path("/anything")
+            .header("x-api-version", "3")
+            .send();
+        assert_eq!(response.content, b"some other version");
+    }
+
+    #[test]
+    fn test_response_status() -> Result<(), core::http::Error> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let mut app = flask::App::new("test".to_string());
+            app.route("/", |_| "works".into());
+            for _ in 0..2 {
+                let mut stream = listener.incoming().next().unwrap().unwrap();
+                app.handle_connection(&mut stream);
+            }
+        });
+
+        let response = test_request("GET", "/").send_to(address.clone())?;
+        assert!(response.is_success());
+        assert_eq!(response.status_u16(), 200);
+        let response = test_request("GET", "/missing").send_to(address)?;
+        assert!(!response.is_success());
+        assert!(response.is_client_error());
+        assert_eq!(response.status_u16(), 404);
+        server.join().unwrap();
+
+        assert!(core::http::HttpStatusCodes::Custom(299).is_success());
+        assert!(core::http::HttpStatusCodes::Found.is_redirection());
+        assert!(core::http::HttpStatusCodes::BadGateway.is_server_error());
+        assert!(core::http::HttpStatusCodes::Continue.is_informational());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unified_error() {
+        fn read() -> Result<core::http::HTTPRequest, Error> {
+            Ok(core::http::HTTPRequest::read_http_request(
+                &mut Cursor::new(b"not http".to_vec()),
+            )?)
+        }
+        assert!(matches!(read(), Err(Error::Http(_))));
+        assert!(matches!(
+            RustedFlaskError::from(flask::CantBind),
+            RustedFlaskError::CantBind
+        ));
+        #[cfg(feature = "jinja")]
+        {
+            let rendered = || -> Result<String, Error> {
+                Ok(render_template_string(
+                    "{{ missing }}".to_string(),
+                    HashMap::new(),
+                    None,
+                )?)
+            };
+            assert!(matches!(
+                rendered(),
+                Err(Error::Jinja(jinja::JinjaError::NoSuchVariable(_)))
+            ));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_render_block() -> Result<(), jinja::JinjaError> {
+        let variables = || {
+            let mut variables = HashMap::new();
+            variables.insert("title", jinja::JinjaValue::from("Greetings"));
+            variables.insert("name", jinja::JinjaValue::from("Ferris"));
+            variables
+        };
+
+        assert_eq!(
+            jinja::render_block("blocks/page.html.jinja2", "content", variables(), None)?,
+            "<p>Hello, Ferris!</p>"
+        );
+        assert_eq!(
+            jinja::render_block("blocks/page.html.jinja2", "title", variables(), None)?,
+            "Greetings"
+        );
+        // the whole page still renders both blocks
+        assert_eq!(
+            jinja::render_template("blocks/page.html.jinja2", variables(), None)?,
+            "<h1>Greetings</h1>\n<main><p>Hello, Ferris!</p></main>\n"
+        );
+        // blocks the child doesn't fill in come from the parent
+        assert_eq!(
+            jinja::render_block("blocks/base.html.jinja2", "title", variables(), None)?,
+            "Default"
+        );
+        assert!(matches!(
+            jinja::render_block("blocks/page.html.jinja2", "sidebar", variables(), None),
+            Err(jinja::JinjaError::NoSuchBlock(ref name)) if name == "sidebar"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_constructors() -> Result<(), core::http::Error> {
+        let mut request = core::http::HTTPRequest::get("/users?page=2");
+        request
+            .headers
+            .insert("Host".to_string(), "example.com".to_string());
+        assert_eq!(
+            request.to_bytes(),
+            b"GET /users?page=2 HTTP/1.1\r\nHost: example.com\r\n\r\n"
+        );
+
+        let request = core::http::HTTPRequest::post("/users", "name=Ferris");
+        assert_eq!(
+            request.to_bytes(),
+            b"POST /users HTTP/1.1\r\nContent-Length: 11\r\n\r\nname=Ferris"
+        );
+        let parsed =
+            core::http::HTTPRequest::read_http_request(&mut Cursor::new(request.to_bytes()))?;
+        assert_eq!(parsed.method, b"POST");
+        assert_eq!(parsed.path, b"/users");
+        assert_eq!(parsed.httpversion, (1, 1));
+        assert_eq!(parsed.content, b"name=Ferris");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_render_cache() -> Result<(), jinja::JinjaError> {
+        let variables = |name: &str| {
+            let mut variables = HashMap::new();
+            variables.insert("title", jinja::JinjaValue::from("Greetings"));
+            variables.insert("name", jinja::JinjaValue::from(name));
+            variables
+        };
+        let mut state = jinja::JinjaState::new();
+        state.cache_renders(2);
+
+        let first = state.render_template_with_outcome(
+            "blocks/page.html.jinja2",
+            variables("Ferris"),
+            None,
+        )?;
+        assert!(!first.render_cache_hit);
+        let second = state.render_template_with_outcome(
+            "blocks/page.html.jinja2",
+            variables("Ferris"),
+            None,
+        )?;
+        assert!(second.render_cache_hit);
+        assert_eq!(first.body, second.body);
+        // different variables are rendered, and cached, separately
+        let other = state.render_template_with_outcome(
+            "blocks/page.html.jinja2",
+            variables("Corro"),
+            None,
+        )?;
+        assert!(!other.render_cache_hit);
+        assert!(other.body.contains("Corro"));
+        assert_eq!(state.cached_renders(), 2);
+
+        // the least recently used render makes way for new ones
+        state.render_template_with_outcome("blocks/page.html.jinja2", variables("Ferris"), None)?;
+        state.render_template_with_outcome("blocks/page.html.jinja2", variables("Ada"), None)?;
+        assert_eq!(state.cached_renders(), 2);
+        let corro = state.render_template_with_outcome(
+            "blocks/page.html.jinja2",
+            variables("Corro"),
+            None,
+        )?;
+        assert!(!corro.render_cache_hit);
+
+        // changing the template drops what it rendered to
+        let path = "./templates/render_cache_test.html.jinja2";
+        std::fs::write(path, "old {{ name }}").unwrap();
+        let render = |state: &mut jinja::JinjaState| {
+            state.render_template_with_outcome(
+                "render_cache_test.html.jinja2",
+                variables("Ferris"),
+                None,
+            )
+        };
+        assert_eq!(render(&mut state)?.body, "old Ferris");
+        assert!(render(&mut state)?.render_cache_hit);
+        let file = std::fs::File::create(path).unwrap();
+        (&file).write_all(b"new {{ name }}").unwrap();
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let changed = render(&mut state);
+        std::fs::remove_file(path).unwrap();
+        let changed = changed?;
+        assert!(!changed.render_cache_hit);
+        assert_eq!(changed.body, "new Ferris");
+        Ok(())
+    }
+
+    #[test]
+    fn test_fallback() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/api/status", |_| "OK".into());
+        let response = app.test().path("/missing").send();
+        assert_eq!(response.statuscode, core::http::HttpStatusCodes::NotFound);
+
+        app.fallback(|request| {
+            format!("fallback for {}", String::from_utf8_lossy(&request.path))
+                .as_str()
+                .into()
+        });
+        // registered routes still win
+        let response = app.test().path("/api/status").send();
+        assert_eq!(response.content, b"OK");
+        let response = app.test().path("/some/page?tab=2").send();
+        assert_eq!(response.statuscode, core::http::HttpStatusCodes::Ok);
+        assert_eq!(response.content, b"fallback for /some/page?tab=2");
+        // a method the route doesn't allow goes to the fallback too
+        let response = app.test().method("DELETE").path("/api/status").send();
+        assert_eq!(response.content, b"fallback for /api/status");
+    }
+
+    #[test]
+    fn test_request_params() {
+        let with_content_type = |mut request: core::http::HTTPRequest, content_type: &str| {
+            request
+                .headers
+                .insert("Content-Type".to_string(), content_type.to_string());
+            request
+        };
+
+        let request = with_content_type(
+            core::http::HTTPRequest::post("/", "name=Ferris+the+Crab&emoji=%F0%9F%A6%80&empty="),
+            "application/x-www-form-urlencoded",
+        );
+        let params = request.params();
+        assert_eq!(params["name"], "Ferris the Crab");
+        assert_eq!(params["emoji"], "\u{1F980}");
+        assert_eq!(params["empty"], "");
+
+        let request = with_content_type(
+            core::http::HTTPRequest::post(
+                "/",
+                r#"{"name": "Ferris \"the\" Crab", "legs": 10, "shell": true,
+                    "owner": null, "friends": ["Corro", {"name": "Ada"}], "note": "caf\u00e9"}"#,
+            ),
+            "application/json; charset=utf-8",
+        );
+        let params = request.params();
+        assert_eq!(params["name"], "Ferris \"the\" Crab");
+        assert_eq!(params["legs"], "10");
+        assert_eq!(params["shell"], "true");
+        assert_eq!(params["note"], "caf\u{e9}");
+        // nested values and nulls are left out
+        assert!(!params.contains_key("owner"));
+        assert!(!params.contains_key("friends"));
+        assert_eq!(params.len(), 4);
+
+        let request = core::http::HTTPRequest::get("/search?q=rust%20web&page=2&q=ignored");
+        let params = request.params();
+        assert_eq!(params["q"], "rust web");
+        assert_eq!(params["page"], "2");
+
+        // the query string wins over the body
+        let request = with_content_type(
+            core::http::HTTPRequest::post("/search?page=2", "page=3&q=rust"),
+            "application/x-www-form-urlencoded",
+        );
+        let params = request.params();
+        assert_eq!(params["page"], "2");
+        assert_eq!(params["q"], "rust");
+        // bodies of other types aren't read
+        let request = with_content_type(core::http::HTTPRequest::post("/", "q=rust"), "text/plain");
+        assert!(request.params().is_empty());
+    }
+
+    #[test]
+    fn test_parse_content_type() {
+        use crate::core::http::parse_content_type;
+
+        let (media_type, parameters) =
+            parse_content_type("multipart/form-data; boundary=----WebKitFormBoundary7MA4YWxk");
+        assert_eq!(media_type, "multipart/form-data");
+        assert_eq!(parameters["boundary"], "----WebKitFormBoundary7MA4YWxk");
+
+        let (media_type, parameters) = parse_content_type("Text/HTML; Charset=\"UTF-8\"");
+        assert_eq!(media_type, "text/html");
+        assert_eq!(parameters["charset"], "UTF-8");
+
+        // quoted values can have semicolons and escaped quotes in them
+        let (_, parameters) =
+            parse_content_type(r#"multipart/mixed; boundary="a;\"b\""; charset=utf-8"#);
+        assert_eq!(parameters["boundary"], "a;\"b\"");
+        assert_eq!(parameters["charset"], "utf-8");
+
+        let (media_type, parameters) = parse_content_type("application/json");
+        assert_eq!(media_type, "application/json");
+        assert!(parameters.is_empty());
+
+        let mut request = test_request("POST", "/");
+        assert_eq!(request.charset(), None);
+        request.headers.insert(
+            "content-type".to_string(),
+            "text/plain; charset=ISO-8859-1".to_string(),
+        );
+        assert_eq!(request.charset().as_deref(), Some("iso-8859-1"));
+    }
+
+    #[test]
+    fn test_handler_timeout() {
+        let mut app = flask::App::new("test".to_string());
+        let logger = MemoryLogger::default();
+        app.set_logger(logger.clone());
+        app.route("/slow", |_| {
+            thread::sleep(Duration::from_millis(500));
+            "finally".into()
+        });
+        app.route("/fast", |_| "quick".into());
+        app.handler_timeout(Some(Duration::from_millis(50)));
+
+        let started = std::time::Instant::now();
+        let response = app.test().path("/slow").send();
+        assert!(started.elapsed() < Duration::from_millis(400));
+        assert_eq!(
+            response.statuscode,
+            core::http::HttpStatusCodes::GatewayTimeout
+        );
+        assert!(
+            logger
+                .records
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|(level, message)| *level == "warn"
+                    && message.contains("/slow took longer than"))
+        );
+
+        let response = app.test().path("/fast").send();
+        assert_eq!(response.content, b"quick");
+
+        // without a timeout, slow handlers are waited for
+        app.handler_timeout(None);
+        let response = app.test().path("/slow").send();
+        assert_eq!(response.content, b"finally");
+    }
+
+    #[test]
+    fn test_chunked_request_trailers() -> Result<(), core::http::Error> {
+        let mut request = Cursor::new(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nTrailer: Checksum, Content-Length\r\n\r\n\
+              5\r\nhello\r\n0\r\n\
+              Checksum: 5d41402a\r\nContent-Length: 1\r\nUnannounced: yes\r\n\r\n"
+                .to_vec(),
+        );
+        let request = core::http::HTTPRequest::read_http_request(&mut request)?;
+        assert_eq!(request.content, b"hello");
+        assert_eq!(request.header("checksum"), Some("5d41402a"));
+        // only announced trailers are kept, and never ones that frame the body
+        assert_eq!(request.header("Unannounced"), None);
+        assert_eq!(request.header("Content-Length"), None);
+
+        // without a Trailer header, none are kept
+        let mut request = Cursor::new(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              0\r\nChecksum: 5d41402a\r\n\r\n"
+                .to_vec(),
+        );
+        let request = core::http::HTTPRequest::read_http_request(&mut request)?;
+        assert_eq!(request.header("Checksum"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_buffered_reads() -> Result<(), core::http::Error> {
+        /// Counts how many times it's read from
+        struct CountingReader {
+            data: Cursor<Vec<u8>>,
+            reads: Arc<AtomicUsize>,
+        }
+        impl Read for CountingReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.reads.fetch_add(1, Ordering::SeqCst);
+                self.data.read(buf)
+            }
+        }
+        impl Write for CountingReader {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let request = b"POST /upload HTTP/1.1\r\nHost: example.com\r\n\
+            User-Agent: test\r\nAccept: */*\r\nContent-Length: 5\r\n\r\nhello\
+            GET /next HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let reads = Arc::new(AtomicUsize::new(0));
+        let mut stream = core::http::BufferedStream::new(CountingReader {
+            data: Cursor::new(request.to_vec()),
+            reads: reads.clone(),
+        });
+        let first = core::http::HTTPRequest::read_http_request(&mut stream)?;
+        assert_eq!(first.path, b"/upload");
+        assert_eq!(first.headers["User-Agent"], "test");
+        assert_eq!(first.content, b"hello");
+        // the whole head came in one read, not one per byte
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+        // and the next request was kept in the buffer
+        let second = core::http::HTTPRequest::read_http_request(&mut stream)?;
+        assert_eq!(second.path, b"/next");
+
+        // the app reads through a buffer too
+        let mut app = flask::App::new("test".to_string());
+        app.route("/upload", |_| "uploaded".into());
+        app.route("/next", |_| "next".into());
+        let reads = Arc::new(AtomicUsize::new(0));
+        app.handle_connection(&mut CountingReader {
+            data: Cursor::new(request.to_vec()),
+            reads: reads.clone(),
+        });
+        // one read for both requests, and one finding the connection closed
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_on_ipv6() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| "hello".into());
+        // a port that's most likely free on both loopbacks, as run_on can't pick one
+        let port = std::net::TcpListener::bind("[::1]:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addresses = [format!("127.0.0.1:{}", port), format!("[::1]:{}", port)];
+        thread::spawn(move || {
+            let addresses: Vec<&str> = addresses.iter().map(String::as_str).collect();
+            app.run_on(&addresses)
+        });
+
+        for address in [format!("127.0.0.1:{}", port), format!("[::1]:{}", port)] {
+            let mut request = test_request("GET", "/");
+            request
+                .headers
+                .insert("Host".to_string(), "localhost".to_string());
+            let started = std::time::Instant::now();
+            let response = loop {
+                match request.send_to(address.clone()) {
+                    Ok(response) => break response,
+                    Err(_) if started.elapsed() < Duration::from_secs(5) => {
+                        thread::sleep(Duration::from_millis(10))
+                    }
+                    Err(why) => panic!("couldn't reach {}: {:?}", address, why),
+                }
+            };
+            assert_eq!(response.content, b"hello");
+        }
+
+        // an address that can't be bound stops the others from being bound too
+        let listener = std::net::TcpListener::bind("[::1]:0").unwrap();
+        let taken = listener.local_addr().unwrap().to_string();
+        let mut app = flask::App::new("test".to_string());
+        assert!(matches!(app.run_on(&["[::1]:0", &taken]), flask::CantBind));
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_several_substitutions() -> Result<(), jinja::JinjaError> {
+        let mut variables = HashMap::<&str, jinja::JinjaValue>::new();
+        variables.insert("a", "foo".into());
+        variables.insert("b", "bar".into());
+        let rendered =
+            render_template_string("{{ a }} and {{ b }}".to_string(), variables.clone(), None)?;
+        assert_eq!(rendered, "foo and bar");
+        let mut state = jinja::JinjaState::new();
+        let rendered =
+            state.render_template_string("{{ a }} and {{ b }}".to_string(), &variables, None)?;
+        assert_eq!(rendered, "foo and bar");
+
+        // a variable and a function call in the same template
+        fn shout(
+            arguments: Vec<jinja::JinjaValue>,
+        ) -> Result<jinja::JinjaValue, jinja::JinjaError> {
+            Ok(format!("{}!", arguments[0]).to_uppercase().into())
+        }
+        let functions = || {
+            let mut functions: HashMap<&str, jinja::JinjaFunction> = HashMap::new();
+            functions.insert("shout", Arc::new(shout));
+            functions
+        };
+        let template = "{{ a }}, then {{ shout(b) }}".to_string();
+        let rendered =
+            render_template_string(template.clone(), variables.clone(), Some(functions()))?;
+        assert_eq!(rendered, "foo, then BAR!");
+        let rendered = state.render_template_string(template, &variables, Some(functions()))?;
+        assert_eq!(rendered, "foo, then BAR!");
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_from_channel() -> Result<(), core::http::Error> {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/progress", |_| {
+            let (response, sender) = core::http::HTTPResponse::stream_from_channel();
+            thread::spawn(move || {
+                for chunk in ["one", "two", "three"] {
+                    thread::sleep(Duration::from_millis(10));
+                    sender.send(chunk.as_bytes().to_vec()).unwrap();
+                }
+            });
+            response
+        });
+        let mut stream = MockStream::new(b"GET /progress HTTP/1.1\r\nConnection: close\r\n\r\n");
+        app.handle_connection(&mut stream);
+
+        let output = String::from_utf8(stream.output.clone()).unwrap();
+        assert!(output.contains("3\r\none\r\n3\r\ntwo\r\n5\r\nthree\r\n0\r\n\r\n"));
+        let response =
+            core::http::HTTPResponse::read_http_response(&mut Cursor::new(stream.output))?;
+        assert_eq!(response.headers["Transfer-Encoding"], "chunked");
+        assert_eq!(response.content, b"onetwothree");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_post_render() -> Result<(), jinja::JinjaError> {
+        let mut variables = HashMap::<&str, jinja::JinjaValue>::new();
+        variables.insert("name", "Ferris".into());
+        let mut state = jinja::JinjaState::new();
+        let template = "Hello, {{ name }}!".to_string();
+        // off until a hook is given
+        assert_eq!(
+            state.render_template_string(template.clone(), &variables, None)?,
+            "Hello, Ferris!"
+        );
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counted = runs.clone();
+        state.post_render(move |rendered| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            rendered.to_uppercase()
+        });
+        assert_eq!(
+            state.render_template_string(template, &variables, None)?,
+            "HELLO, FERRIS!"
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // it runs on cached renders too
+        state.cache_renders(10);
+        for _ in 0..2 {
+            let outcome = state.render_template_with_outcome(
+                "api/user.json.jinja2",
+                variables.clone(),
+                None,
+            )?;
+            assert_eq!(outcome.body, "{\"NAME\": \"FERRIS\"}\n");
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_chunked_response() -> Result<(), core::http::Error> {
+        let mut chunked = b"HTTP/1.1 200 OK\r\ntransfer-encoding: Chunked\r\n\r\n\
+            7\r\nHello, \r\n6\r\nworld!\r\n0\r\n\r\n"
+            .to_vec();
+        let response = core::http::HTTPResponse::read_http_response(&mut ReadableVec {
+            vector: &mut chunked,
+        })?;
+        assert_eq!(response.content, b"Hello, world!");
+        assert!(chunked.is_empty());
+
+        // the same as if it had a Content-Length
+        let mut with_length =
+            b"HTTP/1.1 200 OK\r\nContent-Length: 13\r\n\r\nHello, world!".to_vec();
+        let same = core::http::HTTPResponse::read_http_response(&mut ReadableVec {
+            vector: &mut with_length,
+        })?;
+        assert_eq!(response.content, same.content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_into_content_length() -> Result<(), core::http::Error> {
+        let mut stream = Cursor::new(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: Expires\r\n\r\n\
+              5\r\nHello\r\n1\r\n!\r\n0\r\nExpires: never\r\n\r\n"
+                .to_vec(),
+        );
+        let response = core::http::HTTPResponse::read_http_response(&mut stream)?;
+        let response = response.into_content_length();
+        assert!(response.header("transfer-encoding").is_none());
+        assert!(response.header("trailer").is_none());
+        assert_eq!(response.header("content-length"), Some("6"));
+
+        // sent on, it reads back the same
+        let bytes = response.to_bytes();
+        assert!(bytes.ends_with(b"\r\n\r\nHello!"));
+        let resent = core::http::HTTPResponse::read_http_response(&mut Cursor::new(bytes))?;
+        assert_eq!(resent.content, b"Hello!");
+
+        // responses that weren't chunked are left alone
+        let plain = core::http::HTTPResponse::from("plain");
+        assert_eq!(plain.clone().into_content_length().headers, plain.headers);
+        Ok(())
+    }
+
+    #[test]
+    fn test_path_params() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/user/<id>", |request| {
+            format!("user {}", request.path_param("id").unwrap())
+                .as_str()
+                .into()
+        });
+        app.route("/user/me", |_| "me".into());
+        app.route("/post/<year>/<slug>", |request| {
+            format!(
+                "{} from {}",
+                request.path_param("slug").unwrap(),
+                request.path_param("year").unwrap()
+            )
+            .as_str()
+            .into()
+        });
+        let get = |path: &str| app.respond(test_request("GET", path));
+
+        assert_eq!(get("/user/42").content, b"user 42");
+        assert_eq!(get("/user/42?tab=posts").content, b"user 42");
+        // the static route wins, even though it was added later
+        assert_eq!(get("/user/me").content, b"me");
+        assert_eq!(
+            get("/post/2024/hello-world").content,
+            b"hello-world from 2024"
+        );
+        // the pattern itself is just another value for the parameter
+        assert_eq!(get("/user/%3Cid%3E").content, b"user <id>");
+        for path in ["/user/", "/user/42/posts", "/post/2024"] {
+            assert_eq!(get(path).status_u16(), 404, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_response_framing() -> Result<(), core::http::Error> {
+        let response = core::http::HTTPResponse::from("body\r\n with CRLFs\r\n");
+        let bytes = response.to_bytes();
+        // status-line CRLF headers CRLF CRLF body, and nothing after the body
+        let head_end = bytes
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap()
+            + 4;
+        assert_eq!(
+            bytes[..head_end],
+            *b"HTTP/1.1 200 OK\r\nContent-Length: 19\r\n\r\n"
+        );
+        assert_eq!(bytes[head_end..], *b"body\r\n with CRLFs\r\n");
+
+        // two responses back to back read as exactly those two
+        let mut stream = Cursor::new([bytes.clone(), bytes].concat());
+        for _ in 0..2 {
+            let parsed = core::http::HTTPResponse::read_http_response(&mut stream)?;
+            assert_eq!(parsed.content, response.content);
+        }
+        assert_eq!(stream.position() as usize, stream.get_ref().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_until_close() -> Result<(), core::http::Error> {
+        let mut response = b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nall of it".to_vec();
+        let parsed = core::http::HTTPResponse::read_http_response(&mut ReadableVec {
+            vector: &mut response,
+        })?;
+        assert_eq!(parsed.content, b"all of it");
+
+        // closing right after the head is an empty body
+        let mut response = b"HTTP/1.0 200 OK\r\n\r\n".to_vec();
+        let parsed = core::http::HTTPResponse::read_http_response(&mut ReadableVec {
+            vector: &mut response,
+        })?;
+        assert!(parsed.content.is_empty());
+
+        // a connection that breaks isn't the end of the body
+        struct BrokenReader(bool);
+        impl Read for BrokenReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if std::mem::replace(&mut self.0, true) {
+                    return Err(std::io::ErrorKind::ConnectionReset.into());
+                }
+                let head = b"HTTP/1.0 200 OK\r\n\r\npart";
+                buf[..head.len()].copy_from_slice(head);
+                Ok(head.len())
+            }
+        }
+        assert!(matches!(
+            core::http::HTTPResponse::read_http_response(&mut std::io::BufReader::new(
+                BrokenReader(false)
+            )),
+            Err(core::http::Error::StreamReadError)
+        ));
+
+        // and responses that can't have a body don't wait for the close
+        let mut stream = Cursor::new(
+            b"HTTP/1.1 304 Not Modified\r\nETag: \"a\"\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n"
+                .to_vec(),
+        );
+        let not_modified = core::http::HTTPResponse::read_http_response(&mut stream)?;
+        assert!(not_modified.content.is_empty());
+        let no_content = core::http::HTTPResponse::read_http_response(&mut stream)?;
+        assert_eq!(no_content.status_u16(), 204);
+        Ok(())
+    }
+
+    #[test]
+    fn test_route_methods() {
+        let mut app = flask::App::new("test".to_string());
+        app.route_any("/any", |request| {
+            String::from_utf8_lossy(&request.method)
+                .to_string()
+                .as_str()
+                .into()
+        });
+        app.route("/get", |_| "got".into());
+        app.default_methods(vec!["POST".to_string(), "PUT".to_string()]);
+        app.route("/no-get", |_| "written".into());
+
+        for method in ["GET", "POST", "DELETE", "PATCH"] {
+            let response = app.respond(test_request(method, "/any"));
+            assert_eq!(response.content, method.as_bytes());
+        }
+        // routes added before the change keep their methods
+        assert_eq!(app.respond(test_request("GET", "/get")).content, b"got");
+        assert_eq!(app.respond(test_request("POST", "/get")).status_u16(), 405);
+
+        assert_eq!(
+            app.respond(test_request("GET", "/no-get")).status_u16(),
+            405
+        );
+        assert_eq!(
+            app.respond(test_request("PUT", "/no-get")).content,
+            b"written"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_jinja_for_tag() -> Result<(), jinja::JinjaError> {
+        let rows = jinja::JinjaValue::from(vec![
+            jinja::JinjaValue::from(vec!["a", "b"]),
+            jinja::JinjaValue::from(vec!["c"]),
+        ]);
+        let render = |template: &str| {
+            render_template_string(
+                template.to_string(),
+                HashMap::from([("rows", rows.clone())]),
+                None,
+            )
+        };
+        // loops nest, and the tag can be spread over lines
+        assert_eq!(
+            render(
+                "{% for row in rows %}[{%\n  for cell   in\trow\n%}{{ cell }}{% endfor %}]{% endfor %}"
+            )?,
+            "[ab][c]"
+        );
+        // names can start with `in` without being taken for it
+        assert_eq!(
+            render_template_string(
+                "{% for index in inbox %}{{ index }}{% endfor %}".to_string(),
+                HashMap::from([("inbox", jinja::JinjaValue::from(vec![1, 2]))]),
+                None
+            )?,
+            "12"
+        );
+        for broken in [
+            "{% for in rows %}{% endfor %}",
+            "{% for row rows %}{% endfor %}",
+            "{% for row, in rows %}{% endfor %}",
+            "{% for row in %}{% endfor %}",
+            "{% for row in rows %}",
+        ] {
+            assert!(
+                matches!(render(broken), Err(jinja::JinjaError::SyntaxError(_))),
+                "{}",
+                broken
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_from_env() {
+        // every test that touches these variables is in here, as tests
+        // run at the same time and share the environment
+        std::env::remove_var(flask::BIND_VAR);
+        std::env::remove_var(flask::PORT_VAR);
+        let bind = flask::App::bind_address_from_env;
+        assert_eq!(bind().unwrap(), "127.0.0.1:5000");
+
+        std::env::set_var(flask::PORT_VAR, "8080");
+        assert_eq!(bind().unwrap(), "0.0.0.0:8080");
+        std::env::set_var(flask::PORT_VAR, "80808");
+        assert!(matches!(bind(), Err(RustedFlaskError::InvalidConfig(_))));
+
+        // RF_BIND wins over PORT
+        std::env::set_var(flask::BIND_VAR, "[::1]:9000");
+        assert_eq!(bind().unwrap(), "[::1]:9000");
+        std::env::set_var(flask::BIND_VAR, "127.0.0.1");
+        match bind() {
+            Err(RustedFlaskError::InvalidConfig(why)) => assert!(why.contains("RF_BIND")),
+            other => panic!("{:?}", other),
+        }
+        let mut app = flask::App::new("test".to_string());
+        assert!(matches!(
+            app.run_from_env(),
+            RustedFlaskError::InvalidConfig(_)
+        ));
+        std::env::remove_var(flask::BIND_VAR);
+        std::env::remove_var(flask::PORT_VAR);
+
+        #[cfg(feature = "jinja")]
+        {
+            std::env::remove_var(jinja::TEMPLATES_FOLDER_VAR);
+            let mut state = jinja::JinjaState::from_env().unwrap();
+            assert!(state
+                .render_template(
+                    "api/user.json.jinja2",
+                    HashMap::from([("name", "a".into())]),
+                    None
+                )
+                .is_ok());
+
+            // other tests render from the templates folder at the same time,
+            // so the one used here has all of the same templates in it
+            let folder =
+                std::env::temp_dir().join(format!("rustedflask-templates-{}", std::process::id()));
+            fn copy_folder(from: &std::path::Path, to: &std::path::Path) {
+                std::fs::create_dir_all(to).unwrap();
+                for entry in std::fs::read_dir(from).unwrap() {
+                    let path = entry.unwrap().path();
+                    let copy = to.join(path.file_name().unwrap());
+                    if path.is_dir() {
+                        copy_folder(&path, &copy);
+                    } else {
+                        std::fs::copy(&path, &copy).unwrap();
+                    }
+                }
+            }
+            copy_folder(std::path::Path::new("templates"), &folder);
+            std::fs::write(folder.join("from_env.txt.jinja2"), "from {{ where }}").unwrap();
+            std::env::set_var(jinja::TEMPLATES_FOLDER_VAR, &folder);
+
+            let mut state = jinja::JinjaState::from_env().unwrap();
+            assert_eq!(
+                state
+                    .render_template(
+                        "from_env.txt.jinja2",
+                        HashMap::from([("where", "state".into())]),
+                        None
+                    )
+                    .unwrap(),
+                "from state"
+            );
+            let mut app = flask::App::new("test".to_string());
+            app.route("/", |_| {
+                flask::render(
+                    "from_env.txt.jinja2",
+                    HashMap::from([("where", "app".into())]),
+                    None,
+                )
+                .unwrap()
+            });
+            let response = app.respond(test_request("GET", "/"));
+            assert_eq!(response.status_u16(), 200);
+            assert_eq!(response.content, b"from app");
+            let variables = HashMap::from([("where", "files".into())]);
+            assert_eq!(
+                jinja::render_template_with_files("from_env.txt.jinja2", variables, None).unwrap(),
+                (
+                    "from files".to_string(),
+                    vec![folder.join("from_env.txt.jinja2")]
+                )
+            );
+
+            std::env::remove_var(jinja::TEMPLATES_FOLDER_VAR);
+            std::fs::remove_dir_all(&folder).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_response_size_limits() {
+        use crate::core::http::{Error, HTTPResponse, RequestLimits};
+        let read = |response: &[u8], limits: &RequestLimits| {
+            HTTPResponse::read_http_response_with_limits(
+                &mut std::io::Cursor::new(response.to_vec()),
+                limits,
+            )
+        };
+        let defaults = RequestLimits::default();
+
+        // a length or chunk size far past what's sent fails without allocating it
+        assert!(matches!(
+            read(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\nshort",
+                &defaults
+            ),
+            Err(Error::BodyTooLarge)
+        ));
+        assert!(matches!(
+            read(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\nshort",
+                &defaults
+            ),
+            Err(Error::BodyTooLarge)
+        ));
+        let unlimited = RequestLimits {
+            max_body_size: usize::MAX,
+            ..RequestLimits::default()
+        };
+        assert!(matches!(
+            read(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 18446744073709551615\r\n\r\nshort",
+                &unlimited
+            ),
+            Err(Error::StreamReadError)
+        ));
+
+        // bodies that run until the connection closes are limited too
+        let small = RequestLimits {
+            max_body_size: 4,
+            ..RequestLimits::default()
+        };
+        assert!(matches!(
+            read(b"HTTP/1.0 200 OK\r\n\r\ntoo long", &small),
+            Err(Error::BodyTooLarge)
+        ));
+        assert_eq!(
+            read(b"HTTP/1.0 200 OK\r\n\r\nfits", &small)
+                .unwrap()
+                .content,
+            b"fits"
+        );
+    }
+
+    #[test]
+    fn test_cors_static_gzip() -> Result<(), core::http::Error> {
+        let folder =
+            std::env::temp_dir().join(format!("rustedflask-cors-static-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("style.css"), b"body {}").unwrap();
+        std::fs::write(folder.join("style.css.gz"), b"pretend this is gzip").unwrap();
+
+        let mut app = flask::App::new("test".to_string());
+        app.static_folder("/static", &folder);
+        app.cors(flask::Cors::new().allow_origin("https://example.com"));
+        let mut stream = MockStream::new(
+            b"GET /static/style.css HTTP/1.1\r\nOrigin: https://example.com\r\nAccept-Encoding: gzip\r\n\r\n",
+        );
+        app.handle_connection(&mut stream);
+        let response =
+            core::http::HTTPResponse::read_http_response(&mut Cursor::new(stream.output))?;
+        assert_eq!(response.content, b"pretend this is gzip");
+        // both the encoding and the origin decide what a cache can reuse
+        assert_eq!(response.headers["Vary"], "Accept-Encoding, Origin");
+
+        let mut response = core::http::HTTPResponse::from("cached");
+        response
+            .headers
+            .insert("vary".to_string(), "origin".to_string());
+        flask::Cors::new()
+            .allow_origin("https://example.com")
+            .add_headers(&mut response, "https://example.com");
+        assert_eq!(response.headers["vary"], "origin");
+        std::fs::remove_dir_all(&folder).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn test_encoded_question_mark() {
+        let mut app = flask::App::new("test".to_string());
+        app.route("/a", |_| "just a".into());
+        app.route("/files/<name>", |request| {
+            let params = request.params();
+            let page = params.get("page").map_or("none", String::as_str);
+            format!("{} [{}]", request.path_param("name").unwrap(), page)
+                .as_str()
+                .into()
+        });
+
+        // an encoded `?` is part of the path, not the start of the query
+        let response = app.respond(test_request("GET", "/a%3Fb"));
+        assert_eq!(response.status_u16(), 404);
+        let response = app.respond(test_request("GET", "/files/what%3Fnow"));
+        assert_eq!(response.content, b"what?now [none]");
+        let response = app.respond(test_request("GET", "/files/what%3fnow?page=2"));
+        assert_eq!(response.content, b"what?now [2]");
+    }
+
+    #[test]
+    fn test_append_to_streamed_body() -> std::io::Result<()> {
+        let segments = vec![b"streamed".to_vec()].into_iter();
+        let mut response = core::http::HTTPResponse::new().with_body_iter(segments, None);
+        response
+            .headers
+            .insert("content-length".to_string(), "99".to_string());
+        response.append_to_body(b"appended");
+
+        let mut sent = Vec::new();
+        response.write_to(&mut sent)?;
+        let head = String::from_utf8_lossy(&sent).to_lowercase();
+        // the body isn't chunked anymore, so only its length frames it
+        assert!(!head.contains("transfer-encoding"));
+        assert_eq!(head.matches("content-length").count(), 1);
+        assert!(head.contains("content-length: 8\r\n"));
+        assert!(head.ends_with("\r\n\r\nappended"));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "jinja")]
+    fn test_render_cache_key_collision() {
+        use jinja::render_cache::RenderCache;
+
+        let mut cache = RenderCache::new(4);
+        let ferris = HashMap::from([("name", jinja::JinjaValue::from("ferris"))]);
+        let corro = HashMap::from([("name", jinja::JinjaValue::from("corro"))]);
+        let key = RenderCache::key("hello.jinja2", &ferris);
+        cache.store(
+            key,
+            "hello.jinja2",
+            &ferris,
+            "Hi ferris".to_string(),
+            Vec::new(),
+        );
+        assert_eq!(
+            cache.get(key, "hello.jinja2", &ferris),
+            Ok(Some("Hi ferris".to_string()))
+        );
+        // another render that ends up with the same key isn't given this one
+        assert_eq!(cache.get(key, "hello.jinja2", &corro), Ok(None));
+        assert_eq!(cache.get(key, "bye.jinja2", &ferris), Ok(None));
+        assert_eq!(cache.get(key, "hello.jinja2", &HashMap::new()), Ok(None));
+    }
+
+    #[test]
+    fn test_respond_keeps_body() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static BODY: AtomicUsize = AtomicUsize::new(0);
+
+        let mut app = flask::App::new("test".to_string());
+        app.route("/", |_| {
+            let response = core::http::HTTPResponse::from("x".repeat(1024));
+            BODY.store(response.content.as_ptr() as usize, Ordering::SeqCst);
+            response
+        });
+        app.cors(flask::Cors::new().allow_origin("https://example.com"));
+        let response = app.test().header("Origin", "https://example.com").send();
+        // the headers are added to the handler's response, not to a copy of it
+        assert_eq!(
+            response.content.as_ptr() as usize,
+            BODY.load(Ordering::SeqCst)
+        );
+        assert!(response.header("X-Request-ID").is_some());
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("https://example.com")
+        );
+    }
+
+    #[test]
+    fn test_body_framing_headers_any_case() -> std::io::Result<()> {
+        let framing = |response: &core::http::HTTPResponse| {
+            let mut names: Vec<String> = response
+                .headers
+                .keys()
+                .map(|name| name.to_lowercase())
+                .filter(|name| name == "content-length" || name == "transfer-encoding")
+                .collect();
+            names.sort();
+            names
+        };
+        let handler_set = |name: &str, value: &str| {
+            core::http::HTTPResponse::new().with_header(name.to_string(), value.to_string())
+        };
+
+        let response = handler_set("transfer-encoding", "chunked")
+            .with_body_reader(std::io::Cursor::new(b"hi".to_vec()), 2);
+        assert_eq!(framing(&response), ["content-length"]);
+        let response = handler_set("transfer-encoding", "chunked")
+            .with_body_iter(vec![b"hi".to_vec()].into_iter(), Some(2));
+        assert_eq!(framing(&response), ["content-length"]);
+        let response = handler_set("transfer-encoding", "chunked")
+            .with_body_from_reader(std::io::Cursor::new(b"hi".to_vec()))?;
+        assert_eq!(framing(&response), ["content-length"]);
+        assert_eq!(response.header("Content-Length"), Some("2"));
+        let response = handler_set("content-length", "2")
+            .with_body_iter(vec![b"hi".to_vec()].into_iter(), None);
+        assert_eq!(framing(&response), ["transfer-encoding"]);
+        Ok(())
+    }
 }